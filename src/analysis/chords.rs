@@ -0,0 +1,205 @@
+use crate::channel::Channel;
+use crate::domain::Note;
+use crate::error::MidiError;
+use crate::smf::Smf;
+use crate::span::NoteSpan;
+use crate::theory::{Chord, ChordQuality};
+use crate::track::Track;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Segmentation {
+    // fixed windows of n beats
+    Beats(u32),
+    // a new segment every time the set of sounding notes changes
+    Changes,
+}
+
+// chord is None where nothing is sounding (N.C. on a lead sheet)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChordSegment {
+    pub start: u64,
+    pub end: u64,
+    pub chord: Option<Chord>,
+    // 0.0 ..= 1.0, how much of the sounding material the chord explains
+    pub confidence: f32,
+}
+
+impl Track {
+    pub fn chords(&self, ticks_per_beat: u32, segmentation: Segmentation) -> Vec<ChordSegment> {
+        recognize_chords(&self.note_spans(), ticks_per_beat, segmentation)
+    }
+}
+
+impl Smf {
+    // every track except the drum channel is taken into account, drums carry no harmony
+    pub fn chords(&self, segmentation: Segmentation) -> Result<Vec<ChordSegment>, MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)?;
+        let spans: Vec<NoteSpan> = self
+            .note_spans()
+            .into_iter()
+            .filter(|s| s.channel != Channel::DRUMS)
+            .collect();
+        Ok(recognize_chords(&spans, ticks_per_beat, segmentation))
+    }
+}
+
+pub fn recognize_chords(
+    spans: &[NoteSpan],
+    ticks_per_beat: u32,
+    segmentation: Segmentation,
+) -> Vec<ChordSegment> {
+    let end = match spans.iter().map(|s| s.end).max() {
+        Some(end) => end,
+        None => return Vec::new(),
+    };
+
+    let boundaries: Vec<u64> = match segmentation {
+        Segmentation::Beats(beats) => {
+            let step = (ticks_per_beat as u64 * beats.max(1) as u64).max(1);
+            (0..end.div_ceil(step)).map(|i| i * step).collect()
+        }
+        Segmentation::Changes => {
+            let mut ticks: Vec<u64> = spans.iter().flat_map(|s| [s.start, s.end]).collect();
+            ticks.push(0);
+            ticks.sort_unstable();
+            ticks.dedup();
+            ticks.retain(|&t| t < end);
+            ticks
+        }
+    };
+
+    let mut segments: Vec<ChordSegment> = Vec::new();
+    let mut previous_notes: Option<Vec<Note>> = None;
+
+    for (i, &start) in boundaries.iter().enumerate() {
+        let stop = boundaries.get(i + 1).copied().unwrap_or(end);
+        let sounding: Vec<&NoteSpan> = spans
+            .iter()
+            .filter(|s| s.overlap(start, stop) > 0)
+            .collect();
+
+        if segmentation == Segmentation::Changes {
+            let mut notes: Vec<Note> = sounding.iter().map(|s| s.note).collect();
+            notes.sort_unstable();
+            notes.dedup();
+            // a re-attack of the same notes doesn't start a new chord
+            if previous_notes.as_ref() == Some(&notes) {
+                if let Some(last) = segments.last_mut() {
+                    last.end = stop;
+                    continue;
+                }
+            }
+            previous_notes = Some(notes);
+        }
+
+        let (chord, confidence) = label(&sounding, start, stop);
+        segments.push(ChordSegment {
+            start,
+            end: stop,
+            chord,
+            confidence,
+        });
+    }
+
+    segments
+}
+
+// Template matching over a pitch class histogram weighted by how long each
+// note sounds inside the segment.
+fn label(sounding: &[&NoteSpan], start: u64, stop: u64) -> (Option<Chord>, f32) {
+    let mut weights = [0f32; 12];
+    for span in sounding {
+        weights[span.note.pitch_class() as usize] += span.overlap(start, stop) as f32;
+    }
+    let total: f32 = weights.iter().sum();
+    let bass = match sounding.iter().map(|s| s.note).min() {
+        Some(bass) if total > 0.0 => bass,
+        _ => return (None, 0.0),
+    };
+
+    let mut best: Option<(f32, u8, ChordQuality)> = None;
+    for root in 0..12u8 {
+        for quality in ChordQuality::ALL {
+            let mut tones = [false; 12];
+            for interval in quality.intervals() {
+                tones[((root + interval) % 12) as usize] = true;
+            }
+            let size = tones.iter().filter(|&&t| t).count() as f32;
+            let covered: f32 = (0..12).filter(|&pc| tones[pc]).map(|pc| weights[pc]).sum();
+            let present = (0..12).filter(|&pc| tones[pc] && weights[pc] > 0.0).count() as f32;
+
+            let mut score = (covered / total) * (present / size);
+            if bass.pitch_class() == root {
+                score += 0.05;
+            }
+            // the bigger the chord the less likely, so a plain triad wins ties
+            score -= 0.01 * size;
+
+            if best.is_none_or(|(b, _, _)| score > b) {
+                best = Some((score, root, quality));
+            }
+        }
+    }
+
+    let (score, root, quality) = best.unwrap();
+    // root voiced at the lowest sounding note of that pitch class
+    let root_note = sounding
+        .iter()
+        .map(|s| s.note)
+        .filter(|n| n.pitch_class() == root)
+        .min()
+        .unwrap_or_else(|| Note::new(48 + root).unwrap());
+
+    let mut chord = Chord::new(root_note, quality);
+    if bass.pitch_class() != root {
+        chord = chord.over(bass);
+    }
+    (Some(chord), score.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Channel, Velocity, Vql};
+
+    fn track_of(chords: &[&str]) -> Track {
+        let mut track = Track::default();
+        let channel = Channel::new(0).unwrap();
+        for symbol in chords {
+            let notes = symbol.parse::<Chord>().unwrap().notes().unwrap();
+            track.chord(
+                Vql::zero(),
+                Vql::try_from(480).unwrap(),
+                channel,
+                &notes,
+                Velocity::new(100).unwrap(),
+            );
+        }
+        track
+    }
+
+    #[test]
+    fn labels_each_beat() {
+        let track = track_of(&["C", "Am7", "F/A", "G7"]);
+        let segments = track.chords(480, Segmentation::Beats(1));
+        let labels: Vec<String> = segments
+            .iter()
+            .map(|s| s.chord.unwrap().to_string())
+            .collect();
+        assert_eq!(labels, vec!["C", "Am7", "F/A", "G7"]);
+        assert!(segments.iter().all(|s| s.confidence > 0.5));
+        assert_eq!((segments[2].start, segments[2].end), (960, 1440));
+    }
+
+    #[test]
+    fn merges_repeated_chords_on_changes() {
+        let track = track_of(&["Dm", "Dm", "G"]);
+        let segments = track.chords(480, Segmentation::Changes);
+        assert_eq!(segments.len(), 2);
+        assert_eq!((segments[0].start, segments[0].end), (0, 960));
+        assert_eq!(segments[1].chord.unwrap().to_string(), "G");
+    }
+}
@@ -0,0 +1,5 @@
+pub mod chords;
+pub mod tonality;
+
+pub use chords::*;
+pub use tonality::*;
@@ -0,0 +1,341 @@
+use crate::channel::Channel;
+use crate::domain::Note;
+use crate::error::MidiError;
+use crate::key::Spelling;
+use crate::meta::MetaEvent;
+use crate::smf::Smf;
+use crate::span::NoteSpan;
+use crate::theory::{Scale, ScaleKind};
+use crate::track::{EventType, TrackEvent};
+use std::fmt;
+
+// Krumhansl-Kessler probe tone profiles, index 0 is the tonic
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+// sharps (negative for flats) of the major key on each pitch class
+const MAJOR_SHARPS: [i8; 12] = [0, -5, 2, -3, 4, -1, 6, 1, -4, 3, -2, 5];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    tonic: u8,
+    is_major: bool,
+}
+
+impl Key {
+    // tonic is a pitch class, 0 = C ... 11 = B
+    pub fn new(tonic: u8, is_major: bool) -> Self {
+        Self {
+            tonic: tonic % 12,
+            is_major,
+        }
+    }
+
+    // reads the fields of a MetaEvent::KeySignature
+    pub fn from_signature(sharps: i8, is_major: bool) -> Option<Self> {
+        let major_tonic = MAJOR_SHARPS.iter().position(|&s| s == sharps)? as u8;
+        let tonic = if is_major {
+            major_tonic
+        } else {
+            (major_tonic + 9) % 12
+        };
+        Some(Key::new(tonic, is_major))
+    }
+
+    pub fn tonic(&self) -> u8 {
+        self.tonic
+    }
+
+    pub fn is_major(&self) -> bool {
+        self.is_major
+    }
+
+    pub fn sharps(&self) -> i8 {
+        let relative_major = if self.is_major {
+            self.tonic
+        } else {
+            (self.tonic + 3) % 12
+        };
+        MAJOR_SHARPS[relative_major as usize]
+    }
+
+    pub fn to_meta(&self) -> MetaEvent {
+        MetaEvent::KeySignature {
+            sharps: self.sharps(),
+            is_major: self.is_major,
+        }
+    }
+
+    // the key as a scale rooted in octave 4 (middle C = C4)
+    pub fn scale(&self) -> Scale {
+        let kind = if self.is_major {
+            ScaleKind::Major
+        } else {
+            ScaleKind::NaturalMinor
+        };
+        Scale::new(Note::new(60 + self.tonic).unwrap(), kind)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let spelling = if self.sharps() < 0 {
+            Spelling::Flats
+        } else {
+            Spelling::Sharps
+        };
+        let tonic = Note::new(self.tonic).unwrap().name(spelling);
+        let mode = if self.is_major { "major" } else { "minor" };
+        write!(f, "{tonic} {mode}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyEstimate {
+    pub key: Key,
+    // correlation with the key profile, clamped to 0.0 ..= 1.0
+    pub confidence: f32,
+}
+
+// A stretch of music in one key, [start, end) in ticks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyRegion {
+    pub start: u64,
+    pub end: u64,
+    pub estimate: KeyEstimate,
+}
+
+// None when there are no notes at all
+pub fn estimate_key(spans: &[NoteSpan]) -> Option<KeyEstimate> {
+    estimate_key_between(spans, 0, u64::MAX)
+}
+
+fn estimate_key_between(spans: &[NoteSpan], start: u64, end: u64) -> Option<KeyEstimate> {
+    let mut histogram = [0f32; 12];
+    for span in spans {
+        histogram[span.note.pitch_class() as usize] += span.overlap(start, end) as f32;
+    }
+    if histogram.iter().all(|&w| w == 0.0) {
+        return None;
+    }
+
+    let mut best: Option<KeyEstimate> = None;
+    for tonic in 0..12u8 {
+        for (profile, is_major) in [(&MAJOR_PROFILE, true), (&MINOR_PROFILE, false)] {
+            let rotated: Vec<f32> = (0..12)
+                .map(|pc| profile[(pc + 12 - tonic as usize) % 12])
+                .collect();
+            let r = correlation(&histogram, &rotated);
+            if best.is_none_or(|b| r > b.confidence) {
+                best = Some(KeyEstimate {
+                    key: Key::new(tonic, is_major),
+                    confidence: r,
+                });
+            }
+        }
+    }
+
+    best.map(|mut b| {
+        b.confidence = b.confidence.clamp(0.0, 1.0);
+        b
+    })
+}
+
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let mean_a = a.iter().sum::<f32>() / n;
+    let mean_b = b.iter().sum::<f32>() / n;
+    let mut cov = 0.0;
+    let mut var_a = 0.0;
+    let mut var_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a).powi(2);
+        var_b += (y - mean_b).powi(2);
+    }
+    if var_a == 0.0 || var_b == 0.0 {
+        return 0.0;
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+// Slides a window of `window` ticks every `hop` ticks and merges consecutive
+// windows that agree on the key, so each region change is a modulation.
+// Windows without notes extend the previous region.
+pub fn key_regions(spans: &[NoteSpan], window: u64, hop: u64) -> Vec<KeyRegion> {
+    let end = spans.iter().map(|s| s.end).max().unwrap_or(0);
+    let window = window.max(1);
+    let hop = hop.max(1);
+
+    let mut regions: Vec<KeyRegion> = Vec::new();
+    let mut start = 0;
+    while start < end {
+        let stop = (start + hop).min(end);
+        match estimate_key_between(spans, start, start + window) {
+            Some(estimate) => match regions.last_mut() {
+                Some(last) if last.estimate.key == estimate.key => {
+                    last.end = stop;
+                    last.estimate.confidence = last.estimate.confidence.max(estimate.confidence);
+                }
+                _ => regions.push(KeyRegion {
+                    start,
+                    end: stop,
+                    estimate,
+                }),
+            },
+            None => {
+                if let Some(last) = regions.last_mut() {
+                    last.end = stop;
+                }
+            }
+        }
+        start += hop;
+    }
+    regions
+}
+
+impl Smf {
+    fn pitched_spans(&self) -> Vec<NoteSpan> {
+        self.note_spans()
+            .into_iter()
+            .filter(|s| s.channel != Channel::DRUMS)
+            .collect()
+    }
+
+    // global key of the whole song, drums excluded
+    pub fn detect_key(&self) -> Option<KeyEstimate> {
+        estimate_key(&self.pitched_spans())
+    }
+
+    // windows and hops are in beats, e.g. (8, 4) looks at two 4/4 bars every bar
+    pub fn detect_key_regions(
+        &self,
+        window_beats: u32,
+        hop_beats: u32,
+    ) -> Result<Vec<KeyRegion>, MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)? as u64;
+        Ok(key_regions(
+            &self.pitched_spans(),
+            window_beats as u64 * ticks_per_beat,
+            hop_beats as u64 * ticks_per_beat,
+        ))
+    }
+
+    // Replaces every KeySignature of the first (conductor) track with one
+    // per region, placed at the region start.
+    pub fn write_key_signatures(&mut self, regions: &[KeyRegion]) -> Result<(), MidiError> {
+        let Some(conductor) = self.tracks_mut().first_mut() else {
+            return Ok(());
+        };
+
+        let mut events: Vec<(u64, TrackEvent)> = conductor
+            .absolute_events()
+            .filter(|(_, e)| !matches!(e.event, EventType::Meta(MetaEvent::KeySignature { .. })))
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+        // insert before what's already at the same tick, so notes come after the signature
+        for region in regions.iter().rev() {
+            let index = events.partition_point(|(tick, _)| *tick < region.start);
+            events.insert(
+                index,
+                (
+                    region.start,
+                    TrackEvent::meta_event(region.estimate.key.to_meta()),
+                ),
+            );
+        }
+        conductor.set_absolute_events(events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Channel, Header, MidiFormat, Track, Velocity, Vql};
+
+    fn melody(track: &mut Track, names: &[&str]) {
+        let channel = Channel::new(0).unwrap();
+        for name in names {
+            let note = Note::try_from(*name).unwrap();
+            track
+                .note_on(Vql::zero(), channel, note, Velocity::new(90).unwrap())
+                .note_off(
+                    Vql::try_from(480).unwrap(),
+                    channel,
+                    note,
+                    Velocity::new(0).unwrap(),
+                );
+        }
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(Key::new(7, true).sharps(), 1);
+        assert_eq!(Key::new(2, false).sharps(), -1);
+        assert_eq!(Key::from_signature(-3, false), Some(Key::new(0, false)));
+        assert_eq!(Key::new(3, true).to_string(), "Eb major");
+        assert_eq!(Key::new(9, false).to_string(), "A minor");
+    }
+
+    #[test]
+    fn detects_the_global_key() {
+        let mut track = Track::default();
+        melody(
+            &mut track,
+            &["A3", "C4", "E4", "G#4", "A4", "B4", "C5", "E4", "A3"],
+        );
+        let estimate = estimate_key(&track.note_spans()).unwrap();
+        assert_eq!(estimate.key, Key::new(9, false));
+        assert!(estimate.confidence > 0.5);
+    }
+
+    #[test]
+    fn detects_a_modulation() {
+        let mut track = Track::default();
+        let c_major = [
+            "C4", "E4", "G4", "C5", "F4", "A4", "G4", "B3", "C4", "E4", "G4", "C4",
+        ];
+        let e_major = [
+            "E4", "G#4", "B4", "E5", "A4", "C#5", "B4", "D#4", "E4", "G#4", "B4", "E4",
+        ];
+        melody(&mut track, &c_major);
+        melody(&mut track, &e_major);
+        track.add_event(TrackEvent::end_track());
+
+        let mut smf = Smf::new(
+            Header::new(MidiFormat::SingleTrack, 1, 480).unwrap(),
+            vec![track],
+        );
+
+        let regions = smf.detect_key_regions(12, 12).unwrap();
+        let keys: Vec<Key> = regions.iter().map(|r| r.estimate.key).collect();
+        assert_eq!(keys, vec![Key::new(0, true), Key::new(4, true)]);
+        assert_eq!(regions[1].start, 12 * 480);
+
+        smf.write_key_signatures(&regions).unwrap();
+        let signatures: Vec<(u64, MetaEvent)> = smf.tracks()[0]
+            .absolute_events()
+            .filter_map(|(tick, e)| match &e.event {
+                EventType::Meta(meta @ MetaEvent::KeySignature { .. }) => {
+                    Some((tick, meta.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            signatures,
+            vec![
+                (0, Key::new(0, true).to_meta()),
+                (12 * 480, Key::new(4, true).to_meta())
+            ]
+        );
+        assert_eq!(smf.tracks()[0].end_tick(), 24 * 480);
+    }
+}
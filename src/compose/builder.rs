@@ -0,0 +1,410 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Program, Velocity};
+use crate::error::MidiError;
+use crate::header::Header;
+use crate::key::MiddleC;
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::meta::MetaEvent;
+use crate::span::sort_releases_first;
+use crate::theory::Duration;
+use crate::track::{EventType, Track, TrackEvent, Vql};
+
+// A time signature and the bar it starts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Meter {
+    tick: u64,
+    bar: u32,
+    numerator: u8,
+    denominator: u8,
+}
+
+// Writes a track in bars, beats and note values instead of deltas.
+// A cursor moves forward with every note and rest, `at` jumps anywhere.
+// Bars and beats count from 1 like on a score, a beat is one
+// denominator note of the time signature (an eighth in 6/8).
+//
+//     builder.at(3, 2).note("E4", Duration::QUARTER, 90)?.rest(Duration::EIGHTH);
+#[derive(Debug, Clone)]
+pub struct TrackBuilder {
+    ticks_per_beat: u32,
+    channel: Channel,
+    middle_c: MiddleC,
+    // kept fractional so tuplets add up to whole beats
+    cursor: f64,
+    meters: Vec<Meter>,
+    events: Vec<(u64, TrackEvent)>,
+}
+
+impl TrackBuilder {
+    // 4/4 on channel 0 until told otherwise
+    pub fn new(header: &Header) -> Result<Self, MidiError> {
+        let ticks_per_beat = header
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)?;
+        Ok(Self {
+            ticks_per_beat,
+            channel: Channel::new(0)?,
+            middle_c: MiddleC::default(),
+            cursor: 0.0,
+            meters: vec![Meter {
+                tick: 0,
+                bar: 1,
+                numerator: 4,
+                denominator: 4,
+            }],
+            events: Vec::new(),
+        })
+    }
+
+    pub fn channel(&mut self, channel: Channel) -> &mut Self {
+        self.channel = channel;
+        self
+    }
+
+    // how note names like "C4" are read
+    pub fn middle_c(&mut self, middle_c: MiddleC) -> &mut Self {
+        self.middle_c = middle_c;
+        self
+    }
+
+    // the cursor in ticks
+    pub fn tick(&self) -> u64 {
+        self.cursor.round() as u64
+    }
+
+    fn meter_at(&self, tick: u64) -> Meter {
+        *self
+            .meters
+            .iter()
+            .rev()
+            .find(|m| m.tick <= tick)
+            .unwrap_or(&self.meters[0])
+    }
+
+    fn bar_ticks(&self, meter: &Meter) -> u64 {
+        self.beat_ticks(meter) * meter.numerator as u64
+    }
+
+    fn beat_ticks(&self, meter: &Meter) -> u64 {
+        (self.ticks_per_beat as u64 * 4 / meter.denominator as u64).max(1)
+    }
+
+    // (bar, beat) of the cursor, both from 1, beat is fractional between beats
+    pub fn position(&self) -> (u32, f64) {
+        let meter = self.meter_at(self.tick());
+        let into = self.cursor - meter.tick as f64;
+        let bar_ticks = self.bar_ticks(&meter) as f64;
+        let bars = (into / bar_ticks).floor();
+        let beat = (into - bars * bar_ticks) / self.beat_ticks(&meter) as f64;
+        (meter.bar + bars as u32, beat + 1.0)
+    }
+
+    pub fn at(&mut self, bar: u32, beat: u32) -> &mut Self {
+        let bar = bar.max(1);
+        let meter = *self
+            .meters
+            .iter()
+            .rev()
+            .find(|m| m.bar <= bar)
+            .unwrap_or(&self.meters[0]);
+        let tick = meter.tick
+            + (bar - meter.bar) as u64 * self.bar_ticks(&meter)
+            + beat.saturating_sub(1) as u64 * self.beat_ticks(&meter);
+        self.cursor = tick as f64;
+        self
+    }
+
+    pub fn at_tick(&mut self, tick: u64) -> &mut Self {
+        self.cursor = tick as f64;
+        self
+    }
+
+    pub fn rest(&mut self, duration: Duration) -> &mut Self {
+        self.cursor += self.length(duration);
+        self
+    }
+
+    fn length(&self, duration: Duration) -> f64 {
+        self.ticks_per_beat as f64 * 4.0 * duration.numerator() as f64
+            / duration.denominator() as f64
+    }
+
+    fn push(&mut self, tick: u64, message: ChannelMessage) {
+        let event = EventType::Midi(MidiMessage::Channel {
+            channel: self.channel,
+            message,
+        });
+        self.events
+            .push((tick, TrackEvent::new(Vql::zero(), event)));
+    }
+
+    fn push_meta(&mut self, tick: u64, meta: MetaEvent) {
+        self.events.push((tick, TrackEvent::meta_event(meta)));
+    }
+
+    // a note name like "E4", "F#3" or "Bb5", then moves on by duration
+    pub fn note(
+        &mut self,
+        note: &str,
+        duration: Duration,
+        velocity: u8,
+    ) -> Result<&mut Self, MidiError> {
+        let note = Note::parse(note, self.middle_c)?;
+        self.notes(&[note], duration, velocity)
+    }
+
+    pub fn chord(
+        &mut self,
+        notes: &[&str],
+        duration: Duration,
+        velocity: u8,
+    ) -> Result<&mut Self, MidiError> {
+        let notes = notes
+            .iter()
+            .map(|n| Note::parse(n, self.middle_c))
+            .collect::<Result<Vec<Note>, MidiError>>()?;
+        self.notes(&notes, duration, velocity)
+    }
+
+    // notes played together, e.g. builder.notes(&chord.notes()?, Duration::HALF, 80)
+    pub fn notes(
+        &mut self,
+        notes: &[Note],
+        duration: Duration,
+        velocity: u8,
+    ) -> Result<&mut Self, MidiError> {
+        let velocity = Velocity::new(velocity)?;
+        let start = self.tick();
+        self.cursor += self.length(duration);
+        let end = self.tick();
+        for &note in notes {
+            self.push(start, ChannelMessage::NoteOn { note, velocity });
+            self.push(
+                end,
+                ChannelMessage::NoteOff {
+                    note,
+                    velocity: Velocity::new(0)?,
+                },
+            );
+        }
+        Ok(self)
+    }
+
+    // every note in the closure is n in the time of m, tuplet(3, 2, ..)
+    // makes three eighths fill a quarter
+    pub fn tuplet<F>(&mut self, n: u32, m: u32, write: F) -> Result<&mut Self, MidiError>
+    where
+        F: FnOnce(&mut TupletWriter) -> Result<(), MidiError>,
+    {
+        let mut writer = TupletWriter {
+            builder: self,
+            n,
+            m,
+        };
+        write(&mut writer)?;
+        Ok(self)
+    }
+
+    pub fn program(&mut self, program: u8) -> Result<&mut Self, MidiError> {
+        let program = Program::new(program)?;
+        self.push(self.tick(), ChannelMessage::ProgramChange { program });
+        Ok(self)
+    }
+
+    pub fn tempo(&mut self, bpm: f64) -> Result<&mut Self, MidiError> {
+        let tempo = (60_000_000.0 / bpm).round();
+        if !bpm.is_finite() || !(1.0..=0xFF_FFFF as f64).contains(&tempo) {
+            return Err(MidiError::InvalidTempo);
+        }
+        self.push_meta(self.tick(), MetaEvent::SetTempo(tempo as u32));
+        Ok(self)
+    }
+
+    // Starts at the first bar line at or after the cursor, bars keep
+    // counting across the change
+    pub fn time_signature(
+        &mut self,
+        numerator: u8,
+        denominator: u8,
+    ) -> Result<&mut Self, MidiError> {
+        if numerator == 0 || !denominator.is_power_of_two() || denominator > 64 {
+            return Err(MidiError::InvalidTimeSignature);
+        }
+        let tick = self.tick();
+        let meter = self.meter_at(tick);
+        let bar_ticks = self.bar_ticks(&meter);
+        let bars = (tick - meter.tick).div_ceil(bar_ticks);
+        let start = Meter {
+            tick: meter.tick + bars * bar_ticks,
+            bar: meter.bar + bars as u32,
+            numerator,
+            denominator,
+        };
+
+        // a later signature is replaced, in the track as well
+        self.meters.retain(|m| m.tick < start.tick);
+        self.meters.push(start);
+        self.events.retain(|(tick, e)| {
+            *tick < start.tick
+                || !matches!(e.event, EventType::Meta(MetaEvent::TimeSignature { .. }))
+        });
+        self.push_meta(
+            start.tick,
+            MetaEvent::TimeSignature {
+                numerator,
+                denominator,
+                clocks_per_tick: 24,
+                thirty_seconds_per_24_clocks: 8,
+            },
+        );
+        Ok(self)
+    }
+
+    pub fn build(&self) -> Result<Track, MidiError> {
+        let mut events = self.events.clone();
+        let end = events
+            .iter()
+            .map(|(tick, _)| *tick)
+            .max()
+            .unwrap_or(0)
+            .max(self.tick());
+        events.push((end, TrackEvent::end_track()));
+        sort_releases_first(&mut events);
+        Track::from_absolute_events(events)
+    }
+}
+
+// The notes of a TrackBuilder::tuplet, durations are scaled by m/n
+pub struct TupletWriter<'a> {
+    builder: &'a mut TrackBuilder,
+    n: u32,
+    m: u32,
+}
+
+impl TupletWriter<'_> {
+    pub fn note(
+        &mut self,
+        note: &str,
+        duration: Duration,
+        velocity: u8,
+    ) -> Result<&mut Self, MidiError> {
+        self.builder
+            .note(note, duration.tuplet(self.n, self.m)?, velocity)?;
+        Ok(self)
+    }
+
+    pub fn chord(
+        &mut self,
+        notes: &[&str],
+        duration: Duration,
+        velocity: u8,
+    ) -> Result<&mut Self, MidiError> {
+        self.builder
+            .chord(notes, duration.tuplet(self.n, self.m)?, velocity)?;
+        Ok(self)
+    }
+
+    pub fn rest(&mut self, duration: Duration) -> Result<&mut Self, MidiError> {
+        self.builder.rest(duration.tuplet(self.n, self.m)?);
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::header::MidiFormat;
+    use crate::test_util::spans;
+
+    fn builder(division: i16) -> TrackBuilder {
+        TrackBuilder::new(&Header::new(MidiFormat::SingleTrack, 1, division).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn bars_beats_and_durations() {
+        let mut b = builder(480);
+        b.at(3, 2)
+            .note("E4", Duration::QUARTER, 90)
+            .unwrap()
+            .rest(Duration::EIGHTH)
+            .note("G4", Duration::QUARTER.dotted().unwrap(), 90)
+            .unwrap();
+        b.at(1, 1)
+            .chord(&["C4", "E4", "G4"], Duration::HALF, 80)
+            .unwrap();
+        let track = b.build().unwrap();
+
+        assert_eq!(
+            spans(&track.note_spans()),
+            vec![
+                (0, 960, 60),
+                (0, 960, 64),
+                (0, 960, 67),
+                (4320, 4800, 64),
+                (5040, 5760, 67),
+            ]
+        );
+        assert_eq!(track.end_tick(), 5760);
+        assert!(b.note("H4", Duration::QUARTER, 90).is_err());
+    }
+
+    #[test]
+    fn tuplets_land_on_the_beat() {
+        let mut b = builder(96);
+        b.tuplet(5, 4, |t| {
+            for note in ["C4", "D4", "E4", "F4", "G4"] {
+                t.note(note, Duration::SIXTEENTH, 90)?;
+            }
+            Ok(())
+        })
+        .unwrap()
+        .note("A4", Duration::QUARTER, 90)
+        .unwrap();
+        let starts: Vec<u64> = spans(&b.build().unwrap().note_spans())
+            .iter()
+            .map(|s| s.0)
+            .collect();
+        assert_eq!(starts, vec![0, 19, 38, 58, 77, 96]);
+    }
+
+    #[test]
+    fn time_signature_changes() {
+        let mut b = builder(96);
+        b.at(2, 3).time_signature(6, 8).unwrap();
+        // 6/8 starts on bar 3, where a bar is six eighths
+        b.at(4, 4).note("C4", Duration::EIGHTH, 90).unwrap();
+        assert_eq!(b.position(), (4, 5.0));
+        assert_eq!(
+            spans(&b.build().unwrap().note_spans()),
+            vec![(1200, 1248, 60)]
+        );
+        assert_eq!(
+            b.time_signature(5, 3).unwrap_err(),
+            MidiError::InvalidTimeSignature
+        );
+    }
+
+    #[test]
+    fn time_signature_twice_on_one_bar() {
+        let mut b = builder(96);
+        b.at(2, 1).time_signature(3, 4).unwrap();
+        b.at(3, 1).time_signature(7, 8).unwrap();
+        b.at(2, 1).time_signature(6, 8).unwrap();
+        let signatures: Vec<(u64, u8, u8)> = b
+            .build()
+            .unwrap()
+            .absolute_events()
+            .filter_map(|(tick, e)| match e.event {
+                EventType::Meta(MetaEvent::TimeSignature {
+                    numerator,
+                    denominator,
+                    ..
+                }) => Some((tick, numerator, denominator)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(signatures, vec![(384, 6, 8)]);
+        b.at(3, 1);
+        assert_eq!(b.tick(), 384 + 288);
+    }
+}
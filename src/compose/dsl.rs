@@ -0,0 +1,650 @@
+use crate::channel::Channel;
+use crate::compose::builder::TrackBuilder;
+use crate::domain::Velocity;
+use crate::error::MidiError;
+use crate::header::{Header, MidiFormat};
+use crate::smf::Smf;
+use crate::theory::Duration;
+use crate::track::Track;
+
+// A compact text notation, close to MML:
+//
+//     t120 o4 l8 c d e f g4 [c e g]2 r4
+//     track "Bass" ch2 @33 !mf o2 (c8 c > c < c)4
+//
+// c d e f g a b   notes, + or # sharp, - flat, then an optional length
+// r               rest
+// 4 8. 16 8t      lengths: 1/4, dotted 1/8, 1/16, triplet 1/8
+// [c e g]2        a chord, its length goes after the bracket
+// o4 > <          octave, one up, one down (o4 c is middle C)
+// l8              default length
+// v100 !mf        velocity, or a dynamic from ppp to fff
+// @33             program
+// ch10            channel, from 1 to 16 (10 is drums)
+// t120            tempo in BPM
+// (...)3          the inside played 3 times (up to 1000), twice with no count,
+//                 nested up to 64 deep and a million commands all unrolled
+// track "Name"    starts a new track, the name can also be a single word
+// ; comment       until the end of the line, | is ignored (for bar lines)
+const TICKS_PER_BEAT: i16 = 480;
+const DEFAULT_VELOCITY: u8 = 100;
+// 1/4.... is as far as dots go
+const MAX_DOTS: u32 = 4;
+const MAX_REPEATS: u32 = 1000;
+// how deep loops and chords nest, and how many commands the loops unroll to
+const MAX_DEPTH: usize = 64;
+const MAX_EXPANDED: u64 = 1_000_000;
+// the octaves a note name can have
+const OCTAVES: std::ops::RangeInclusive<i8> = -1..=9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Length {
+    denominator: Option<u32>,
+    dots: u32,
+    triplet: bool,
+}
+
+impl Length {
+    fn resolve(&self, default: Duration) -> Result<Duration, MidiError> {
+        let mut duration = match self.denominator {
+            Some(denominator) => Duration::new(1, denominator)?,
+            None => default,
+        };
+        if self.dots > 0 {
+            // one dot is 3/2, two are 7/4, three 15/8
+            let k = 1 << self.dots;
+            duration = duration.tuplet(k, 2 * k - 1)?;
+        }
+        if self.triplet {
+            duration = duration.triplet()?;
+        }
+        Ok(duration)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Track(String),
+    Channel(u8),
+    Program(u8),
+    Tempo(u32),
+    Octave(i8),
+    OctaveUp,
+    OctaveDown,
+    DefaultLength(Length),
+    Velocity(u8),
+    // the letter with its accidentals in Note::parse form, e.g. "C#"
+    Note(String, Length),
+    Rest(Length),
+    Chord(Vec<Positioned>, Length),
+    Repeat(Vec<Positioned>, u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Positioned {
+    command: Command,
+    line: usize,
+    column: usize,
+}
+
+fn error_at(line: usize, column: usize, message: impl Into<String>) -> MidiError {
+    MidiError::Parse {
+        line,
+        column,
+        message: message.into(),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Self {
+        Self {
+            chars: source.chars().collect(),
+            index: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.index + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: impl Into<String>) -> MidiError {
+        error_at(self.line, self.column, message)
+    }
+
+    fn skip_blank(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ';' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                c if c.is_whitespace() || c == '|' => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let mut digits = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(c);
+            self.bump();
+        }
+        digits.parse().ok()
+    }
+
+    fn expect_number(&mut self, what: &str, max: u32) -> Result<u32, MidiError> {
+        let (line, column) = (self.line, self.column);
+        match self.number() {
+            Some(n) if n <= max => Ok(n),
+            Some(n) => Err(error_at(line, column, format!("{what} {n} is over {max}"))),
+            None => Err(self.error(format!("expected a number for the {what}"))),
+        }
+    }
+
+    fn length(&mut self) -> Result<Length, MidiError> {
+        let denominator = self.number();
+        let mut dots = 0;
+        while self.peek() == Some('.') {
+            if dots == MAX_DOTS {
+                return Err(self.error(format!("at most {MAX_DOTS} dots")));
+            }
+            self.bump();
+            dots += 1;
+        }
+        // a t right after a length is a triplet, unless it's the start of
+        // the next command like t120 or track
+        let triplet = matches!(self.peek(), Some('t' | 'T'))
+            && denominator.is_some()
+            && !self.peek_at(1).is_some_and(|c| c.is_alphanumeric());
+        if triplet {
+            self.bump();
+        }
+        Ok(Length {
+            denominator,
+            dots,
+            triplet,
+        })
+    }
+
+    // commands until close, or until the end when close is None
+    fn block(&mut self, close: Option<char>, depth: usize) -> Result<Vec<Positioned>, MidiError> {
+        let (line, column) = (self.line, self.column);
+        let mut commands = Vec::new();
+        loop {
+            self.skip_blank();
+            match (self.peek(), close) {
+                (None, None) => return Ok(commands),
+                (None, Some(close)) => {
+                    return Err(error_at(line, column, format!("missing '{close}'")))
+                }
+                (Some(c), Some(close)) if c == close => {
+                    self.bump();
+                    return Ok(commands);
+                }
+                _ => commands.push(self.command(depth)?),
+            }
+        }
+    }
+
+    fn command(&mut self, depth: usize) -> Result<Positioned, MidiError> {
+        let (line, column) = (self.line, self.column);
+        let c = self.bump().unwrap();
+        if matches!(c, '[' | '(') && depth >= MAX_DEPTH {
+            return Err(error_at(
+                line,
+                column,
+                format!("nested more than {MAX_DEPTH} deep"),
+            ));
+        }
+        let command = match c.to_ascii_lowercase() {
+            'c' if self.peek() == Some('h') => {
+                self.bump();
+                let channel = self.expect_number("channel", 16)?;
+                if channel == 0 {
+                    return Err(error_at(line, column, "channels go from 1 to 16"));
+                }
+                Command::Channel(channel as u8 - 1)
+            }
+            't' if self.chars[self.index..].starts_with(&['r', 'a', 'c', 'k']) => {
+                if depth > 0 {
+                    return Err(error_at(line, column, "a track can't start inside a loop"));
+                }
+                (0..4).for_each(|_| {
+                    self.bump();
+                });
+                Command::Track(self.track_name()?)
+            }
+            't' => Command::Tempo(self.expect_number("tempo", 60_000_000)?),
+            'a'..='g' => {
+                let mut name = c.to_ascii_uppercase().to_string();
+                while let Some(accidental) = self.peek() {
+                    match accidental {
+                        '+' | '#' => name.push('#'),
+                        '-' => name.push('b'),
+                        _ => break,
+                    }
+                    self.bump();
+                }
+                Command::Note(name, self.length()?)
+            }
+            'r' => Command::Rest(self.length()?),
+            'o' => {
+                let negative = self.peek() == Some('-');
+                if negative {
+                    self.bump();
+                }
+                let octave = self.expect_number("octave", 10)? as i8;
+                Command::Octave(if negative { -octave } else { octave })
+            }
+            '>' => Command::OctaveUp,
+            '<' => Command::OctaveDown,
+            'l' => {
+                let length = self.length()?;
+                if length.denominator.is_none() {
+                    return Err(self.error("expected a length like l8 or l4."));
+                }
+                Command::DefaultLength(length)
+            }
+            'v' => Command::Velocity(self.expect_number("velocity", 127)? as u8),
+            '@' => Command::Program(self.expect_number("program", 127)? as u8),
+            '!' => {
+                let mut mark = String::new();
+                while let Some(c) = self.peek().filter(|c| c.is_ascii_alphabetic()) {
+                    mark.push(c);
+                    self.bump();
+                }
+                let velocity = Velocity::from_dynamic(&mark).ok_or_else(|| {
+                    error_at(
+                        line,
+                        column,
+                        format!("unknown dynamic '!{mark}', expected ppp to fff"),
+                    )
+                })?;
+                Command::Velocity(velocity.value())
+            }
+            '[' => {
+                let notes = self.block(Some(']'), depth + 1)?;
+                if let Some(other) = notes.iter().find(|n| {
+                    !matches!(
+                        n.command,
+                        Command::Note(_, _)
+                            | Command::Octave(_)
+                            | Command::OctaveUp
+                            | Command::OctaveDown
+                    )
+                }) {
+                    return Err(error_at(
+                        other.line,
+                        other.column,
+                        "only notes and octave changes can go in a chord",
+                    ));
+                }
+                Command::Chord(notes, self.length()?)
+            }
+            '(' => {
+                let body = self.block(Some(')'), depth + 1)?;
+                let times = match self.peek() {
+                    Some(c) if c.is_ascii_digit() => {
+                        self.expect_number("repeat count", MAX_REPEATS)?
+                    }
+                    _ => 2,
+                };
+                Command::Repeat(body, times)
+            }
+            _ => return Err(error_at(line, column, format!("unexpected '{c}'"))),
+        };
+        Ok(Positioned {
+            command,
+            line,
+            column,
+        })
+    }
+
+    fn track_name(&mut self) -> Result<String, MidiError> {
+        while self.peek().is_some_and(|c| c == ' ' || c == '\t') {
+            self.bump();
+        }
+        let mut name = String::new();
+        if self.peek() == Some('"') {
+            let (line, column) = (self.line, self.column);
+            self.bump();
+            loop {
+                match self.bump() {
+                    Some('"') => break,
+                    Some('\n') | None => return Err(error_at(line, column, "unclosed track name")),
+                    Some(c) => name.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = self.peek().filter(|c| !c.is_whitespace()) {
+                name.push(c);
+                self.bump();
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error("expected a track name"));
+        }
+        Ok(name)
+    }
+}
+
+// One track being written, with the state the commands change
+struct Voice {
+    builder: TrackBuilder,
+    name: Option<String>,
+    used: bool,
+    octave: i8,
+    length: Duration,
+    velocity: u8,
+}
+
+impl Voice {
+    // track n starts on channel n, stepping over the drums
+    fn new(header: &Header, index: usize) -> Result<Self, MidiError> {
+        let channel = if index < 9 { index } else { index + 1 } % 16;
+        let mut builder = TrackBuilder::new(header)?;
+        builder.channel(Channel::new(channel as u8)?);
+        Ok(Self {
+            builder,
+            name: None,
+            used: false,
+            octave: 4,
+            length: Duration::QUARTER,
+            velocity: DEFAULT_VELOCITY,
+        })
+    }
+
+    // one octave up or down, as long as there are notes there
+    fn shift(&mut self, by: i8, positioned: &Positioned) -> Result<(), MidiError> {
+        match self.octave.checked_add(by) {
+            Some(octave) if OCTAVES.contains(&octave) => {
+                self.octave = octave;
+                Ok(())
+            }
+            _ => Err(error_at(
+                positioned.line,
+                positioned.column,
+                "no octave there, they go from -1 to 9",
+            )),
+        }
+    }
+
+    fn note_name(&self, letter: &str) -> String {
+        format!("{letter}{}", self.octave)
+    }
+}
+
+// How many commands run goes through once the loops are unrolled, an
+// error at the loop going over MAX_EXPANDED
+fn expanded(commands: &[Positioned]) -> Result<u64, MidiError> {
+    let mut total: u64 = 0;
+    for positioned in commands {
+        let count = match &positioned.command {
+            Command::Repeat(body, times) => expanded(body)?.checked_mul(*times as u64),
+            _ => Some(1),
+        };
+        total = count
+            .and_then(|count| total.checked_add(count))
+            .filter(|total| *total <= MAX_EXPANDED)
+            .ok_or_else(|| {
+                error_at(
+                    positioned.line,
+                    positioned.column,
+                    format!("the loops unroll to over {MAX_EXPANDED} commands"),
+                )
+            })?;
+    }
+    Ok(total)
+}
+
+fn run(commands: &[Positioned], voices: &mut Vec<Voice>, header: &Header) -> Result<(), MidiError> {
+    for positioned in commands {
+        let at = |e: MidiError| error_at(positioned.line, positioned.column, e.to_string());
+        if let Command::Track(name) = &positioned.command {
+            if voices.last().is_none_or(|v| v.used) {
+                voices.push(Voice::new(header, voices.len()).map_err(at)?);
+            }
+            voices.last_mut().unwrap().name = Some(name.clone());
+            continue;
+        }
+        if voices.is_empty() {
+            voices.push(Voice::new(header, 0).map_err(at)?);
+        }
+        let voice = voices.last_mut().unwrap();
+        voice.used = true;
+
+        match &positioned.command {
+            Command::Track(_) => unreachable!(),
+            Command::Channel(channel) => {
+                voice.builder.channel(Channel::new(*channel).map_err(at)?);
+            }
+            Command::Program(program) => {
+                voice.builder.program(*program).map_err(at)?;
+            }
+            Command::Tempo(bpm) => {
+                voice.builder.tempo(*bpm as f64).map_err(at)?;
+            }
+            Command::Octave(octave) => voice.octave = *octave,
+            Command::OctaveUp => voice.shift(1, positioned)?,
+            Command::OctaveDown => voice.shift(-1, positioned)?,
+            Command::DefaultLength(length) => {
+                voice.length = length.resolve(voice.length).map_err(at)?
+            }
+            Command::Velocity(velocity) => voice.velocity = *velocity,
+            Command::Note(letter, length) => {
+                let duration = length.resolve(voice.length).map_err(at)?;
+                let name = voice.note_name(letter);
+                voice
+                    .builder
+                    .note(&name, duration, voice.velocity)
+                    .map_err(at)?;
+            }
+            Command::Rest(length) => {
+                let duration = length.resolve(voice.length).map_err(at)?;
+                voice.builder.rest(duration);
+            }
+            Command::Chord(notes, length) => {
+                let mut names = Vec::new();
+                for note in notes {
+                    match &note.command {
+                        Command::Note(letter, _) => names.push(voice.note_name(letter)),
+                        Command::Octave(octave) => voice.octave = *octave,
+                        Command::OctaveUp => voice.shift(1, note)?,
+                        Command::OctaveDown => voice.shift(-1, note)?,
+                        _ => unreachable!(),
+                    }
+                }
+                let names: Vec<&str> = names.iter().map(String::as_str).collect();
+                let duration = length.resolve(voice.length).map_err(at)?;
+                voice
+                    .builder
+                    .chord(&names, duration, voice.velocity)
+                    .map_err(at)?;
+            }
+            Command::Repeat(body, times) => {
+                for _ in 0..*times {
+                    run(body, voices, header)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Smf {
+    // Compiles the text notation described above, at 480 ticks per beat.
+    // Errors, bad syntax or notes out of range, say where they are.
+    pub fn from_text(source: &str) -> Result<Smf, MidiError> {
+        let commands = Parser::new(source).block(None, 0)?;
+        expanded(&commands)?;
+        let header = Header::new(MidiFormat::MultipleTrack, 1, TICKS_PER_BEAT)?;
+        let mut voices: Vec<Voice> = Vec::new();
+        run(&commands, &mut voices, &header)?;
+
+        let mut tracks: Vec<Track> = Vec::new();
+        for voice in &voices {
+            let track = voice.builder.build()?;
+            tracks.push(match &voice.name {
+                Some(name) => track.with_name(name),
+                None => track,
+            });
+        }
+        if tracks.is_empty() {
+            tracks.push(TrackBuilder::new(&header)?.build()?);
+        }
+        let format = if tracks.len() == 1 {
+            MidiFormat::SingleTrack
+        } else {
+            MidiFormat::MultipleTrack
+        };
+        let header = Header::new(format, tracks.len() as u16, TICKS_PER_BEAT)?;
+        Ok(Smf::new(header, tracks))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{ChannelMessage, MidiMessage};
+    use crate::meta::MetaEvent;
+    use crate::test_util::spans;
+    use crate::track::EventType;
+
+    #[test]
+    fn melody_and_chord() {
+        let smf = Smf::from_text("t120 o4 l8 c d e f g4 [c e g]2 r4").unwrap();
+        assert_eq!(smf.tracks().len(), 1);
+        let track = &smf.tracks()[0];
+        assert_eq!(
+            track.events()[0].event,
+            EventType::Meta(MetaEvent::SetTempo(500_000))
+        );
+        assert_eq!(
+            spans(&track.note_spans()),
+            vec![
+                (0, 240, 60),
+                (240, 480, 62),
+                (480, 720, 64),
+                (720, 960, 65),
+                (960, 1440, 67),
+                (1440, 2400, 60),
+                (1440, 2400, 64),
+                (1440, 2400, 67),
+            ]
+        );
+        assert_eq!(track.end_tick(), 2880);
+    }
+
+    #[test]
+    fn tracks_loops_and_marks() {
+        let source = "\
+track \"Bass\" ch2 @33 !mf o2
+  (c8 c > c < c)2   ; two bars of octaves
+track Drums ch10 !ff
+  c4. c8t c8t c8t
+";
+        let smf = Smf::from_text(source).unwrap();
+        assert_eq!(smf.tracks().len(), 2);
+        let bass = &smf.tracks()[0];
+        let drums = &smf.tracks()[1];
+        assert_eq!(bass.name(), "Bass");
+        assert_eq!(drums.name(), "Drums");
+
+        let bass_notes = bass.note_spans();
+        assert_eq!(bass_notes.len(), 8);
+        assert!(bass_notes
+            .iter()
+            .all(|s| s.channel.value() == 1 && s.velocity.value() == 80));
+        assert_eq!(bass_notes[2].note.value(), 48);
+        assert!(bass.events().iter().any(|e| matches!(
+            e.event,
+            EventType::Midi(MidiMessage::Channel {
+                message: ChannelMessage::ProgramChange { .. },
+                ..
+            })
+        )));
+
+        let starts: Vec<(u64, u8, u8)> = drums
+            .note_spans()
+            .iter()
+            .map(|s| (s.start, s.channel.value(), s.velocity.value()))
+            .collect();
+        assert_eq!(
+            starts,
+            vec![(0, 9, 112), (720, 9, 112), (880, 9, 112), (1040, 9, 112)]
+        );
+    }
+
+    #[test]
+    fn errors_say_where() {
+        let error = Smf::from_text("o4 c d\n  e h").unwrap_err();
+        assert_eq!(
+            error,
+            MidiError::Parse {
+                line: 2,
+                column: 5,
+                message: "unexpected 'h'".to_string()
+            }
+        );
+
+        let error = Smf::from_text("o9 c b").unwrap_err();
+        assert!(matches!(
+            error,
+            MidiError::Parse {
+                line: 1,
+                column: 6,
+                ..
+            }
+        ));
+        let position = |source: &str| match Smf::from_text(source) {
+            Err(MidiError::Parse { line, column, .. }) => (line, column),
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(position(&format!("o4 {}c", ">".repeat(200))), (1, 9));
+        assert_eq!(position("o-1 [c < e]"), (1, 8));
+        assert_eq!(position("(c)999999999"), (1, 4));
+        assert_eq!(position("c4....."), (1, 7));
+        assert!(Smf::from_text("(c)1000 c4....").is_ok());
+        assert_eq!(position("c (((c)1000)1000)1000"), (1, 3));
+        assert_eq!(position(&"(".repeat(100_000)), (1, 65));
+        assert_eq!(position(&"[".repeat(100_000)), (1, 65));
+
+        assert!(matches!(
+            Smf::from_text("(c d").unwrap_err(),
+            MidiError::Parse {
+                line: 1,
+                column: 2,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,6 @@
+pub mod builder;
+pub mod dsl;
+pub mod pattern;
+
+pub use builder::*;
+pub use pattern::*;
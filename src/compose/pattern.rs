@@ -0,0 +1,312 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Velocity};
+use crate::error::MidiError;
+use crate::span::sort_releases_first;
+use crate::track::{EventType, Track, TrackEvent, Vql};
+
+// A reusable chunk of music, e.g. one bar of a drum loop. Events are
+// stored at ticks from the start of the pattern (their v_time is not
+// used) and length is where the next pattern starts, so a note may ring
+// past it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pattern {
+    length: u64,
+    events: Vec<(u64, TrackEvent)>,
+}
+
+impl Pattern {
+    pub fn new(length: u64) -> Self {
+        Self {
+            length,
+            events: Vec::new(),
+        }
+    }
+
+    // The whole track as a pattern, as long as the track. Only the MIDI and
+    // sysex events are kept: the name, tempo, signatures and other metas
+    // belong to the track the pattern ends up on, not to every repetition
+    pub fn from_track(track: &Track) -> Self {
+        let events = track
+            .absolute_events()
+            .filter(|(_, e)| !matches!(e.event, EventType::Meta(_)))
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+        Self {
+            length: track.end_tick(),
+            events,
+        }
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn set_length(&mut self, length: u64) {
+        self.length = length;
+    }
+
+    pub fn events(&self) -> &[(u64, TrackEvent)] {
+        &self.events
+    }
+
+    // for variations: change, add or drop events in place
+    pub fn events_mut(&mut self) -> &mut Vec<(u64, TrackEvent)> {
+        &mut self.events
+    }
+
+    pub fn event(&mut self, tick: u64, event: EventType) -> &mut Self {
+        self.events
+            .push((tick, TrackEvent::new(Vql::zero(), event)));
+        self
+    }
+
+    pub fn note(
+        &mut self,
+        start: u64,
+        duration: u64,
+        channel: Channel,
+        note: Note,
+        velocity: Velocity,
+    ) -> &mut Self {
+        let release = Velocity::new(0).unwrap();
+        self.events.push((
+            start,
+            TrackEvent::note_on(Vql::zero(), channel, note, velocity),
+        ));
+        self.events.push((
+            start + duration,
+            TrackEvent::note_off(Vql::zero(), channel, note, release),
+        ));
+        self
+    }
+
+    // this pattern with other played at the same time, as long as the longer one
+    pub fn layer(&self, other: &Pattern) -> Pattern {
+        let mut layered = self.clone();
+        layered.events.extend(other.events.iter().cloned());
+        layered.length = self.length.max(other.length);
+        layered
+    }
+
+    // this pattern then other
+    pub fn then(&self, other: &Pattern) -> Pattern {
+        let mut sequence = self.clone();
+        sequence.append(other);
+        sequence
+    }
+
+    pub fn repeat(&self, times: usize) -> Pattern {
+        let mut repeated = Pattern::new(0);
+        for _ in 0..times {
+            repeated.append(self);
+        }
+        repeated
+    }
+
+    fn append(&mut self, other: &Pattern) {
+        let offset = self.length;
+        self.events.extend(
+            other
+                .events
+                .iter()
+                .map(|(tick, e)| (tick + offset, e.clone())),
+        );
+        self.length += other.length;
+    }
+
+    pub fn to_track(&self) -> Result<Track, MidiError> {
+        let mut events = self.events.clone();
+        events.push((self.length, TrackEvent::end_track()));
+        sort_releases_first(&mut events);
+        Track::from_absolute_events(events)
+    }
+}
+
+// Lays patterns one after the other on a track, e.g. A A B A, or the same
+// bar 16 times with a closure changing some of them.
+#[derive(Debug, Clone, Default)]
+pub struct Arrangement {
+    cursor: u64,
+    arranged: Pattern,
+}
+
+impl Arrangement {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // where the next pattern goes
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    pub fn play(&mut self, pattern: &Pattern) -> &mut Self {
+        self.layer(pattern);
+        self.cursor += pattern.length;
+        self
+    }
+
+    pub fn sequence(&mut self, patterns: &[&Pattern]) -> &mut Self {
+        for pattern in patterns {
+            self.play(pattern);
+        }
+        self
+    }
+
+    pub fn repeat(&mut self, pattern: &Pattern, times: usize) -> &mut Self {
+        self.repeat_with(pattern, times, |_, _| {})
+    }
+
+    // vary gets the index of the repetition and a fresh copy of the
+    // pattern to change before it's played
+    pub fn repeat_with<F>(&mut self, pattern: &Pattern, times: usize, mut vary: F) -> &mut Self
+    where
+        F: FnMut(usize, &mut Pattern),
+    {
+        for i in 0..times {
+            let mut variation = pattern.clone();
+            vary(i, &mut variation);
+            self.play(&variation);
+        }
+        self
+    }
+
+    // plays pattern at the cursor without moving it, so the next one
+    // goes on top of it
+    pub fn layer(&mut self, pattern: &Pattern) -> &mut Self {
+        let cursor = self.cursor;
+        self.arranged.events.extend(
+            pattern
+                .events
+                .iter()
+                .map(|(tick, e)| (tick + cursor, e.clone())),
+        );
+        self.arranged.length = self.arranged.length.max(cursor + pattern.length);
+        self
+    }
+
+    pub fn rest(&mut self, ticks: u64) -> &mut Self {
+        self.cursor += ticks;
+        self.arranged.length = self.arranged.length.max(self.cursor);
+        self
+    }
+
+    pub fn to_pattern(&self) -> Pattern {
+        self.arranged.clone()
+    }
+
+    pub fn build(&self) -> Result<Track, MidiError> {
+        self.arranged.to_track()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{ChannelMessage, MidiMessage};
+    use crate::meta::MetaEvent;
+
+    fn hit(note: u8) -> Pattern {
+        let mut pattern = Pattern::new(96);
+        pattern.note(
+            0,
+            24,
+            Channel::DRUMS,
+            Note::new(note).unwrap(),
+            Velocity::new(100).unwrap(),
+        );
+        pattern
+    }
+
+    fn starts(track: &Track) -> Vec<(u64, u8, u8)> {
+        track
+            .note_spans()
+            .iter()
+            .map(|s| (s.start, s.note.value(), s.velocity.value()))
+            .collect()
+    }
+
+    #[test]
+    fn sequence_and_layer() {
+        let kick = hit(36);
+        let snare = hit(38);
+        let hats = hit(42).repeat(2);
+
+        let mut arrangement = Arrangement::new();
+        arrangement
+            .sequence(&[&kick, &kick, &snare, &kick])
+            .rest(96)
+            .layer(&hats)
+            .play(&kick.then(&snare));
+        let track = arrangement.build().unwrap();
+
+        assert_eq!(
+            starts(&track),
+            vec![
+                (0, 36, 100),
+                (96, 36, 100),
+                (192, 38, 100),
+                (288, 36, 100),
+                (480, 42, 100),
+                (480, 36, 100),
+                (576, 42, 100),
+                (576, 38, 100),
+            ]
+        );
+        assert_eq!(track.end_tick(), 672);
+    }
+
+    #[test]
+    fn repeat_with_variation() {
+        let bar = hit(36).layer(&hit(42));
+        let mut arrangement = Arrangement::new();
+        // every fourth bar the hat gets an accent
+        arrangement.repeat_with(&bar, 8, |i, pattern| {
+            if i % 4 == 3 {
+                for (_, event) in pattern.events_mut().iter_mut() {
+                    if let EventType::Midi(MidiMessage::Channel {
+                        message: ChannelMessage::NoteOn { note, velocity },
+                        ..
+                    }) = &mut event.event
+                    {
+                        if note.value() == 42 {
+                            *velocity = Velocity::new(127).unwrap();
+                        }
+                    }
+                }
+            }
+        });
+        let track = arrangement.build().unwrap();
+
+        let accents: Vec<u64> = starts(&track)
+            .into_iter()
+            .filter(|(_, _, velocity)| *velocity == 127)
+            .map(|(start, _, _)| start)
+            .collect();
+        assert_eq!(accents, vec![288, 672]);
+        assert_eq!(track.end_tick(), 8 * 96);
+    }
+
+    #[test]
+    fn from_track_drops_metas() {
+        let mut track = hit(36).to_track().unwrap();
+        track.set_name("Drums");
+        let mut with_tempo = vec![(
+            0,
+            TrackEvent::new(Vql::zero(), EventType::Meta(MetaEvent::SetTempo(500_000))),
+        )];
+        with_tempo.extend(track.absolute_events().map(|(tick, e)| (tick, e.clone())));
+        let track = Track::from_absolute_events(with_tempo).unwrap();
+
+        let pattern = Pattern::from_track(&track);
+        assert_eq!(pattern.length(), 96);
+        assert_eq!(pattern.events().len(), 2);
+        assert!(pattern
+            .events()
+            .iter()
+            .all(|(_, e)| matches!(e.event, EventType::Midi(_))));
+        let looped = pattern.repeat(2).to_track().unwrap();
+        assert_eq!(looped.name(), "");
+        assert_eq!(starts(&looped), vec![(0, 36, 100), (96, 36, 100)]);
+    }
+}
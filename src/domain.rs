@@ -2,7 +2,12 @@ use crate::error::MidiError;
 
 macro_rules! midi_value {
     ($name:ident, $kind:literal) => {
-        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[cfg_attr(
+            feature = "serde",
+            derive(serde::Serialize, serde::Deserialize),
+            serde(try_from = "u8", into = "u8")
+        )]
         pub struct $name(u8);
 
         impl TryFrom<u8> for $name {
@@ -17,6 +22,12 @@ macro_rules! midi_value {
             }
         }
 
+        impl From<$name> for u8 {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
         impl $name {
             pub fn new(value: u8) -> Result<Self, MidiError> {
                 Self::try_from(value)
@@ -34,3 +45,21 @@ midi_value!(Velocity, "Velocity");
 midi_value!(Pressure, "Pressure");
 midi_value!(Program, "Program");
 midi_value!(Control, "Control");
+
+impl Velocity {
+    // what a dynamic mark from "ppp" to "fff" usually plays as
+    pub fn from_dynamic(mark: &str) -> Option<Velocity> {
+        let value = match mark {
+            "ppp" => 16,
+            "pp" => 33,
+            "p" => 49,
+            "mp" => 64,
+            "mf" => 80,
+            "f" => 96,
+            "ff" => 112,
+            "fff" => 127,
+            _ => return None,
+        };
+        Some(Velocity(value))
+    }
+}
@@ -31,5 +31,54 @@ pub enum MidiError {
 
 
     #[error("Not a valid str note")]
-    InvalidNote
+    InvalidNote,
+
+    #[error("Not a valid scale, intervals should start at 0 and grow within one octave")]
+    InvalidScale,
+
+    #[error("Not a valid chord")]
+    InvalidChord,
+
+    #[error("SMPTE time division is not supported here, a ticks per beat division is needed")]
+    UnsupportedDivision,
+
+    #[error("Note out of range after the transformation: {0}. It should be from 0 to 127.")]
+    NoteOutOfRange(i32),
+
+    #[error("Not a valid duration, expected something like 1/4, 1/8T or 1/4.")]
+    InvalidDuration,
+
+    #[error("Invalid pitch bend: {0}. It should be from 0 to 16383.")]
+    InvalidPitchBend(u16),
+
+    #[error("Not a valid tempo, it should be from 1 to 16777215 microseconds per quarter note")]
+    InvalidTempo,
+
+    #[error("Not a valid time signature, expected something like 4/4 or 6/8")]
+    InvalidTimeSignature,
+
+    #[error("Nothing to merge, at least one song is needed")]
+    NothingToMerge,
+
+    #[error("No free channel left to move a colliding channel to")]
+    NoFreeChannel,
+
+    #[error("No track {0} in this song")]
+    NoSuchTrack(usize),
+
+    #[error("Not a valid SoundFont: {0}")]
+    InvalidSoundFont(String),
+
+    #[error("Too much audio for a WAV file, its sizes and byte rate must fit in 32 bits")]
+    WavTooLarge,
+
+    #[error("Too long to write as a score, it would take more than 10000 bars")]
+    TooManyMeasures,
+
+    #[error("Line {line}, column {column}: {message}")]
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
 }
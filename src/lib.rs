@@ -1,9 +1,24 @@
+pub mod analysis;
+pub mod compose;
 pub mod domain;
 pub mod error;
 pub mod midi;
+pub mod notation;
+pub mod render;
 pub mod smf;
+pub mod theory;
+pub mod transform;
 
+#[cfg(test)]
+mod test_util;
+
+pub use analysis::*;
+pub use compose::*;
 pub use domain::*;
 pub use error::*;
 pub use midi::*;
+pub use notation::*;
+pub use render::*;
 pub use smf::*;
+pub use theory::*;
+pub use transform::*;
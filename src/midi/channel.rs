@@ -1,7 +1,12 @@
 use crate::error::MidiError;
 use std::convert::TryFrom;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u8", into = "u8")
+)]
 pub struct Channel(u8);
 
 impl TryFrom<u8> for Channel {
@@ -16,7 +21,16 @@ impl TryFrom<u8> for Channel {
     }
 }
 
+impl From<Channel> for u8 {
+    fn from(value: Channel) -> Self {
+        value.0
+    }
+}
+
 impl Channel {
+    // General MIDI puts drums on channel 10 (9 counting from 0)
+    pub const DRUMS: Channel = Channel(9);
+
     pub fn new(value: u8) -> Result<Self, MidiError> {
         Self::try_from(value)
     }
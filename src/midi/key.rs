@@ -1,37 +1,166 @@
 use crate::domain::Note;
-use std::convert::TryFrom;
 use crate::error::MidiError;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
 
-impl TryFrom<&str> for Note {
-    type Error = MidiError;
+const SHARP_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+const FLAT_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B",
+];
 
-    fn try_from(value: &str) -> Result<Note, Self::Error> {
-        if value.len() < 2 {
-            return Err(MidiError::InvalidNote);
+// How black keys are written when a Note is turned back into a name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Spelling {
+    #[default]
+    Sharps,
+    Flats,
+}
+
+// Which octave number middle C (MIDI 60) gets.
+// C4 is the scientific pitch notation, C3 is what Yamaha and many DAWs use,
+// C5 is rare but shows up in some trackers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MiddleC {
+    C3,
+    #[default]
+    C4,
+    C5,
+}
+
+impl MiddleC {
+    // midi = (octave + offset) * 12 + pitch class
+    fn offset(&self) -> i32 {
+        match self {
+            MiddleC::C3 => 2,
+            MiddleC::C4 => 1,
+            MiddleC::C5 => 0,
         }
+    }
+}
 
-        let octave_char = value.chars().last().unwrap();
-        let octave = octave_char.to_digit(10).ok_or(MidiError::InvalidNote)? as i8;
-        let note_str = &value[0..value.len() - 1];
-
-        let note_index = match note_str {
-            "C"  => 0,
-            "C#" | "Db" => 1,
-            "D"  => 2,
-            "D#" | "Eb" => 3,
-            "E"  => 4,
-            "F"  => 5,
-            "F#" | "Gb" => 6,
-            "G"  => 7,
-            "G#" | "Ab" => 8,
-            "A"  => 9,
-            "A#" | "Bb" => 10,
-            "B"  => 11,
+impl Note {
+    // Parses names like "C4", "F#3", "Bb1", "Cb4", "E#3", "Fx2", "Abb5", "C-1".
+    // Sharps are '#' (or '♯'), flats 'b' (or '♭'), 'x' is a double sharp,
+    // at most two accidentals are allowed and they can't be mixed.
+    pub fn parse(value: &str, middle_c: MiddleC) -> Result<Note, MidiError> {
+        let mut chars = value.chars().peekable();
+
+        let letter = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
             _ => return Err(MidiError::InvalidNote),
         };
 
-        let midi_number = ((octave + 1) * 12 + note_index) as u8;
-        midi_number.try_into()
+        let mut accidental: i32 = 0;
+        let mut count = 0;
+        while let Some(&c) = chars.peek() {
+            let step: i32 = match c {
+                '#' | '♯' => 1,
+                'b' | '♭' => -1,
+                'x' | '𝄪' => 2,
+                '𝄫' => -2,
+                _ => break,
+            };
+            if accidental != 0 && accidental.signum() != step.signum() {
+                return Err(MidiError::InvalidNote);
+            }
+            accidental += step;
+            count += 1;
+            chars.next();
+        }
+        if count > 2 || accidental.abs() > 2 {
+            return Err(MidiError::InvalidNote);
+        }
+
+        let octave_str: String = chars.collect();
+        if octave_str.is_empty() {
+            return Err(MidiError::InvalidNote);
+        }
+        let octave: i32 = octave_str.parse().map_err(|_| MidiError::InvalidNote)?;
+
+        // any octave outside MIDI's couldn't be a note, and would overflow
+        if !(-2..=10).contains(&octave) {
+            return Err(MidiError::InvalidNote);
+        }
+        let midi_number = (octave + middle_c.offset()) * 12 + letter + accidental;
+        if !(0..=127).contains(&midi_number) {
+            return Err(MidiError::InvalidNote);
+        }
+        Note::new(midi_number as u8)
+    }
+
+    // 0 = C, 1 = C#/Db ... 11 = B
+    pub fn pitch_class(&self) -> u8 {
+        self.value() % 12
+    }
+
+    pub fn octave(&self, middle_c: MiddleC) -> i8 {
+        (self.value() as i32 / 12 - middle_c.offset()) as i8
+    }
+
+    pub fn name(&self, spelling: Spelling) -> &'static str {
+        match spelling {
+            Spelling::Sharps => SHARP_NAMES[self.pitch_class() as usize],
+            Spelling::Flats => FLAT_NAMES[self.pitch_class() as usize],
+        }
+    }
+
+    // The name with its octave, for formatting with {}. 61 is Db3 with
+    // Note::display(Spelling::Flats, MiddleC::C3)
+    pub fn display(&self, spelling: Spelling, middle_c: MiddleC) -> NoteName {
+        NoteName {
+            note: *self,
+            spelling,
+            middle_c,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteName {
+    note: Note,
+    spelling: Spelling,
+    middle_c: MiddleC,
+}
+
+impl fmt::Display for NoteName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            self.note.name(self.spelling),
+            self.note.octave(self.middle_c)
+        )
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(Spelling::default(), MiddleC::default()).fmt(f)
+    }
+}
+
+impl TryFrom<&str> for Note {
+    type Error = MidiError;
+
+    fn try_from(value: &str) -> Result<Note, Self::Error> {
+        Note::parse(value, MiddleC::default())
+    }
+}
+
+impl FromStr for Note {
+    type Err = MidiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Note::try_from(s)
     }
 }
 
@@ -57,11 +186,55 @@ mod test {
         assert_eq!(a0.value(), 21);
     }
 
+    #[test]
+    fn test_enharmonics() {
+        assert_eq!(Note::try_from("Cb4").unwrap().value(), 59);
+        assert_eq!(Note::try_from("E#3").unwrap().value(), 53);
+        assert_eq!(Note::try_from("B#3").unwrap().value(), 60);
+        assert_eq!(Note::try_from("Fx2").unwrap().value(), 43);
+        assert_eq!(Note::try_from("F##2").unwrap().value(), 43);
+        assert_eq!(Note::try_from("Abb5").unwrap().value(), 79);
+        assert_eq!(Note::try_from("C-1").unwrap().value(), 0);
+        assert_eq!(Note::try_from("G9").unwrap().value(), 127);
+    }
+
+    #[test]
+    fn test_middle_c_conventions() {
+        assert_eq!(Note::parse("C3", MiddleC::C3).unwrap().value(), 60);
+        assert_eq!(Note::parse("C5", MiddleC::C5).unwrap().value(), 60);
+        assert_eq!(Note::parse("C-2", MiddleC::C3).unwrap().value(), 0);
+        assert!(Note::parse("C-2", MiddleC::C4).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let note = Note::new(61).unwrap();
+        assert_eq!(note.to_string(), "C#4");
+        assert_eq!(
+            note.display(Spelling::Flats, MiddleC::C3).to_string(),
+            "Db3"
+        );
+        assert_eq!(Note::new(0).unwrap().to_string(), "C-1");
+        for value in 0..=127 {
+            let note = Note::new(value).unwrap();
+            assert_eq!(note.to_string().parse::<Note>().unwrap(), note);
+        }
+    }
+
     #[test]
     fn test_invalid_notes() {
         assert!(Note::try_from("H4").is_err());
         assert!(Note::try_from("C").is_err());
         assert!(Note::try_from("").is_err());
-        assert!(Note::try_from("Cb4").is_err());
+        assert!(Note::try_from("C#b4").is_err());
+        assert!(Note::try_from("C###4").is_err());
+        assert!(Note::try_from("G#9").is_err());
+        assert!(Note::try_from("Cb-1").is_err());
+        assert!(Note::try_from("C-").is_err());
+        assert_eq!(
+            Note::parse("C2000000000", MiddleC::C4),
+            Err(MidiError::InvalidNote)
+        );
+        assert!(Note::parse("C-2000000000", MiddleC::C3).is_err());
     }
 }
@@ -68,6 +68,11 @@ impl Status {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "RawChannelMessage", into = "RawChannelMessage")
+)]
 pub enum ChannelMessage {
     NoteOff { note: Note, velocity: Velocity },
     NoteOn { note: Note, velocity: Velocity },
@@ -78,7 +83,89 @@ pub enum ChannelMessage {
     PitchBend { value: u16 },
 }
 
+// the same shape as ChannelMessage, deserialized pitch bends are checked
+// for 14 bits on the way in
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "ChannelMessage")]
+enum RawChannelMessage {
+    NoteOff { note: Note, velocity: Velocity },
+    NoteOn { note: Note, velocity: Velocity },
+    PolyphonicKeyPressure { note: Note, pressure: Pressure },
+    ControlChange { control: Control, value: Control },
+    ProgramChange { program: Program },
+    ChannelPressure { pressure: Pressure },
+    PitchBend { value: u16 },
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawChannelMessage> for ChannelMessage {
+    type Error = MidiError;
+
+    fn try_from(raw: RawChannelMessage) -> Result<Self, Self::Error> {
+        Ok(match raw {
+            RawChannelMessage::NoteOff { note, velocity } => Self::NoteOff { note, velocity },
+            RawChannelMessage::NoteOn { note, velocity } => Self::NoteOn { note, velocity },
+            RawChannelMessage::PolyphonicKeyPressure { note, pressure } => {
+                Self::PolyphonicKeyPressure { note, pressure }
+            }
+            RawChannelMessage::ControlChange { control, value } => {
+                Self::ControlChange { control, value }
+            }
+            RawChannelMessage::ProgramChange { program } => Self::ProgramChange { program },
+            RawChannelMessage::ChannelPressure { pressure } => Self::ChannelPressure { pressure },
+            RawChannelMessage::PitchBend { value } if value <= 0x3FFF => Self::PitchBend { value },
+            RawChannelMessage::PitchBend { value } => {
+                return Err(MidiError::InvalidPitchBend(value))
+            }
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ChannelMessage> for RawChannelMessage {
+    fn from(message: ChannelMessage) -> Self {
+        match message {
+            ChannelMessage::NoteOff { note, velocity } => Self::NoteOff { note, velocity },
+            ChannelMessage::NoteOn { note, velocity } => Self::NoteOn { note, velocity },
+            ChannelMessage::PolyphonicKeyPressure { note, pressure } => {
+                Self::PolyphonicKeyPressure { note, pressure }
+            }
+            ChannelMessage::ControlChange { control, value } => {
+                Self::ControlChange { control, value }
+            }
+            ChannelMessage::ProgramChange { program } => Self::ProgramChange { program },
+            ChannelMessage::ChannelPressure { pressure } => Self::ChannelPressure { pressure },
+            ChannelMessage::PitchBend { value } => Self::PitchBend { value },
+        }
+    }
+}
+
+// What a ChannelMessage is, without its data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    NoteOff,
+    NoteOn,
+    PolyphonicKeyPressure,
+    ControlChange,
+    ProgramChange,
+    ChannelPressure,
+    PitchBend,
+}
+
 impl ChannelMessage {
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            ChannelMessage::NoteOff { .. } => MessageKind::NoteOff,
+            ChannelMessage::NoteOn { .. } => MessageKind::NoteOn,
+            ChannelMessage::PolyphonicKeyPressure { .. } => MessageKind::PolyphonicKeyPressure,
+            ChannelMessage::ControlChange { .. } => MessageKind::ControlChange,
+            ChannelMessage::ProgramChange { .. } => MessageKind::ProgramChange,
+            ChannelMessage::ChannelPressure { .. } => MessageKind::ChannelPressure,
+            ChannelMessage::PitchBend { .. } => MessageKind::PitchBend,
+        }
+    }
+
     pub fn event_type(&self) -> u8 {
         match self {
             ChannelMessage::NoteOff { .. } => 0,
@@ -143,6 +230,7 @@ impl ChannelMessage {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MidiMessage {
     Channel {
         channel: Channel,
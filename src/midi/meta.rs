@@ -1,6 +1,13 @@
+#[cfg(feature = "serde")]
+use crate::error::MidiError;
 use crate::track::Vql;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "RawMetaEvent", into = "RawMetaEvent")
+)]
 pub enum MetaEvent {
     TrackName(Vec<u8>),
     EndOfTrack,
@@ -21,6 +28,89 @@ pub enum MetaEvent {
     },
 }
 
+// the same shape as MetaEvent, deserialized tempos have to fit three
+// bytes and time signatures need a power of two below the bar
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename = "MetaEvent")]
+enum RawMetaEvent {
+    TrackName(Vec<u8>),
+    EndOfTrack,
+    SetTempo(u32),
+    TimeSignature {
+        numerator: u8,
+        denominator: u8,
+        clocks_per_tick: u8,
+        thirty_seconds_per_24_clocks: u8,
+    },
+    KeySignature {
+        sharps: i8,
+        is_major: bool,
+    },
+    Unknown {
+        event_type: u8,
+        data: Vec<u8>,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawMetaEvent> for MetaEvent {
+    type Error = MidiError;
+
+    fn try_from(raw: RawMetaEvent) -> Result<Self, Self::Error> {
+        Ok(match raw {
+            RawMetaEvent::TrackName(name) => Self::TrackName(name),
+            RawMetaEvent::EndOfTrack => Self::EndOfTrack,
+            RawMetaEvent::SetTempo(tempo @ 1..=0xFF_FFFF) => Self::SetTempo(tempo),
+            RawMetaEvent::SetTempo(_) => return Err(MidiError::InvalidTempo),
+            RawMetaEvent::TimeSignature {
+                numerator,
+                denominator,
+                clocks_per_tick,
+                thirty_seconds_per_24_clocks,
+            } => {
+                if numerator == 0 || !denominator.is_power_of_two() {
+                    return Err(MidiError::InvalidTimeSignature);
+                }
+                Self::TimeSignature {
+                    numerator,
+                    denominator,
+                    clocks_per_tick,
+                    thirty_seconds_per_24_clocks,
+                }
+            }
+            RawMetaEvent::KeySignature { sharps, is_major } => {
+                Self::KeySignature { sharps, is_major }
+            }
+            RawMetaEvent::Unknown { event_type, data } => Self::Unknown { event_type, data },
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<MetaEvent> for RawMetaEvent {
+    fn from(event: MetaEvent) -> Self {
+        match event {
+            MetaEvent::TrackName(name) => Self::TrackName(name),
+            MetaEvent::EndOfTrack => Self::EndOfTrack,
+            MetaEvent::SetTempo(tempo) => Self::SetTempo(tempo),
+            MetaEvent::TimeSignature {
+                numerator,
+                denominator,
+                clocks_per_tick,
+                thirty_seconds_per_24_clocks,
+            } => Self::TimeSignature {
+                numerator,
+                denominator,
+                clocks_per_tick,
+                thirty_seconds_per_24_clocks,
+            },
+            MetaEvent::KeySignature { sharps, is_major } => Self::KeySignature { sharps, is_major },
+            MetaEvent::Unknown { event_type, data } => Self::Unknown { event_type, data },
+        }
+    }
+}
+
 impl MetaEvent {
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = vec![0xFF]; // Meta event marker
@@ -1,12 +1,12 @@
 pub mod channel;
-// pub mod key;
+pub mod key;
 pub mod message;
 pub mod meta;
 pub mod real_time;
 pub mod system_common;
 
 pub use channel::*;
-// pub use key::*;
+pub use key::*;
 pub use message::*;
 pub use meta::*;
 pub use real_time::*;
@@ -3,6 +3,7 @@ use std::convert::TryFrom;
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RealTimeMessage {
     TimingClock = 0xF8,
     Reserved1 = 0xF9,
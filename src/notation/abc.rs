@@ -0,0 +1,1151 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Velocity};
+use crate::error::MidiError;
+use crate::header::{Header, MidiFormat};
+use crate::key::Spelling;
+use crate::meta::MetaEvent;
+use crate::notation::score::{key_spelling, segments};
+use crate::smf::Smf;
+use crate::span::{sort_releases_first, NoteSpan};
+use crate::theory::Duration;
+use crate::track::{EventType, Track, TrackEvent, Vql};
+use std::collections::{HashMap, HashSet};
+
+// ABC tunes, see https://abcnotation.com/wiki/abc:standard:v2.1
+//
+// Import reads the first tune of the text: the X T M L Q K header, notes
+// with accidentals and octave marks, rests, ties, tuplets, broken rhythm,
+// chords, repeats with 1st/2nd endings and inline [K:] [M:] [L:] [Q:]
+// fields. Chord symbols, decorations, grace notes and lyrics are skipped.
+const TICKS_PER_BEAT: u32 = 480;
+const VELOCITY: u8 = 80;
+
+// C D E F G A B
+const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+const LETTER_PITCH: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+// sharps of the major key on each letter, F is the only one with a flat
+const LETTER_SHARPS: [i8; 7] = [0, 2, 4, -1, 1, 3, 5];
+// letters in the order sharps are added, flats go the other way
+const SHARP_ORDER: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+
+fn letter_index(letter: char) -> Option<usize> {
+    LETTERS
+        .iter()
+        .position(|l| *l == letter.to_ascii_uppercase())
+}
+
+// how the key signature alters each letter
+fn key_alterations(sharps: i8) -> [i32; 7] {
+    let mut alterations = [0; 7];
+    for i in 0..sharps.unsigned_abs().min(7) as usize {
+        if sharps > 0 {
+            alterations[SHARP_ORDER[i]] = 1;
+        } else {
+            alterations[SHARP_ORDER[6 - i]] = -1;
+        }
+    }
+    alterations
+}
+
+fn time_signature(numerator: u8, denominator: u8) -> MetaEvent {
+    MetaEvent::TimeSignature {
+        numerator,
+        denominator,
+        clocks_per_tick: 24,
+        thirty_seconds_per_24_clocks: 8,
+    }
+}
+
+fn scale(duration: Duration, numerator: u32, denominator: u32) -> Result<Duration, MidiError> {
+    match (
+        duration.numerator().checked_mul(numerator),
+        duration.denominator().checked_mul(denominator),
+    ) {
+        (Some(numerator), Some(denominator)) => Duration::new(numerator, denominator),
+        _ => Err(MidiError::InvalidDuration),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Element {
+    Notes {
+        notes: Vec<Note>,
+        duration: Duration,
+        tie: bool,
+    },
+    Rest(Duration),
+    Meta(MetaEvent),
+    RepeatStart,
+    RepeatEnd,
+    Ending(u32),
+}
+
+impl Element {
+    fn duration_mut(&mut self) -> Option<&mut Duration> {
+        match self {
+            Element::Notes { duration, .. } | Element::Rest(duration) => Some(duration),
+            _ => None,
+        }
+    }
+}
+
+struct Tune {
+    title: Option<String>,
+    meter: (u8, u8),
+    unit: Option<Duration>,
+    sharps: i8,
+    // accidentals written in the current bar, by (letter, octave)
+    bar: HashMap<(usize, i32), i32>,
+    // (notes left, factor numerator, factor denominator)
+    tuplet: Option<(u32, u32, u32)>,
+    // what the next note is scaled by after a > or <
+    broken: Option<(u32, u32)>,
+    elements: Vec<Element>,
+}
+
+impl Tune {
+    fn new() -> Self {
+        Self {
+            title: None,
+            meter: (4, 4),
+            unit: None,
+            sharps: 0,
+            bar: HashMap::new(),
+            tuplet: None,
+            broken: None,
+            elements: Vec::new(),
+        }
+    }
+
+    // without an L: field the unit is 1/16 for meters under 3/4, else 1/8
+    fn unit(&self) -> Duration {
+        self.unit.unwrap_or({
+            let (n, d) = self.meter;
+            if (n as f32) / (d as f32) < 0.75 {
+                Duration::SIXTEENTH
+            } else {
+                Duration::EIGHTH
+            }
+        })
+    }
+
+    fn field(&mut self, field: char, value: &str, line: usize) -> Result<(), MidiError> {
+        let error = |message: &str| MidiError::Parse {
+            line,
+            column: 1,
+            message: format!("{message} in {field}:{value}"),
+        };
+        match field {
+            'T' if self.title.is_none() => self.title = Some(value.to_string()),
+            'M' => {
+                self.meter = parse_meter(value).ok_or_else(|| error("bad meter"))?;
+                let (n, d) = self.meter;
+                self.elements.push(Element::Meta(time_signature(n, d)));
+            }
+            'L' => {
+                self.unit = Some(value.parse().map_err(|_| error("bad unit length"))?);
+            }
+            'Q' => {
+                let tempo = parse_tempo(value, self.unit()).ok_or_else(|| error("bad tempo"))?;
+                self.elements
+                    .push(Element::Meta(MetaEvent::SetTempo(tempo)));
+            }
+            'K' => {
+                let (sharps, is_major) = parse_key(value).ok_or_else(|| error("bad key"))?;
+                self.sharps = sharps;
+                self.elements
+                    .push(Element::Meta(MetaEvent::KeySignature { sharps, is_major }));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn push_sound(&mut self, mut element: Element) -> Result<(), MidiError> {
+        if let Some(duration) = element.duration_mut() {
+            if let Some((left, n, d)) = self.tuplet {
+                *duration = scale(*duration, n, d)?;
+                self.tuplet = (left > 1).then_some((left - 1, n, d));
+            }
+            if let Some((n, d)) = self.broken.take() {
+                *duration = scale(*duration, n, d)?;
+            }
+        }
+        self.elements.push(element);
+        Ok(())
+    }
+
+    fn new_bar(&mut self) {
+        self.bar.clear();
+    }
+
+    // the elements with repeats played out
+    fn unrolled(&self) -> Vec<Element> {
+        let elements = &self.elements;
+        let mut played = Vec::new();
+        let mut repeated: HashSet<usize> = HashSet::new();
+        let (mut start, mut pass, mut i) = (0, 1, 0);
+        while i < elements.len() {
+            match &elements[i] {
+                Element::RepeatStart => {
+                    start = i + 1;
+                    pass = 1;
+                }
+                Element::RepeatEnd if !repeated.contains(&i) => {
+                    repeated.insert(i);
+                    pass += 1;
+                    i = start;
+                    continue;
+                }
+                // more endings follow, the section isn't over yet
+                Element::RepeatEnd if matches!(elements.get(i + 1), Some(Element::Ending(_))) => {}
+                Element::RepeatEnd => {
+                    start = i + 1;
+                    pass = 1;
+                }
+                Element::Ending(n) if *n != pass => {
+                    // skip to the ending of this pass, or past the repeat
+                    i += 1;
+                    while i < elements.len() {
+                        match &elements[i] {
+                            Element::Ending(m) if *m == pass => break,
+                            Element::RepeatEnd if repeated.contains(&i) => break,
+                            Element::RepeatStart => break,
+                            _ => i += 1,
+                        }
+                    }
+                    continue;
+                }
+                Element::Ending(_) => {}
+                other => played.push(other.clone()),
+            }
+            i += 1;
+        }
+        played
+    }
+}
+
+// "4/4", "6/8", "C", "C|", "2+3/8"
+fn parse_meter(value: &str) -> Option<(u8, u8)> {
+    match value.trim() {
+        "C" => return Some((4, 4)),
+        "C|" => return Some((2, 2)),
+        "none" | "" => return Some((4, 4)),
+        _ => {}
+    }
+    let (numerator, denominator) = value.trim().split_once('/')?;
+    let numerator: u32 = numerator
+        .split('+')
+        .map(|n| n.trim().parse::<u32>().ok())
+        .sum::<Option<u32>>()?;
+    let denominator: u8 = denominator.trim().parse().ok()?;
+    if numerator == 0 || numerator > 255 || !denominator.is_power_of_two() {
+        return None;
+    }
+    Some((numerator as u8, denominator))
+}
+
+// "1/4=120", "3/8=60", "120" (in unit lengths), with optional "text"
+fn parse_tempo(value: &str, unit: Duration) -> Option<u32> {
+    let value: String = value.split('"').step_by(2).collect();
+    let (beat, bpm) = match value.split_once('=') {
+        Some((beat, bpm)) => {
+            let mut length = 0.0;
+            for part in beat.split_whitespace() {
+                let d: Duration = part.parse().ok()?;
+                length += d.numerator() as f64 / d.denominator() as f64;
+            }
+            (length, bpm)
+        }
+        None => (
+            unit.numerator() as f64 / unit.denominator() as f64,
+            value.as_str(),
+        ),
+    };
+    let bpm: f64 = bpm.trim().parse().ok()?;
+    let quarters = beat * 4.0;
+    if bpm <= 0.0 || quarters <= 0.0 {
+        return None;
+    }
+    let tempo = (60_000_000.0 / (bpm * quarters)).round();
+    (1.0..=0xFF_FFFF as f64)
+        .contains(&tempo)
+        .then_some(tempo as u32)
+}
+
+// "G", "Bb", "F#m", "Ador", "Emin", "Dmix", "none"
+fn parse_key(value: &str) -> Option<(i8, bool)> {
+    let value = value.split_whitespace().next().unwrap_or("none");
+    if value == "none" || value.starts_with('H') {
+        return Some((0, true));
+    }
+    let mut chars = value.chars();
+    let letter = letter_index(chars.next()?)?;
+    let rest = chars.as_str();
+    let (accidental, mode) = match rest.chars().next() {
+        Some('#') => (7, &rest[1..]),
+        Some('b') => (-7, &rest[1..]),
+        _ => (0, rest),
+    };
+    let mode = mode.to_ascii_lowercase();
+    let (shift, is_major) = match mode.get(..3.min(mode.len())).unwrap_or("") {
+        "" | "maj" | "ion" => (0, true),
+        "m" | "mi" | "min" | "aeo" => (-3, false),
+        "mix" => (-1, true),
+        "dor" => (-2, true),
+        "phr" => (-4, true),
+        "lyd" => (1, true),
+        "loc" => (-5, true),
+        _ => return None,
+    };
+    let sharps = LETTER_SHARPS[letter] + accidental + shift;
+    (-7..=7).contains(&sharps).then_some((sharps, is_major))
+}
+
+// Reads one body line into tune
+struct Line<'a> {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    tune: &'a mut Tune,
+}
+
+impl Line<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.index + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.index += 1;
+        c
+    }
+
+    fn error(&self, column: usize, message: impl Into<String>) -> MidiError {
+        MidiError::Parse {
+            line: self.line,
+            column: column + 1,
+            message: message.into(),
+        }
+    }
+
+    fn skip_past(&mut self, close: char) -> Result<(), MidiError> {
+        let start = self.index;
+        self.bump();
+        while let Some(c) = self.bump() {
+            if c == close {
+                return Ok(());
+            }
+        }
+        Err(self.error(start, format!("missing '{close}'")))
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let mut digits = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(c);
+            self.bump();
+        }
+        digits.parse().ok()
+    }
+
+    // "2", "3/2", "/", "//", "/4", as a fraction of the unit
+    fn length(&mut self) -> Result<(u32, u32), MidiError> {
+        let column = self.index;
+        let numerator = self.number().unwrap_or(1);
+        let mut denominator: u32 = 1;
+        while self.peek() == Some('/') {
+            self.bump();
+            denominator = denominator
+                .checked_mul(self.number().unwrap_or(2))
+                .ok_or_else(|| self.error(column, "length too short"))?;
+        }
+        Ok((numerator, denominator))
+    }
+
+    fn parse(&mut self) -> Result<(), MidiError> {
+        while let Some(c) = self.peek() {
+            let column = self.index;
+            match c {
+                '%' => break,
+                ' ' | '\t' | '`' | '\\' | 'y' | ')' => {
+                    self.bump();
+                }
+                '"' => self.skip_past('"')?,
+                '!' => self.skip_past('!')?,
+                '+' => self.skip_past('+')?,
+                '{' => self.skip_past('}')?,
+                '~' | '.' | 'H' | 'L' | 'M' | 'O' | 'P' | 'S' | 'T' | 'u' | 'v' => {
+                    self.bump();
+                }
+                '|' | ':' => self.bar_line(),
+                '[' => match self.peek_at(1) {
+                    Some(d) if d.is_ascii_digit() => {
+                        self.bump();
+                        let n = self.number().unwrap_or(1);
+                        self.tune.elements.push(Element::Ending(n));
+                    }
+                    Some('|') => {
+                        self.bump();
+                        self.bar_line();
+                    }
+                    Some(f) if f.is_ascii_alphabetic() && self.peek_at(2) == Some(':') => {
+                        self.inline_field()?
+                    }
+                    _ => self.chord()?,
+                },
+                '(' => self.tuplet(column)?,
+                '>' | '<' => self.broken_rhythm(column)?,
+                'z' | 'x' => {
+                    self.bump();
+                    let (n, d) = self.length()?;
+                    let duration = scale(self.tune.unit(), n, d)
+                        .map_err(|_| self.error(column, "bad length"))?;
+                    self.push_sound(column, Element::Rest(duration))?;
+                }
+                'Z' | 'X' => {
+                    self.bump();
+                    let bars = self.number().unwrap_or(1);
+                    let (n, d) = self.tune.meter;
+                    let duration = (n as u32)
+                        .checked_mul(bars)
+                        .ok_or(MidiError::InvalidDuration)
+                        .and_then(|n| Duration::new(n, d as u32))
+                        .map_err(|_| self.error(column, "bad bar count"))?;
+                    self.tune.elements.push(Element::Rest(duration));
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    let (note, (n, d)) = self.note()?;
+                    let duration = scale(self.tune.unit(), n, d)
+                        .map_err(|_| self.error(column, "bad length"))?;
+                    let tie = self.tie();
+                    self.push_sound(
+                        column,
+                        Element::Notes {
+                            notes: vec![note],
+                            duration,
+                            tie,
+                        },
+                    )?;
+                }
+                _ => return Err(self.error(column, format!("unexpected '{c}'"))),
+            }
+        }
+        Ok(())
+    }
+
+    // a tuplet or broken rhythm can still make the length impossible
+    fn push_sound(&mut self, column: usize, element: Element) -> Result<(), MidiError> {
+        self.tune
+            .push_sound(element)
+            .map_err(|_| self.error(column, "bad length"))
+    }
+
+    fn tie(&mut self) -> bool {
+        let tie = self.peek() == Some('-');
+        if tie {
+            self.bump();
+        }
+        tie
+    }
+
+    // | || |] [| |: :| :: and endings like |1 or :|2
+    fn bar_line(&mut self) {
+        let mut run = String::new();
+        while let Some(c) = self.peek().filter(|c| matches!(c, '|' | ':' | ']' | '[')) {
+            if c == '[' && !run.is_empty() {
+                break;
+            }
+            run.push(c);
+            self.bump();
+        }
+        if run.starts_with(':') {
+            self.tune.elements.push(Element::RepeatEnd);
+        }
+        if run.ends_with(':') && run.len() > 1 || run == ":" {
+            self.tune.elements.push(Element::RepeatStart);
+        }
+        self.tune.new_bar();
+        if let Some(n) = self.number() {
+            self.tune.elements.push(Element::Ending(n));
+        }
+    }
+
+    fn inline_field(&mut self) -> Result<(), MidiError> {
+        let start = self.index;
+        self.bump();
+        let field = self.bump().unwrap();
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some(']') => break,
+                Some(c) => value.push(c),
+                None => return Err(self.error(start, "missing ']'")),
+            }
+        }
+        self.tune.field(field, value.trim(), self.line)
+    }
+
+    fn note(&mut self) -> Result<(Note, (u32, u32)), MidiError> {
+        let column = self.index;
+        let mut accidental: Option<i32> = None;
+        while let Some(c) = self.peek() {
+            let step = match c {
+                '^' => 1,
+                '_' => -1,
+                '=' => 0,
+                _ => break,
+            };
+            accidental = Some(accidental.unwrap_or(0) + step);
+            self.bump();
+        }
+        let letter = self.bump().filter(|c| c.is_ascii_alphabetic());
+        let Some(index) = letter.and_then(letter_index) else {
+            return Err(self.error(column, "expected a note"));
+        };
+        let mut octave: i32 = if letter.unwrap().is_ascii_lowercase() {
+            5
+        } else {
+            4
+        };
+        while let Some(mark) = self.peek() {
+            match mark {
+                '\'' => octave += 1,
+                ',' => octave -= 1,
+                _ => break,
+            }
+            self.bump();
+        }
+
+        let alteration = match accidental {
+            Some(a) => {
+                self.tune.bar.insert((index, octave), a);
+                a
+            }
+            None => *self
+                .tune
+                .bar
+                .get(&(index, octave))
+                .unwrap_or(&key_alterations(self.tune.sharps)[index]),
+        };
+        let pitch = (octave + 1) * 12 + LETTER_PITCH[index] + alteration;
+        let note = u8::try_from(pitch)
+            .ok()
+            .and_then(|p| Note::new(p).ok())
+            .ok_or_else(|| self.error(column, "note out of range"))?;
+        Ok((note, self.length()?))
+    }
+
+    // [CEG]2, the length of the first note times the one after the bracket
+    fn chord(&mut self) -> Result<(), MidiError> {
+        let start = self.index;
+        self.bump();
+        let mut notes = Vec::new();
+        let mut inner = None;
+        loop {
+            match self.peek() {
+                Some(']') => {
+                    self.bump();
+                    break;
+                }
+                Some(' ') | Some('-') => {
+                    self.bump();
+                }
+                Some(_) => {
+                    let (note, length) = self.note()?;
+                    inner.get_or_insert(length);
+                    notes.push(note);
+                }
+                None => return Err(self.error(start, "missing ']'")),
+            }
+        }
+        let (n, d) = inner.unwrap_or((1, 1));
+        let (outer_n, outer_d) = self.length()?;
+        let duration = scale(self.tune.unit(), n, d)
+            .and_then(|inner| scale(inner, outer_n, outer_d))
+            .map_err(|_| self.error(start, "bad length"))?;
+        let tie = self.tie();
+        self.push_sound(
+            start,
+            Element::Notes {
+                notes,
+                duration,
+                tie,
+            },
+        )
+    }
+
+    // (3 is three in the time of two, (p:q:r puts p notes in the time of q
+    // for the next r notes
+    fn tuplet(&mut self, column: usize) -> Result<(), MidiError> {
+        self.bump();
+        let Some(p) = self.number() else {
+            return Ok(()); // a slur
+        };
+        let mut q = None;
+        let mut r = None;
+        if self.peek() == Some(':') {
+            self.bump();
+            q = self.number();
+            if self.peek() == Some(':') {
+                self.bump();
+                r = self.number();
+            }
+        }
+        let (n, _) = self.tune.meter;
+        let compound = n % 3 == 0 && n > 3;
+        let q = q.unwrap_or(match p {
+            2 | 4 | 8 => 3,
+            3 | 6 => 2,
+            _ if compound => 3,
+            _ => 2,
+        });
+        if p == 0 || q == 0 {
+            return Err(self.error(column, "bad tuplet"));
+        }
+        self.tune.tuplet = Some((r.unwrap_or(p), q, p));
+        Ok(())
+    }
+
+    // a>b makes a dotted and b short, a<b the other way round
+    fn broken_rhythm(&mut self, column: usize) -> Result<(), MidiError> {
+        let symbol = self.bump().unwrap();
+        let mut count = 1;
+        while self.peek() == Some(symbol) {
+            self.bump();
+            count += 1;
+        }
+        let k = 1u32 << count.min(3);
+        let (long, short) = ((2 * k - 1, k), (1, k));
+        let (previous, next) = if symbol == '>' {
+            (long, short)
+        } else {
+            (short, long)
+        };
+        let Some(duration) = self
+            .tune
+            .elements
+            .iter_mut()
+            .rev()
+            .find_map(|e| e.duration_mut())
+        else {
+            return Err(self.error(column, "nothing before the broken rhythm"));
+        };
+        let Ok(scaled) = scale(*duration, previous.0, previous.1) else {
+            return Err(self.error(column, "bad length"));
+        };
+        *duration = scaled;
+        self.tune.broken = Some(next);
+        Ok(())
+    }
+}
+
+impl Smf {
+    // The first tune of an ABC text as a single track at 480 ticks per beat
+    pub fn from_abc(text: &str) -> Result<Smf, MidiError> {
+        let mut tune = Tune::new();
+        let mut in_body = false;
+        let mut started = false;
+
+        for (number, raw) in text.lines().enumerate() {
+            let line = number + 1;
+            let content = raw.split('%').next().unwrap_or("").trim_end();
+            let mut chars = content.chars();
+            let field = match (chars.next(), chars.next()) {
+                (Some(f), Some(':')) if f.is_ascii_alphabetic() => Some(f),
+                _ => None,
+            };
+
+            match field {
+                Some('X') if started => break,
+                Some(f) => {
+                    started = true;
+                    tune.field(f, content[2..].trim(), line)?;
+                    if f == 'K' {
+                        in_body = true;
+                    }
+                }
+                // a blank line ends the tune
+                None if content.trim().is_empty() && in_body => break,
+                None if content.trim().is_empty() => {}
+                None if in_body => {
+                    Line {
+                        chars: content.chars().collect(),
+                        index: 0,
+                        line,
+                        tune: &mut tune,
+                    }
+                    .parse()?;
+                }
+                None => {}
+            }
+        }
+        if !in_body {
+            return Err(MidiError::Parse {
+                line: text.lines().count().max(1),
+                column: 1,
+                message: "no K: field, the tune has no body".to_string(),
+            });
+        }
+
+        let track = tune_to_track(&tune)?;
+        let header = Header::new(MidiFormat::SingleTrack, 1, TICKS_PER_BEAT as i16)?;
+        Ok(Smf::new(header, vec![track]))
+    }
+}
+
+fn tune_to_track(tune: &Tune) -> Result<Track, MidiError> {
+    let channel = Channel::new(0)?;
+    let velocity = Velocity::new(VELOCITY)?;
+    let whole = TICKS_PER_BEAT as f64 * 4.0;
+    let ticks = |d: Duration| whole * d.numerator() as f64 / d.denominator() as f64;
+
+    let mut events: Vec<(u64, TrackEvent)> = Vec::new();
+    // (start, end, note) with ties still open, by note
+    let mut spans: Vec<(f64, f64, Note)> = Vec::new();
+    let mut open: HashMap<Note, usize> = HashMap::new();
+    let mut cursor: f64 = 0.0;
+
+    for element in tune.unrolled() {
+        match element {
+            Element::Meta(meta) => {
+                events.push((cursor.round() as u64, TrackEvent::meta_event(meta)));
+            }
+            Element::Rest(duration) => {
+                open.clear();
+                cursor += ticks(duration);
+            }
+            Element::Notes {
+                notes,
+                duration,
+                tie,
+            } => {
+                let end = cursor + ticks(duration);
+                let mut still_open = HashMap::new();
+                for note in notes {
+                    let index = match open.get(&note) {
+                        Some(&i) if (spans[i].1 - cursor).abs() < 0.5 => {
+                            spans[i].1 = end;
+                            i
+                        }
+                        _ => {
+                            spans.push((cursor, end, note));
+                            spans.len() - 1
+                        }
+                    };
+                    if tie {
+                        still_open.insert(note, index);
+                    }
+                }
+                open = still_open;
+                cursor = end;
+            }
+            _ => {}
+        }
+    }
+
+    let release = Velocity::new(0)?;
+    for (start, end, note) in spans {
+        events.push((
+            start.round() as u64,
+            TrackEvent::note_on(Vql::zero(), channel, note, velocity),
+        ));
+        events.push((
+            end.round() as u64,
+            TrackEvent::note_off(Vql::zero(), channel, note, release),
+        ));
+    }
+    events.push((cursor.round() as u64, TrackEvent::end_track()));
+    sort_releases_first(&mut events);
+    let track = Track::from_absolute_events(events)?;
+    Ok(match &tune.title {
+        Some(title) => track.with_name(title),
+        None => track,
+    })
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Writes the segments of one voice as ABC, notes crossing a bar line are
+// split and tied. Lengths are in eighths (L:1/8).
+struct AbcWriter {
+    ticks_per_beat: u64,
+    bar_ticks: u64,
+    alterations: [i32; 7],
+    spelling: Spelling,
+    bar: HashMap<(usize, i32), i32>,
+    bars: Vec<String>,
+    current: String,
+    position: u64,
+}
+
+impl AbcWriter {
+    fn length(&self, ticks: u64) -> String {
+        // in eighths: ticks / (ticks_per_beat / 2)
+        let (n, d) = (ticks * 2, self.ticks_per_beat);
+        let g = gcd(n, d).max(1);
+        match (n / g, d / g) {
+            (1, 1) => String::new(),
+            (n, 1) => n.to_string(),
+            (1, 2) => "/".to_string(),
+            (1, d) => format!("/{d}"),
+            (n, d) => format!("{n}/{d}"),
+        }
+    }
+
+    fn pitch(&mut self, note: Note) -> String {
+        let name = note.name(self.spelling);
+        let mut chars = name.chars();
+        let letter = chars.next().unwrap();
+        let alteration = match chars.next() {
+            Some('#') => 1,
+            Some('b') => -1,
+            _ => 0,
+        };
+        let index = letter_index(letter).unwrap();
+        let octave = note.value() as i32 / 12 - 1;
+
+        let mut text = String::new();
+        let current = *self
+            .bar
+            .get(&(index, octave))
+            .unwrap_or(&self.alterations[index]);
+        if current != alteration {
+            text.push(match alteration {
+                1 => '^',
+                -1 => '_',
+                _ => '=',
+            });
+            self.bar.insert((index, octave), alteration);
+        }
+        if octave >= 5 {
+            text.push(letter.to_ascii_lowercase());
+            text.extend(std::iter::repeat_n('\'', (octave - 5) as usize));
+        } else {
+            text.push(letter);
+            text.extend(std::iter::repeat_n(',', (4 - octave) as usize));
+        }
+        text
+    }
+
+    // notes (or a rest when empty) for ticks, split at bar lines
+    fn write(&mut self, notes: &[Note], mut ticks: u64) {
+        while ticks > 0 {
+            let room = self.bar_ticks - self.position % self.bar_ticks;
+            let chunk = ticks.min(room);
+            let length = self.length(chunk);
+            if !self.current.is_empty() {
+                self.current.push(' ');
+            }
+            match notes {
+                [] => self.current.push('z'),
+                [note] => {
+                    let pitch = self.pitch(*note);
+                    self.current.push_str(&pitch);
+                }
+                _ => {
+                    self.current.push('[');
+                    for note in notes {
+                        let pitch = self.pitch(*note);
+                        self.current.push_str(&pitch);
+                    }
+                    self.current.push(']');
+                }
+            }
+            self.current.push_str(&length);
+            ticks -= chunk;
+            if ticks > 0 && !notes.is_empty() {
+                self.current.push('-');
+            }
+            self.position += chunk;
+            if self.position.is_multiple_of(self.bar_ticks) {
+                self.bars.push(std::mem::take(&mut self.current));
+                self.bar.clear();
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.current.is_empty() {
+            self.bars.push(std::mem::take(&mut self.current));
+        }
+        let lines: Vec<String> = self
+            .bars
+            .chunks(4)
+            .map(|bars| format!("{} |", bars.join(" | ")))
+            .collect();
+        let mut body = lines.join("\n");
+        if body.ends_with('|') {
+            body.push(']');
+        }
+        body
+    }
+}
+
+fn write_abc(
+    title: &str,
+    ticks_per_beat: u32,
+    metas: &[(u64, MetaEvent)],
+    spans: &[NoteSpan],
+) -> String {
+    let (mut numerator, mut denominator) = (4u8, 4u8);
+    let (mut sharps, mut is_major) = (0i8, true);
+    let mut tempo = None;
+    for (_, meta) in metas.iter().rev() {
+        match meta {
+            MetaEvent::TimeSignature {
+                numerator: n,
+                denominator: d,
+                ..
+            } => (numerator, denominator) = (*n, *d),
+            MetaEvent::KeySignature {
+                sharps: s,
+                is_major: m,
+            } => (sharps, is_major) = (*s, *m),
+            MetaEvent::SetTempo(t) => tempo = Some(*t),
+            _ => {}
+        }
+    }
+
+    let mut text = format!("X:1\nT:{}\nM:{numerator}/{denominator}\nL:1/8\n", title);
+    if let Some(tempo) = tempo {
+        text += &format!("Q:1/4={}\n", (60_000_000.0 / tempo as f64).round());
+    }
+    let tonic = crate::tonality::Key::from_signature(sharps, is_major);
+    let spelling = key_spelling(sharps);
+    let key = tonic.map_or("C".to_string(), |k| {
+        let name = Note::new(k.tonic())
+            .map(|n| n.name(spelling))
+            .unwrap_or("C");
+        format!("{name}{}", if is_major { "" } else { "m" })
+    });
+    text += &format!("K:{key}\n");
+
+    let tpb = ticks_per_beat.max(1) as u64;
+    let mut writer = AbcWriter {
+        ticks_per_beat: tpb,
+        bar_ticks: (tpb * 4 * numerator as u64 / denominator.max(1) as u64).max(1),
+        alterations: key_alterations(sharps),
+        spelling,
+        bar: HashMap::new(),
+        bars: Vec::new(),
+        current: String::new(),
+        position: 0,
+    };
+
+    // the same voice as the other score writers, in ticks
+    for segment in segments(spans, |tick| tick) {
+        writer.write(&segment.notes, segment.end - segment.start);
+    }
+
+    text += &writer.finish();
+    text.push('\n');
+    text
+}
+
+impl Track {
+    // Best effort ABC for a monophonic track, using the time signature,
+    // key signature and tempo found in the track itself
+    pub fn to_abc(&self, ticks_per_beat: u32) -> String {
+        let metas: Vec<(u64, MetaEvent)> = self
+            .absolute_events()
+            .filter_map(|(tick, e)| match &e.event {
+                EventType::Meta(meta) => Some((tick, meta.clone())),
+                _ => None,
+            })
+            .collect();
+        let title = if self.name().is_empty() {
+            "Untitled"
+        } else {
+            self.name()
+        };
+        write_abc(title, ticks_per_beat, &metas, &self.note_spans())
+    }
+}
+
+impl Smf {
+    // like Track::to_abc, the signatures and tempo can be in any track
+    // (usually the first one of a multi track file)
+    pub fn to_abc(&self, track: usize) -> Result<String, MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)?;
+        let metas: Vec<(u64, MetaEvent)> = self
+            .tracks()
+            .iter()
+            .flat_map(|t| t.absolute_events())
+            .filter_map(|(tick, e)| match &e.event {
+                EventType::Meta(meta) => Some((tick, meta.clone())),
+                _ => None,
+            })
+            .collect();
+        let chosen = self
+            .tracks()
+            .get(track)
+            .ok_or(MidiError::NoSuchTrack(track))?;
+        let title = self
+            .tracks()
+            .iter()
+            .map(|t| t.name())
+            .find(|n| !n.is_empty())
+            .unwrap_or("Untitled");
+        Ok(write_abc(
+            title,
+            ticks_per_beat,
+            &metas,
+            &chosen.note_spans(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{note, track_of};
+
+    fn spans(smf: &Smf) -> Vec<(u64, u64, u8)> {
+        smf.note_spans()
+            .iter()
+            .map(|s| (s.start, s.end, s.note.value()))
+            .collect()
+    }
+
+    fn metas(smf: &Smf) -> Vec<(u64, MetaEvent)> {
+        smf.tracks()[0]
+            .absolute_events()
+            .filter_map(|(tick, e)| match &e.event {
+                EventType::Meta(MetaEvent::EndOfTrack)
+                | EventType::Meta(MetaEvent::TrackName(_)) => None,
+                EventType::Meta(meta) => Some((tick, meta.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn header_and_notes() {
+        let tune = "\
+X:1
+T:Test Reel
+M:4/4
+L:1/8
+Q:1/4=120
+K:G
+\"G\"GA ^A=A f2 e>d | (3efg c'2 B,4- | B,2 z2 [GBd]4 |]
+";
+        let smf = Smf::from_abc(tune).unwrap();
+        assert_eq!(smf.tracks()[0].name(), "Test Reel");
+        assert_eq!(
+            metas(&smf),
+            vec![
+                (0, time_signature(4, 4)),
+                (0, MetaEvent::SetTempo(500_000)),
+                (
+                    0,
+                    MetaEvent::KeySignature {
+                        sharps: 1,
+                        is_major: true
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            spans(&smf),
+            vec![
+                (0, 240, 67),
+                (240, 480, 69),
+                (480, 720, 70),
+                (720, 960, 69),
+                // f is sharp in G
+                (960, 1440, 78),
+                (1440, 1800, 76),
+                (1800, 1920, 74),
+                (1920, 2080, 76),
+                (2080, 2240, 78),
+                (2240, 2400, 79),
+                (2400, 2880, 84),
+                // tied across the bar line
+                (2880, 4320, 59),
+                (4800, 5760, 67),
+                (4800, 5760, 71),
+                (4800, 5760, 74),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_and_endings() {
+        let smf = Smf::from_abc("X:1\nL:1/4\nK:Am\n|: A B |1 c d :|2 e f |]\n").unwrap();
+        let notes: Vec<u8> = spans(&smf).iter().map(|s| s.2).collect();
+        assert_eq!(notes, vec![69, 71, 72, 74, 69, 71, 76, 77]);
+        assert_eq!(
+            metas(&smf)[0].1,
+            MetaEvent::KeySignature {
+                sharps: 0,
+                is_major: false
+            }
+        );
+
+        let error = Smf::from_abc("X:1\nK:C\nC D | E ? |\n").unwrap_err();
+        assert_eq!(
+            error,
+            MidiError::Parse {
+                line: 3,
+                column: 9,
+                message: "unexpected '?'".to_string()
+            }
+        );
+
+        // lengths that don't fit are errors where they are written
+        let position = |body: &str| match Smf::from_abc(&format!("X:1\nK:C\n{body}\n")) {
+            Err(MidiError::Parse { line, column, .. }) => (line, column),
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(position(&format!("D C{}", "/".repeat(50))), (3, 4));
+        assert_eq!(position("C Z4000000000"), (3, 3));
+        assert_eq!(position("C (0 DEF"), (3, 3));
+        assert_eq!(position("C4294967295 >> D"), (3, 13));
+    }
+
+    #[test]
+    fn export_and_back() {
+        let tune = "X:1\nT:Round\nM:3/4\nL:1/8\nK:F\nF2 G2 A B | c4 _d =d- | d6 |]\n";
+        let smf = Smf::from_abc(tune).unwrap();
+        let abc = smf.to_abc(0).unwrap();
+        assert_eq!(
+            abc,
+            "X:1\nT:Round\nM:3/4\nL:1/8\nK:F\nF2 G2 A B | c4 _d =d- | d6 |]\n"
+        );
+        assert_eq!(spans(&Smf::from_abc(&abc).unwrap()), spans(&smf));
+    }
+
+    #[test]
+    fn export_cuts_voices_like_the_other_scores() {
+        // C doubled and E held on, the chord is cut where G starts
+        let track = track_of(
+            &[
+                note(0, 240, 60),
+                note(0, 240, 60),
+                note(0, 960, 64),
+                note(480, 1920, 67),
+            ],
+            Vec::new(),
+        );
+        assert_eq!(
+            track.to_abc(480),
+            "X:1\nT:Untitled\nM:4/4\nL:1/8\nK:C\n[CE] z G6 |]\n"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use crate::error::MidiError;
+use crate::key::Spelling;
+use crate::meta::MetaEvent;
+use crate::notation::score::{conductor, key_spelling, measures, segments, Measure, Segment};
+use crate::smf::Smf;
+use crate::tonality::Key;
+use crate::track::Track;
+use crate::Note;
+
+// LilyPond scores, see https://lilypond.org/doc/v2.24/Documentation/notation/
+//
+// Times are quantized to the nearest 32nd note. Each track is a staff of
+// one voice, notes starting together are a chord and a note is cut where
+// the next one starts. Lengths that aren't a plain or dotted value are
+// written as tied notes, and so are notes crossing a bar line.
+const PER_QUARTER: u64 = 8;
+
+// (length in 32nds, LilyPond duration), longest first
+const VALUES: [(u64, &str); 11] = [
+    (48, "1."),
+    (32, "1"),
+    (24, "2."),
+    (16, "2"),
+    (12, "4."),
+    (8, "4"),
+    (6, "8."),
+    (4, "8"),
+    (3, "16."),
+    (2, "16"),
+    (1, "32"),
+];
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// c' is middle C, "C#" in octave 4 is cis', "Bb" in octave 2 is bes,
+fn pitch(note: Note, spelling: Spelling) -> String {
+    let name = note.name(spelling);
+    let mut text = name[..1].to_ascii_lowercase();
+    match name.chars().nth(1) {
+        Some('#') => text.push_str("is"),
+        Some('b') => text.push_str("es"),
+        _ => {}
+    }
+    let octave = note.value() as i32 / 12 - 1;
+    let mark = if octave > 3 { '\'' } else { ',' };
+    text.extend(std::iter::repeat_n(
+        mark,
+        (octave - 3).unsigned_abs() as usize,
+    ));
+    text
+}
+
+// treble unless the notes sit mostly below middle C, with the octave
+// clefs for parts far off the staff
+fn clef(notes: &[Note]) -> &'static str {
+    if notes.is_empty() {
+        return "treble";
+    }
+    let lowest = notes.iter().min().unwrap().value();
+    let highest = notes.iter().max().unwrap().value();
+    let average = notes.iter().map(|n| n.value() as usize).sum::<usize>() / notes.len();
+    if lowest >= 84 {
+        "treble^8"
+    } else if highest <= 40 {
+        "bass_8"
+    } else if average < 60 {
+        "bass"
+    } else {
+        "treble"
+    }
+}
+
+fn key(sharps: i8, is_major: bool) -> String {
+    let tonic = Key::from_signature(sharps, is_major)
+        .and_then(|k| Note::new(k.tonic()).ok())
+        .map(|n| pitch(n, key_spelling(sharps)))
+        .unwrap_or("c".to_string());
+    // pitch() marks the octave, the key only wants the name
+    let name = tonic.trim_end_matches([',', '\'']);
+    let mode = if is_major { "major" } else { "minor" };
+    format!("\\key {name} \\{mode}")
+}
+
+// notes (or a rest) lasting length, split into values tied together
+fn push_notes(
+    bar: &mut Vec<String>,
+    notes: &[Note],
+    mut length: u64,
+    tie: bool,
+    spelling: Spelling,
+) {
+    let chord = match notes {
+        [] => "r".to_string(),
+        [note] => pitch(*note, spelling),
+        _ => {
+            let pitches: Vec<String> = notes.iter().map(|n| pitch(*n, spelling)).collect();
+            format!("<{}>", pitches.join(" "))
+        }
+    };
+    let mut pieces = Vec::new();
+    while length > 0 {
+        let &(value, duration) = VALUES.iter().find(|(value, _)| *value <= length).unwrap();
+        length -= value;
+        let tied = !notes.is_empty() && (length > 0 || tie);
+        pieces.push(format!("{chord}{duration}{}", if tied { "~" } else { "" }));
+    }
+    // short rests first, so the long ones fall on the beat
+    if notes.is_empty() {
+        pieces.reverse();
+    }
+    bar.extend(pieces);
+}
+
+// one staff, with the tempo marks only when tempo is set
+fn staff(
+    text: &mut String,
+    name: &str,
+    notes: Vec<Note>,
+    voice: &[Segment],
+    measures: &[Measure],
+    tempo: bool,
+) {
+    if name.is_empty() {
+        text.push_str("    \\new Staff {\n");
+    } else {
+        let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+        text.push_str(&format!(
+            "    \\new Staff \\with {{ instrumentName = \"{name}\" }} {{\n"
+        ));
+    }
+    text.push_str(&format!("      \\clef \"{}\"\n", clef(&notes)));
+
+    let mut spelling = Spelling::Sharps;
+    for measure in measures {
+        let (start, end) = (measure.start, measure.start + measure.length);
+        if let Some((sharps, is_major)) = measure.key {
+            spelling = key_spelling(sharps);
+            text.push_str(&format!("      {}\n", key(sharps, is_major)));
+        }
+        if let Some((numerator, denominator)) = measure.time {
+            text.push_str(&format!("      \\time {numerator}/{denominator}\n"));
+        }
+        if tempo {
+            for (_, bpm) in &measure.tempos {
+                text.push_str(&format!("      \\tempo 4 = {}\n", bpm.round()));
+            }
+        }
+
+        let in_bar: Vec<&Segment> = voice
+            .iter()
+            .filter(|s| s.start < end && s.end > start)
+            .collect();
+        let mut bar = Vec::new();
+        if in_bar.iter().all(|s| s.notes.is_empty()) {
+            // a whole bar rest, as long as the bar whatever the meter
+            let g = gcd(measure.length, PER_QUARTER * 4).max(1);
+            bar.push(format!("R1*{}/{}", measure.length / g, PER_QUARTER * 4 / g));
+        } else {
+            let mut position = start;
+            for segment in in_bar {
+                let (from, to) = (segment.start.max(start), segment.end.min(end));
+                push_notes(
+                    &mut bar,
+                    &segment.notes,
+                    to - from,
+                    segment.end > end,
+                    spelling,
+                );
+                position = to;
+            }
+            push_notes(&mut bar, &[], end - position, false, spelling);
+        }
+        text.push_str(&format!("      {} |\n", bar.join(" ")));
+    }
+    text.push_str("    }\n");
+}
+
+fn score(
+    ticks_per_beat: u32,
+    conductor_tracks: &[Track],
+    staves: &[&Track],
+) -> Result<String, MidiError> {
+    let ticks_per_beat = ticks_per_beat.max(1) as u64;
+    let quantize = |tick: u64| (tick * PER_QUARTER + ticks_per_beat / 2) / ticks_per_beat;
+    let metas: Vec<(u64, MetaEvent)> = conductor(conductor_tracks, quantize);
+    let end = staves
+        .iter()
+        .flat_map(|t| t.note_spans())
+        .map(|s| quantize(s.end))
+        .max()
+        .unwrap_or(0);
+    let measures = measures(&metas, end, PER_QUARTER)?;
+
+    let mut text = String::from("\\version \"2.24.0\"\n\n\\score {\n  <<\n");
+    for (index, track) in staves.iter().enumerate() {
+        let spans = track.note_spans();
+        let notes: Vec<Note> = spans.iter().map(|s| s.note).collect();
+        let voice = segments(&spans, quantize);
+        staff(
+            &mut text,
+            track.name(),
+            notes,
+            &voice,
+            &measures,
+            index == 0,
+        );
+    }
+    text.push_str("  >>\n  \\layout { }\n}\n");
+    Ok(text)
+}
+
+impl Track {
+    // The track as a LilyPond score of one staff, with the time and key
+    // signatures and tempo found in the track itself
+    pub fn to_lilypond(&self, ticks_per_beat: u32) -> Result<String, MidiError> {
+        score(ticks_per_beat, std::slice::from_ref(self), &[self])
+    }
+}
+
+impl Smf {
+    // The chosen tracks as staves of one LilyPond score, every track with
+    // notes when none are chosen. The signatures and tempo can be on any
+    // track of the song
+    pub fn to_lilypond(&self, tracks: &[usize]) -> Result<String, MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)?;
+        let staves: Vec<&Track> = if tracks.is_empty() {
+            self.tracks()
+                .iter()
+                .filter(|t| !t.note_spans().is_empty())
+                .collect()
+        } else {
+            tracks
+                .iter()
+                .map(|&i| self.tracks().get(i).ok_or(MidiError::NoSuchTrack(i)))
+                .collect::<Result<_, _>>()?
+        };
+        score(ticks_per_beat, self.tracks(), &staves)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{note, track_of};
+
+    #[test]
+    fn staff_with_ties_chords_and_clef() {
+        // 3/4 in F with a tempo, a dotted quarter Bb, a chord over the bar
+        // line and an odd length at the end
+        let abc = "X:1\nT:Tune\nM:3/4\nL:1/8\nQ:1/4=90\nK:F\nB3 c [FAc]2- | [FAc]2 d5 e/ |]\n";
+        let smf = Smf::from_abc(abc).unwrap();
+        let ly = smf.to_lilypond(&[]).unwrap();
+
+        assert!(ly.starts_with("\\version \"2.24.0\""));
+        assert!(ly.contains("\\new Staff \\with { instrumentName = \"Tune\" } {"));
+        assert!(ly.contains("\\clef \"treble\""));
+        assert!(ly.contains("\\key f \\major"));
+        assert!(ly.contains("\\time 3/4"));
+        assert!(ly.contains("\\tempo 4 = 90"));
+        assert!(ly.contains("      bes'4. c''8 <f' a' c''>4~ |\n"));
+        assert!(ly.contains("      <f' a' c''>4 d''2~ |\n      d''8 e''16 r16 r2 |\n"));
+        assert_eq!(
+            smf.to_lilypond(&[3]).unwrap_err(),
+            MidiError::NoSuchTrack(3)
+        );
+    }
+
+    #[test]
+    fn pitches_clefs_and_rests() {
+        let note = |v| Note::new(v).unwrap();
+        assert_eq!(pitch(note(60), Spelling::Sharps), "c'");
+        assert_eq!(pitch(note(61), Spelling::Flats), "des'");
+        assert_eq!(pitch(note(46), Spelling::Flats), "bes,");
+        assert_eq!(pitch(note(54), Spelling::Sharps), "fis");
+        assert_eq!(clef(&[note(40), note(55)]), "bass");
+        assert_eq!(clef(&[note(88), note(96)]), "treble^8");
+        assert_eq!(key(-3, false), "\\key c \\minor");
+
+        let track = Smf::from_abc("X:1\nM:2/4\nL:1/4\nK:C\nz2 | C2 |]\n").unwrap();
+        let ly = track.tracks()[0].to_lilypond(480).unwrap();
+        assert!(ly.contains("      R1*1/2 |\n      c'2 |\n"));
+        assert!(ly.contains("\\clef \"treble\""));
+    }
+
+    #[test]
+    fn too_many_bars() {
+        // a whole note past 10000 bars of 4/4
+        let track = track_of(&[note(0, 480 * 4 * 10_001, 60)], Vec::new());
+        assert_eq!(track.to_lilypond(480), Err(MidiError::TooManyMeasures));
+        let track = track_of(&[note(0, 480 * 4 * 10_000, 60)], Vec::new());
+        assert!(track.to_lilypond(480).is_ok());
+    }
+}
@@ -0,0 +1,7 @@
+// MusicXML scores, see https://www.w3.org/2021/06/musicxml40/
+mod read;
+mod write;
+mod xml;
+
+// divisions of a quarter note, fine enough for 64th triplets
+const DIVISIONS: u64 = 24;
@@ -0,0 +1,692 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Program, Velocity};
+use crate::error::MidiError;
+use crate::header::{Header, MidiFormat};
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::meta::MetaEvent;
+use crate::smf::Smf;
+use crate::span::sort_releases_first;
+use crate::track::{EventType, Track, TrackEvent, Vql};
+use std::collections::HashMap;
+
+use super::xml::{self, Element};
+
+const TICKS_PER_BEAT: u32 = 480;
+// what <sound dynamics="100"/> is, the dynamics attributes are percentages of it
+const FORTE_VELOCITY: f64 = 90.0;
+// the most times="n" a backward repeat can ask for
+const MAX_REPEATS: u32 = 100;
+
+const STEPS: [(&str, i32); 7] = [
+    ("C", 0),
+    ("D", 2),
+    ("E", 4),
+    ("F", 5),
+    ("G", 7),
+    ("A", 9),
+    ("B", 11),
+];
+
+fn percent_velocity(percent: f64) -> u8 {
+    (percent * FORTE_VELOCITY / 100.0).round().clamp(1.0, 127.0) as u8
+}
+
+// what the part list says about a part
+#[derive(Debug, Clone, Default)]
+struct PartInfo {
+    name: String,
+    channel: Option<u8>,
+    program: Option<u8>,
+}
+
+fn part_list(root: &Element) -> Result<HashMap<String, PartInfo>, MidiError> {
+    let mut parts = HashMap::new();
+    let Some(list) = root.child("part-list") else {
+        return Ok(parts);
+    };
+    for part in list.children_named("score-part") {
+        let id = part
+            .attribute("id")
+            .ok_or_else(|| part.error("<score-part> without an id"))?;
+        let mut info = PartInfo {
+            name: part.text_of("part-name").unwrap_or("").to_string(),
+            ..PartInfo::default()
+        };
+        if let Some(midi) = part.child("midi-instrument") {
+            let channel: Option<u8> = midi.number_of("midi-channel")?;
+            let program: Option<u8> = midi.number_of("midi-program")?;
+            info.channel = channel.filter(|c| (1..=16).contains(c)).map(|c| c - 1);
+            info.program = program.filter(|p| (1..=128).contains(p)).map(|p| p - 1);
+        }
+        parts.insert(id.to_string(), info);
+    }
+    Ok(parts)
+}
+
+// Every part with the elements holding its music, one per measure. In a
+// partwise score those are the <measure>s of the <part>, in a timewise
+// one the <part>s inside each <measure>
+fn parts(root: &Element) -> Result<Vec<(String, Vec<&Element>)>, MidiError> {
+    let mut parts: Vec<(String, Vec<&Element>)> = Vec::new();
+    match root.name.as_str() {
+        "score-partwise" => {
+            for part in root.children_named("part") {
+                let id = part.attribute("id").unwrap_or("").to_string();
+                parts.push((id, part.children_named("measure").collect()));
+            }
+        }
+        "score-timewise" => {
+            for measure in root.children_named("measure") {
+                for part in measure.children_named("part") {
+                    let id = part.attribute("id").unwrap_or("");
+                    match parts.iter_mut().find(|(p, _)| p == id) {
+                        Some((_, measures)) => measures.push(part),
+                        None => parts.push((id.to_string(), vec![part])),
+                    }
+                }
+            }
+        }
+        other => {
+            return Err(root.error(format!(
+                "expected <score-partwise> or <score-timewise>, found <{other}>"
+            )))
+        }
+    }
+    if parts.is_empty() {
+        return Err(root.error("the score has no parts"));
+    }
+    Ok(parts)
+}
+
+// The measures in the order they are played, with repeats and 1st/2nd
+// endings unrolled. A backward repeat goes back to the last forward one,
+// or to the end of the previous repeat, times="n" plays it n times
+fn playback_order(measures: &[&Element]) -> Result<Vec<usize>, MidiError> {
+    let count = measures.len();
+    let mut forward = vec![false; count];
+    let mut backward: Vec<Option<u32>> = vec![None; count];
+    let mut endings: Vec<Option<Vec<u32>>> = vec![None; count];
+
+    let mut active: Option<Vec<u32>> = None;
+    for (i, measure) in measures.iter().enumerate() {
+        let barlines: Vec<&Element> = measure.children_named("barline").collect();
+        for barline in &barlines {
+            if let Some(ending) = barline.child("ending") {
+                if ending.attribute("type") == Some("start") {
+                    let numbers = ending
+                        .attribute("number")
+                        .unwrap_or("1")
+                        .split([',', ' '])
+                        .filter_map(|n| n.trim().parse().ok())
+                        .collect();
+                    active = Some(numbers);
+                }
+            }
+            if let Some(repeat) = barline.child("repeat") {
+                match repeat.attribute("direction") {
+                    Some("forward") => forward[i] = true,
+                    Some("backward") => {
+                        let times = match repeat.attribute("times") {
+                            Some(times) => times
+                                .trim()
+                                .parse()
+                                .ok()
+                                .filter(|t| (1..=MAX_REPEATS).contains(t))
+                                .ok_or_else(|| {
+                                    repeat.error(format!(
+                                        "times must be a number from 1 to {MAX_REPEATS}"
+                                    ))
+                                })?,
+                            None => 2,
+                        };
+                        backward[i] = Some(times);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        endings[i] = active.clone();
+        if barlines.iter().any(|b| {
+            b.child("ending")
+                .is_some_and(|e| e.attribute("type") != Some("start"))
+        }) {
+            active = None;
+        }
+    }
+
+    let mut order = Vec::new();
+    let mut repeats = vec![0; count];
+    let (mut i, mut start, mut pass) = (0, 0, 1);
+    let mut jumped = false;
+    let mut section_over = false;
+    while i < count {
+        if forward[i] && !jumped {
+            start = i;
+            pass = 1;
+            section_over = false;
+        }
+        jumped = false;
+        match &endings[i] {
+            Some(numbers) if !numbers.contains(&pass) => {
+                i += 1;
+                continue;
+            }
+            Some(_) => section_over = true,
+            // the first measure after a repeat or the last ending
+            None if section_over => {
+                start = i;
+                pass = 1;
+                section_over = false;
+            }
+            None => {}
+        }
+        order.push(i);
+        if let Some(times) = backward[i] {
+            if repeats[i] + 1 < times {
+                repeats[i] += 1;
+                pass += 1;
+                i = start;
+                jumped = true;
+                section_over = false;
+                continue;
+            }
+            section_over = true;
+        }
+        i += 1;
+    }
+    Ok(order)
+}
+
+// One part played through, in ticks
+struct Reader {
+    ticks_per_division: f64,
+    cursor: f64,
+    // where the last note without <chord/> started
+    chord_start: f64,
+    velocity: u8,
+    // (start, end, note, velocity)
+    notes: Vec<(f64, f64, Note, u8)>,
+    // tied notes waiting for their continuation, by note
+    ties: HashMap<Note, usize>,
+    metas: Vec<(f64, MetaEvent)>,
+}
+
+impl Reader {
+    fn duration(&self, element: &Element) -> Result<f64, MidiError> {
+        let divisions: f64 = element.number_of("duration")?.unwrap_or(0.0);
+        if !divisions.is_finite() || divisions < 0.0 {
+            return Err(element.error("a duration has to be a number from 0 up"));
+        }
+        Ok(divisions * self.ticks_per_division)
+    }
+
+    // a meta event unless the last one of its kind already says the same
+    fn meta(&mut self, meta: MetaEvent) {
+        let same_kind = |m: &MetaEvent| std::mem::discriminant(m) == std::mem::discriminant(&meta);
+        let last = self.metas.iter().rev().find(|(_, m)| same_kind(m));
+        if last.is_none_or(|(_, m)| *m != meta) {
+            self.metas.push((self.cursor, meta));
+        }
+    }
+
+    fn sound(&mut self, sound: &Element) -> Result<(), MidiError> {
+        if let Some(tempo) = sound.attribute("tempo") {
+            let bpm: f64 = tempo
+                .trim()
+                .parse()
+                .map_err(|_| sound.error("expected a number in tempo"))?;
+            if bpm > 0.0 {
+                let tempo = (60_000_000.0 / bpm).round().min(0xFF_FFFF as f64) as u32;
+                self.meta(MetaEvent::SetTempo(tempo));
+            }
+        }
+        if let Some(dynamics) = sound.attribute("dynamics") {
+            let percent: f64 = dynamics
+                .trim()
+                .parse()
+                .map_err(|_| sound.error("expected a number in dynamics"))?;
+            self.velocity = percent_velocity(percent);
+        }
+        Ok(())
+    }
+
+    fn attributes(&mut self, attributes: &Element) -> Result<(), MidiError> {
+        if let Some(divisions) = attributes.number_of::<f64>("divisions")? {
+            if divisions <= 0.0 {
+                return Err(attributes.error("divisions must be positive"));
+            }
+            self.ticks_per_division = TICKS_PER_BEAT as f64 / divisions;
+        }
+        if let Some(key) = attributes.child("key") {
+            if let Some(sharps) = key.number_of::<i8>("fifths")? {
+                self.meta(MetaEvent::KeySignature {
+                    sharps: sharps.clamp(-7, 7),
+                    is_major: key.text_of("mode") != Some("minor"),
+                });
+            }
+        }
+        if let Some(time) = attributes.child("time") {
+            // beats can be added up like 3+2
+            let beats: Option<u32> = time
+                .text_of("beats")
+                .and_then(|b| b.split('+').map(|n| n.trim().parse::<u32>().ok()).sum());
+            let beat_type: Option<u8> = time.number_of("beat-type")?;
+            if let (Some(numerator), Some(denominator)) = (beats, beat_type) {
+                if (1..=255).contains(&numerator) && denominator.is_power_of_two() {
+                    self.meta(MetaEvent::TimeSignature {
+                        numerator: numerator as u8,
+                        denominator,
+                        clocks_per_tick: 24,
+                        thirty_seconds_per_24_clocks: 8,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn direction(&mut self, direction: &Element) -> Result<(), MidiError> {
+        for kind in direction.children_named("direction-type") {
+            if let Some(dynamics) = kind.child("dynamics") {
+                if let Some(velocity) = dynamics
+                    .children
+                    .iter()
+                    .find_map(|mark| Velocity::from_dynamic(&mark.name))
+                {
+                    self.velocity = velocity.value();
+                }
+            }
+        }
+        // an explicit sound wins over the mark
+        if let Some(sound) = direction.child("sound") {
+            self.sound(sound)?;
+        }
+        Ok(())
+    }
+
+    fn pitch(&self, note: &Element) -> Result<Note, MidiError> {
+        let (pitch, step, octave) = match (note.child("pitch"), note.child("unpitched")) {
+            (Some(pitch), _) => (pitch, "step", "octave"),
+            (None, Some(unpitched)) => (unpitched, "display-step", "display-octave"),
+            (None, None) => return Err(note.error("a <note> without a pitch or rest")),
+        };
+        let step = pitch
+            .text_of(step)
+            .and_then(|s| STEPS.iter().find(|(name, _)| *name == s))
+            .ok_or_else(|| pitch.error("expected a step from A to G"))?
+            .1;
+        let octave: i32 = pitch
+            .number_of(octave)?
+            .ok_or_else(|| pitch.error("a pitch without an octave"))?;
+        let alter: f64 = pitch.number_of("alter")?.unwrap_or(0.0);
+        let value = (octave + 1) * 12 + step + alter.round() as i32;
+        u8::try_from(value)
+            .ok()
+            .and_then(|v| Note::new(v).ok())
+            .ok_or(MidiError::NoteOutOfRange(value))
+    }
+
+    fn note(&mut self, note: &Element) -> Result<(), MidiError> {
+        if note.has("grace") {
+            return Ok(());
+        }
+        let duration = self.duration(note)?;
+        let start = if note.has("chord") {
+            self.chord_start
+        } else {
+            self.chord_start = self.cursor;
+            self.cursor += duration;
+            self.chord_start
+        };
+        if note.has("rest") || note.has("cue") {
+            return Ok(());
+        }
+
+        let pitch = self.pitch(note)?;
+        let velocity = match note.attribute("dynamics") {
+            Some(percent) => percent_velocity(
+                percent
+                    .trim()
+                    .parse()
+                    .map_err(|_| note.error("expected a number in dynamics"))?,
+            ),
+            None => self.velocity,
+        };
+        let tied = |kind: &str| {
+            note.children_named("tie")
+                .chain(
+                    note.children_named("notations")
+                        .flat_map(|n| n.children_named("tied")),
+                )
+                .any(|t| t.attribute("type") == Some(kind))
+        };
+
+        let end = start + duration;
+        let index = match self.ties.get(&pitch) {
+            Some(&i) if tied("stop") && (self.notes[i].1 - start).abs() < 1.0 => {
+                self.notes[i].1 = end;
+                i
+            }
+            _ => {
+                self.notes.push((start, end, pitch, velocity));
+                self.notes.len() - 1
+            }
+        };
+        if tied("start") {
+            self.ties.insert(pitch, index);
+        } else {
+            self.ties.remove(&pitch);
+        }
+        Ok(())
+    }
+
+    fn measure(&mut self, measure: &Element) -> Result<(), MidiError> {
+        let mut furthest = self.cursor;
+        for child in &measure.children {
+            match child.name.as_str() {
+                "attributes" => self.attributes(child)?,
+                "direction" => self.direction(child)?,
+                "sound" => self.sound(child)?,
+                "note" => self.note(child)?,
+                "backup" => self.cursor = (self.cursor - self.duration(child)?).max(0.0),
+                "forward" => self.cursor += self.duration(child)?,
+                _ => {}
+            }
+            furthest = furthest.max(self.cursor);
+        }
+        // the next measure starts after the longest voice
+        self.cursor = furthest;
+        Ok(())
+    }
+}
+
+impl Smf {
+    // A MusicXML score, partwise or timewise, with one track per part at 480
+    // ticks per beat. Repeats and endings are played out, tied notes become
+    // one, dynamics set the velocity and the tempo comes from <sound tempo>.
+    // The time and key signatures of the first part and the tempo changes
+    // of all parts go on the first track
+    pub fn from_musicxml(text: &str) -> Result<Smf, MidiError> {
+        let root = xml::parse(text)?;
+        let info = part_list(&root)?;
+        let parts = parts(&root)?;
+
+        let mut tracks = Vec::new();
+        let mut conductor: Vec<(u64, MetaEvent)> = Vec::new();
+        for (index, (id, measures)) in parts.iter().enumerate() {
+            let mut reader = Reader {
+                ticks_per_division: TICKS_PER_BEAT as f64,
+                cursor: 0.0,
+                chord_start: 0.0,
+                velocity: percent_velocity(100.0),
+                notes: Vec::new(),
+                ties: HashMap::new(),
+                metas: Vec::new(),
+            };
+            for i in playback_order(measures)? {
+                reader.measure(measures[i])?;
+            }
+
+            for (tick, meta) in &reader.metas {
+                let tick = tick.round() as u64;
+                let wanted = index == 0 || matches!(meta, MetaEvent::SetTempo(_));
+                if wanted && !conductor.contains(&(tick, meta.clone())) {
+                    conductor.push((tick, meta.clone()));
+                }
+            }
+
+            let part = info.get(id).cloned().unwrap_or_default();
+            // the part's own channel, else one per part stepping over drums
+            let channel = Channel::new(part.channel.unwrap_or({
+                let c = index as u8 % 15;
+                if c >= 9 {
+                    c + 1
+                } else {
+                    c
+                }
+            }))?;
+            let mut events: Vec<(u64, TrackEvent)> = Vec::new();
+            if let Some(program) = part.program {
+                let message = ChannelMessage::ProgramChange {
+                    program: Program::new(program)?,
+                };
+                events.push((
+                    0,
+                    TrackEvent::new(
+                        Vql::zero(),
+                        EventType::Midi(MidiMessage::Channel { channel, message }),
+                    ),
+                ));
+            }
+            for &(start, end, note, velocity) in &reader.notes {
+                let velocity = Velocity::new(velocity)?;
+                events.push((
+                    start.round() as u64,
+                    TrackEvent::note_on(Vql::zero(), channel, note, velocity),
+                ));
+                events.push((
+                    end.round() as u64,
+                    TrackEvent::note_off(Vql::zero(), channel, note, Velocity::new(0)?),
+                ));
+            }
+            events.push((reader.cursor.round() as u64, TrackEvent::end_track()));
+            tracks.push((part.name, events));
+        }
+
+        let count = tracks.len();
+        let mut built = Vec::new();
+        for (index, (name, mut events)) in tracks.into_iter().enumerate() {
+            if index == 0 {
+                let conductor = conductor
+                    .iter()
+                    .map(|(t, m)| (*t, TrackEvent::meta_event(m.clone())));
+                events.splice(0..0, conductor.collect::<Vec<_>>());
+            }
+            sort_releases_first(&mut events);
+            let track = Track::from_absolute_events(events)?;
+            built.push(if name.is_empty() {
+                track
+            } else {
+                track.with_name(name)
+            });
+        }
+
+        let format = if count == 1 {
+            MidiFormat::SingleTrack
+        } else {
+            MidiFormat::MultipleTrack
+        };
+        let header = Header::new(format, count as u16, TICKS_PER_BEAT as i16)?;
+        Ok(Smf::new(header, built))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spans(smf: &Smf, track: usize) -> Vec<(u64, u64, u8, u8)> {
+        smf.tracks()[track]
+            .note_spans()
+            .iter()
+            .map(|s| (s.start, s.end, s.note.value(), s.velocity.value()))
+            .collect()
+    }
+
+    fn metas(smf: &Smf) -> Vec<(u64, MetaEvent)> {
+        smf.tracks()[0]
+            .absolute_events()
+            .filter_map(|(tick, e)| match &e.event {
+                EventType::Meta(MetaEvent::EndOfTrack | MetaEvent::TrackName(_)) => None,
+                EventType::Meta(meta) => Some((tick, meta.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    const PARTWISE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<score-partwise version="4.0">
+  <part-list>
+    <score-part id="P1">
+      <part-name>Flute</part-name>
+      <midi-instrument id="P1-I1">
+        <midi-channel>2</midi-channel>
+        <midi-program>74</midi-program>
+      </midi-instrument>
+    </score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes>
+        <divisions>2</divisions>
+        <key><fifths>2</fifths><mode>major</mode></key>
+        <time><beats>2</beats><beat-type>4</beat-type></time>
+      </attributes>
+      <barline location="left"><repeat direction="forward"/></barline>
+      <direction>
+        <direction-type><dynamics><f/></dynamics></direction-type>
+        <sound tempo="90"/>
+      </direction>
+      <note><pitch><step>D</step><octave>4</octave></pitch><duration>2</duration></note>
+      <note>
+        <pitch><step>F</step><alter>1</alter><octave>4</octave></pitch>
+        <duration>2</duration><tie type="start"/>
+      </note>
+    </measure>
+    <measure number="2">
+      <barline location="left"><ending number="1" type="start"/></barline>
+      <note>
+        <pitch><step>F</step><alter>1</alter><octave>4</octave></pitch>
+        <duration>2</duration><tie type="stop"/>
+      </note>
+      <note><rest/><duration>2</duration></note>
+      <barline location="right">
+        <ending number="1" type="stop"/>
+        <repeat direction="backward"/>
+      </barline>
+    </measure>
+    <measure number="3">
+      <barline location="left"><ending number="2" type="start"/></barline>
+      <note dynamics="50"><pitch><step>A</step><octave>4</octave></pitch><duration>4</duration></note>
+      <note><chord/><pitch><step>D</step><octave>5</octave></pitch><duration>4</duration></note>
+      <barline location="right"><ending number="2" type="discontinue"/></barline>
+    </measure>
+  </part>
+</score-partwise>
+"#;
+
+    #[test]
+    fn partwise_with_repeats_ties_and_dynamics() {
+        let smf = Smf::from_musicxml(PARTWISE).unwrap();
+        assert_eq!(smf.header().ticks_per_beat(), Some(480));
+        assert_eq!(smf.tracks()[0].name(), "Flute");
+        assert_eq!(
+            spans(&smf, 0),
+            vec![
+                (0, 480, 62, 96),
+                (480, 1440, 66, 96),
+                (1920, 2400, 62, 96),
+                (2400, 2880, 66, 96),
+                (2880, 3840, 69, 45),
+                (2880, 3840, 74, 96),
+            ]
+        );
+        assert_eq!(smf.note_spans()[0].channel.value(), 1);
+        // played twice, but written once
+        assert_eq!(
+            metas(&smf),
+            vec![
+                (
+                    0,
+                    MetaEvent::KeySignature {
+                        sharps: 2,
+                        is_major: true
+                    }
+                ),
+                (
+                    0,
+                    MetaEvent::TimeSignature {
+                        numerator: 2,
+                        denominator: 4,
+                        clocks_per_tick: 24,
+                        thirty_seconds_per_24_clocks: 8
+                    }
+                ),
+                (0, MetaEvent::SetTempo(666_667)),
+            ]
+        );
+
+        // the backward repeat is on line 39
+        let endless = PARTWISE.replace(
+            r#"<repeat direction="backward"/>"#,
+            r#"<repeat direction="backward" times="4000000000"/>"#,
+        );
+        assert!(matches!(
+            Smf::from_musicxml(&endless),
+            Err(MidiError::Parse { line: 39, .. })
+        ));
+        let twice = PARTWISE.replace(
+            r#"<repeat direction="backward"/>"#,
+            r#"<repeat direction="backward" times="2"/>"#,
+        );
+        assert_eq!(
+            Smf::from_musicxml(&twice).unwrap().note_spans(),
+            smf.note_spans()
+        );
+
+        // the rest is on line 36
+        for bad in ["-2", "NaN", "inf"] {
+            let rest = PARTWISE.replace(
+                "<note><rest/><duration>2</duration></note>",
+                &format!("<note><rest/><duration>{bad}</duration></note>"),
+            );
+            assert!(matches!(
+                Smf::from_musicxml(&rest),
+                Err(MidiError::Parse { line: 36, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn timewise_and_round_trip() {
+        let timewise = r#"<score-timewise>
+  <part-list>
+    <score-part id="A"><part-name>Left</part-name></score-part>
+    <score-part id="B"><part-name>Right</part-name></score-part>
+  </part-list>
+  <measure number="1">
+    <part id="A"><attributes><divisions>1</divisions></attributes>
+      <note><pitch><step>C</step><octave>3</octave></pitch><duration>4</duration></note>
+    </part>
+    <part id="B"><attributes><divisions>4</divisions></attributes>
+      <note><pitch><step>E</step><octave>5</octave></pitch><duration>8</duration></note>
+      <note><pitch><step>G</step><octave>5</octave></pitch><duration>8</duration></note>
+    </part>
+  </measure>
+</score-timewise>"#;
+        let smf = Smf::from_musicxml(timewise).unwrap();
+        assert_eq!(smf.tracks().len(), 2);
+        assert_eq!(smf.tracks()[1].name(), "Right");
+        assert_eq!(spans(&smf, 0), vec![(0, 1920, 48, 90)]);
+        assert_eq!(spans(&smf, 1), vec![(0, 960, 76, 90), (960, 1920, 79, 90)]);
+
+        let abc = Smf::from_abc("X:1\nM:6/8\nL:1/8\nK:Bb\nB3 (3cde f2- | f2 [DF]4 |]\n").unwrap();
+        let back = Smf::from_musicxml(&abc.to_musicxml().unwrap()).unwrap();
+        let pitches = |smf: &Smf| -> Vec<(u64, u64, u8)> {
+            smf.note_spans()
+                .iter()
+                .map(|s| (s.start, s.end, s.note.value()))
+                .collect()
+        };
+        assert_eq!(pitches(&back), pitches(&abc));
+
+        assert!(matches!(
+            Smf::from_musicxml("<score/>"),
+            Err(MidiError::Parse {
+                line: 1,
+                column: 1,
+                ..
+            })
+        ));
+    }
+}
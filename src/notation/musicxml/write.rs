@@ -0,0 +1,522 @@
+use crate::error::MidiError;
+use crate::key::Spelling;
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::notation::score::{conductor, key_spelling, measures, segments, Measure};
+use crate::smf::Smf;
+use crate::track::{EventType, Track};
+use crate::Note;
+
+use super::DIVISIONS;
+
+// (length in divisions, note type, dotted, triplet), longest first. A
+// length is written greedily with these, so any number of divisions
+// comes out as tied notes
+const NOTE_VALUES: [(u64, &str, bool, bool); 18] = [
+    (144, "whole", true, false),
+    (96, "whole", false, false),
+    (72, "half", true, false),
+    (64, "whole", false, true),
+    (48, "half", false, false),
+    (36, "quarter", true, false),
+    (32, "half", false, true),
+    (24, "quarter", false, false),
+    (18, "eighth", true, false),
+    (16, "quarter", false, true),
+    (12, "eighth", false, false),
+    (9, "16th", true, false),
+    (8, "eighth", false, true),
+    (6, "16th", false, false),
+    (4, "16th", false, true),
+    (3, "32nd", false, false),
+    (2, "32nd", false, true),
+    (1, "64th", false, true),
+];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Indented XML, one element per line
+struct Xml {
+    text: String,
+    depth: usize,
+}
+
+impl Xml {
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.text.push_str("  ");
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    fn open(&mut self, tag: &str) {
+        self.line(&format!("<{tag}>"));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.line(&format!("</{tag}>"));
+    }
+
+    fn leaf(&mut self, tag: &str, value: impl std::fmt::Display) {
+        let name = tag.split(' ').next().unwrap_or(tag);
+        self.line(&format!("<{tag}>{value}</{name}>"));
+    }
+}
+
+fn pitch(xml: &mut Xml, note: Note, spelling: Spelling) {
+    let name = note.name(spelling);
+    let alter = match name.chars().nth(1) {
+        Some('#') => 1,
+        Some('b') => -1,
+        _ => 0,
+    };
+    xml.open("pitch");
+    xml.leaf("step", &name[..1]);
+    if alter != 0 {
+        xml.leaf("alter", alter);
+    }
+    xml.leaf("octave", note.value() as i32 / 12 - 1);
+    xml.close("pitch");
+}
+
+// One written note value of a chord or rest, with the ties it needs
+struct Piece<'a> {
+    notes: &'a [Note],
+    value: u64,
+    kind: &'static str,
+    dotted: bool,
+    triplet: bool,
+    tie_stop: bool,
+    tie_start: bool,
+}
+
+// notes (or a rest) for length divisions, tied to what comes before
+// and after as asked
+fn split<'a>(
+    pieces: &mut Vec<Piece<'a>>,
+    notes: &'a [Note],
+    mut length: u64,
+    tied_before: bool,
+    tied_after: bool,
+) {
+    let mut first = true;
+    while length > 0 {
+        let &(value, kind, dotted, triplet) = NOTE_VALUES
+            .iter()
+            .find(|(value, ..)| *value <= length)
+            .unwrap();
+        length -= value;
+        pieces.push(Piece {
+            notes,
+            value,
+            kind,
+            dotted,
+            triplet,
+            tie_stop: !notes.is_empty() && (tied_before || !first),
+            tie_start: !notes.is_empty() && (tied_after || length > 0),
+        });
+        first = false;
+    }
+}
+
+// The pieces of a measure. Triplets are bracketed from the first one until
+// they fill a length without triplets (a multiple of 3 divisions), or until
+// something else comes, so every bracket closes within the measure
+fn write_notes(xml: &mut Xml, pieces: &[Piece], spelling: Spelling) {
+    let mut in_tuplet = 0;
+    for (i, piece) in pieces.iter().enumerate() {
+        let mut tuplet = Vec::new();
+        if piece.triplet {
+            if in_tuplet == 0 {
+                tuplet.push("start");
+            }
+            in_tuplet += piece.value;
+            let next_triplet = pieces.get(i + 1).is_some_and(|p| p.triplet);
+            if in_tuplet % 3 == 0 || !next_triplet {
+                tuplet.push("stop");
+                in_tuplet = 0;
+            }
+        }
+        let ties: Vec<&str> = [(piece.tie_stop, "stop"), (piece.tie_start, "start")]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, kind)| *kind)
+            .collect();
+
+        let chord: &[Option<Note>] = &if piece.notes.is_empty() {
+            vec![None]
+        } else {
+            piece.notes.iter().map(|n| Some(*n)).collect()
+        };
+        for (index, note) in chord.iter().enumerate() {
+            xml.open("note");
+            if index > 0 {
+                xml.line("<chord/>");
+            }
+            match note {
+                Some(note) => pitch(xml, *note, spelling),
+                None => xml.line("<rest/>"),
+            }
+            xml.leaf("duration", piece.value);
+            for tie in &ties {
+                xml.line(&format!("<tie type=\"{tie}\"/>"));
+            }
+            xml.leaf("voice", 1);
+            xml.leaf("type", piece.kind);
+            if piece.dotted {
+                xml.line("<dot/>");
+            }
+            if piece.triplet {
+                xml.open("time-modification");
+                xml.leaf("actual-notes", 3);
+                xml.leaf("normal-notes", 2);
+                xml.close("time-modification");
+            }
+            // the bracket goes on the first note of a chord only
+            let tuplet: &[&str] = if index == 0 { &tuplet } else { &[] };
+            if !ties.is_empty() || !tuplet.is_empty() {
+                xml.open("notations");
+                for tie in &ties {
+                    xml.line(&format!("<tied type=\"{tie}\"/>"));
+                }
+                for kind in tuplet {
+                    xml.line(&format!("<tuplet type=\"{kind}\"/>"));
+                }
+                xml.close("notations");
+            }
+            xml.close("note");
+        }
+    }
+}
+
+fn attributes(xml: &mut Xml, measure: &Measure, first: bool, bass: bool) {
+    if !first && measure.time.is_none() && measure.key.is_none() {
+        return;
+    }
+    xml.open("attributes");
+    if first {
+        xml.leaf("divisions", DIVISIONS);
+    }
+    if let Some((sharps, is_major)) = measure.key {
+        xml.open("key");
+        xml.leaf("fifths", sharps);
+        xml.leaf("mode", if is_major { "major" } else { "minor" });
+        xml.close("key");
+    }
+    if let Some((numerator, denominator)) = measure.time {
+        xml.open("time");
+        xml.leaf("beats", numerator);
+        xml.leaf("beat-type", denominator);
+        xml.close("time");
+    }
+    if first {
+        xml.open("clef");
+        xml.leaf("sign", if bass { "F" } else { "G" });
+        xml.leaf("line", if bass { 4 } else { 2 });
+        xml.close("clef");
+    }
+    xml.close("attributes");
+}
+
+fn tempo(xml: &mut Xml, offset: u64, bpm: f64) {
+    xml.line("<direction placement=\"above\">");
+    xml.depth += 1;
+    xml.open("direction-type");
+    xml.open("metronome");
+    xml.leaf("beat-unit", "quarter");
+    xml.leaf("per-minute", bpm);
+    xml.close("metronome");
+    xml.close("direction-type");
+    if offset > 0 {
+        xml.leaf("offset", offset);
+    }
+    xml.line(&format!("<sound tempo=\"{bpm}\"/>"));
+    xml.close("direction");
+}
+
+fn program(track: &Track) -> Option<(u8, u8)> {
+    track.events().iter().find_map(|e| match &e.event {
+        EventType::Midi(MidiMessage::Channel {
+            channel,
+            message: ChannelMessage::ProgramChange { program },
+        }) => Some((channel.value(), program.value())),
+        _ => None,
+    })
+}
+
+impl Smf {
+    // The song as a partwise MusicXML score. Every track with notes is a
+    // part named after its TrackName, note times are quantized to 1/24 of
+    // a quarter and written as one voice per part: notes starting together
+    // are a chord and a note is cut short where the next one starts. The
+    // tempo, time and key signatures can be on any track
+    pub fn to_musicxml(&self) -> Result<String, MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)? as u64;
+        let quantize = |tick: u64| (tick * DIVISIONS + ticks_per_beat / 2) / ticks_per_beat;
+
+        let metas = conductor(self.tracks(), quantize);
+
+        let mut parts: Vec<&Track> = self
+            .tracks()
+            .iter()
+            .filter(|t| !t.note_spans().is_empty())
+            .collect();
+        if parts.is_empty() {
+            parts = self.tracks().iter().collect();
+        }
+        let end = parts
+            .iter()
+            .flat_map(|t| t.note_spans())
+            .map(|s| quantize(s.end))
+            .max()
+            .unwrap_or(0);
+        let measures = measures(&metas, end, DIVISIONS)?;
+
+        let mut xml = Xml {
+            text: String::new(),
+            depth: 0,
+        };
+        xml.line("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>");
+        xml.line("<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \"http://www.musicxml.org/dtds/partwise.dtd\">");
+        xml.line("<score-partwise version=\"4.0\">");
+        xml.depth += 1;
+
+        xml.open("part-list");
+        for (index, track) in parts.iter().enumerate() {
+            let id = format!("P{}", index + 1);
+            let name = if track.name().is_empty() {
+                format!("Track {}", index + 1)
+            } else {
+                escape(track.name())
+            };
+            xml.line(&format!("<score-part id=\"{id}\">"));
+            xml.depth += 1;
+            xml.leaf("part-name", &name);
+            if let Some((channel, program)) = program(track) {
+                xml.line(&format!("<score-instrument id=\"{id}-I1\">"));
+                xml.depth += 1;
+                xml.leaf("instrument-name", &name);
+                xml.close("score-instrument");
+                xml.line(&format!("<midi-instrument id=\"{id}-I1\">"));
+                xml.depth += 1;
+                xml.leaf("midi-channel", channel + 1);
+                xml.leaf("midi-program", program + 1);
+                xml.close("midi-instrument");
+            }
+            xml.close("score-part");
+        }
+        xml.close("part-list");
+
+        for (index, track) in parts.iter().enumerate() {
+            let spans = track.note_spans();
+            let bass = !spans.is_empty()
+                && spans.iter().map(|s| s.note.value() as usize).sum::<usize>() / spans.len() < 60;
+            let voice = segments(&spans, quantize);
+            let mut spelling = Spelling::Sharps;
+
+            xml.line(&format!("<part id=\"P{}\">", index + 1));
+            xml.depth += 1;
+            for (number, measure) in measures.iter().enumerate() {
+                let (start, end) = (measure.start, measure.start + measure.length);
+                xml.line(&format!("<measure number=\"{}\">", number + 1));
+                xml.depth += 1;
+                if let Some((sharps, _)) = measure.key {
+                    spelling = key_spelling(sharps);
+                }
+                attributes(&mut xml, measure, number == 0, bass);
+                if index == 0 {
+                    for &(offset, bpm) in &measure.tempos {
+                        tempo(&mut xml, offset, bpm);
+                    }
+                }
+
+                let mut pieces = Vec::new();
+                let mut position = start;
+                for segment in voice.iter().filter(|s| s.start < end && s.end > start) {
+                    let (from, to) = (segment.start.max(start), segment.end.min(end));
+                    split(
+                        &mut pieces,
+                        &segment.notes,
+                        to - from,
+                        segment.start < start,
+                        segment.end > end,
+                    );
+                    position = to;
+                }
+                if position == start {
+                    // nothing in this bar
+                    xml.open("note");
+                    xml.line("<rest measure=\"yes\"/>");
+                    xml.leaf("duration", measure.length);
+                    xml.leaf("voice", 1);
+                    xml.close("note");
+                } else {
+                    if position < end {
+                        split(&mut pieces, &[], end - position, false, false);
+                    }
+                    write_notes(&mut xml, &pieces, spelling);
+                }
+                xml.close("measure");
+            }
+            xml.close("part");
+        }
+
+        xml.close("score-partwise");
+        Ok(xml.text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::channel::Channel;
+    use crate::header::{Header, MidiFormat};
+    use crate::message::{ChannelMessage, MidiMessage};
+    use crate::meta::MetaEvent;
+    use crate::smf::Smf;
+    use crate::track::{EventType, Track, TrackEvent};
+    use crate::{Note, Program, Velocity, Vql};
+
+    // without the indentation
+    fn compact(xml: &str) -> String {
+        xml.lines().map(str::trim).collect()
+    }
+
+    fn song() -> Smf {
+        let conductor = Track::from_absolute_events(vec![
+            (0, TrackEvent::meta_event(MetaEvent::SetTempo(600_000))),
+            (
+                0,
+                TrackEvent::meta_event(MetaEvent::TimeSignature {
+                    numerator: 3,
+                    denominator: 4,
+                    clocks_per_tick: 24,
+                    thirty_seconds_per_24_clocks: 8,
+                }),
+            ),
+            (
+                0,
+                TrackEvent::meta_event(MetaEvent::KeySignature {
+                    sharps: -1,
+                    is_major: true,
+                }),
+            ),
+        ])
+        .unwrap();
+
+        let channel = Channel::new(2).unwrap();
+        let on = |note| {
+            TrackEvent::note_on(
+                Vql::zero(),
+                channel,
+                Note::new(note).unwrap(),
+                Velocity::new(90).unwrap(),
+            )
+        };
+        let off = |note| {
+            TrackEvent::note_off(
+                Vql::zero(),
+                channel,
+                Note::new(note).unwrap(),
+                Velocity::new(0).unwrap(),
+            )
+        };
+        // a quarter A4, then Bb4 from beat 3 tied over the bar line for a
+        // half, then a quarter rest
+        let melody = Track::from_absolute_events(vec![
+            (
+                0,
+                TrackEvent::new(
+                    Vql::zero(),
+                    EventType::Midi(MidiMessage::Channel {
+                        channel,
+                        message: ChannelMessage::ProgramChange {
+                            program: Program::new(40).unwrap(),
+                        },
+                    }),
+                ),
+            ),
+            (0, on(69)),
+            (96, off(69)),
+            (192, on(70)),
+            (384, off(70)),
+        ])
+        .unwrap()
+        .with_name("Violin & Co");
+
+        Smf::new(
+            Header::new(MidiFormat::MultipleTrack, 2, 96).unwrap(),
+            vec![conductor, melody],
+        )
+    }
+
+    #[test]
+    fn parts_measures_and_ties() {
+        let xml = compact(&song().to_musicxml().unwrap());
+
+        // the conductor track has no notes, so there is one part
+        assert_eq!(xml.matches("<score-part ").count(), 1);
+        assert!(xml.contains("<part-name>Violin &amp; Co</part-name>"));
+        assert!(xml.contains("<midi-channel>3</midi-channel>"));
+        assert!(xml.contains("<midi-program>41</midi-program>"));
+        assert!(xml.contains("<fifths>-1</fifths>"));
+        assert!(xml.contains("<beats>3</beats>"));
+        assert!(xml.contains("<per-minute>100</per-minute>"));
+        assert_eq!(xml.matches("<measure ").count(), 2);
+
+        // Bb spelled with a flat, split at the bar line
+        let bb = "<step>B</step><alter>-1</alter><octave>4</octave>";
+        assert_eq!(xml.matches(bb).count(), 2);
+        assert_eq!(xml.matches("<tie type=\"start\"/>").count(), 1);
+        assert_eq!(xml.matches("<tied type=\"stop\"/>").count(), 1);
+        assert_eq!(xml.matches("<rest/>").count(), 2);
+    }
+
+    #[test]
+    fn odd_lengths_are_tied_values() {
+        let mut smf = song();
+        // five sixteenths from the start, a quarter and a sixteenth
+        let channel = Channel::new(0).unwrap();
+        let note = Note::new(60).unwrap();
+        smf.tracks_mut()[1] = Track::from_absolute_events(vec![
+            (
+                0,
+                TrackEvent::note_on(Vql::zero(), channel, note, Velocity::new(90).unwrap()),
+            ),
+            (
+                120,
+                TrackEvent::note_off(Vql::zero(), channel, note, Velocity::new(0).unwrap()),
+            ),
+        ])
+        .unwrap();
+        let xml = compact(&smf.to_musicxml().unwrap());
+
+        assert!(xml.contains("<duration>24</duration><tie type=\"start\"/>"));
+        assert!(xml.contains("<duration>6</duration><tie type=\"stop\"/>"));
+        assert!(xml.contains("<part-name>Track 1</part-name>"));
+        assert!(xml.contains("<sign>G</sign>"));
+    }
+
+    #[test]
+    fn triplets_are_bracketed() {
+        let smf = Smf::from_abc("X:1\nM:2/4\nL:1/8\nK:C\n(3cde f2 | (3c2d2e2 |]\n").unwrap();
+        let xml = compact(&smf.to_musicxml().unwrap());
+
+        assert_eq!(xml.matches("<time-modification>").count(), 6);
+        assert_eq!(xml.matches("<tuplet type=\"start\"/>").count(), 2);
+        assert_eq!(xml.matches("<tuplet type=\"stop\"/>").count(), 2);
+        // the bracket closes on the third note of each
+        assert!(xml.contains("<step>E</step><octave>5</octave></pitch><duration>8</duration><voice>1</voice><type>eighth</type><time-modification><actual-notes>3</actual-notes><normal-notes>2</normal-notes></time-modification><notations><tuplet type=\"stop\"/></notations>"));
+        assert!(xml.contains("<type>quarter</type><time-modification><actual-notes>3</actual-notes><normal-notes>2</normal-notes></time-modification><notations><tuplet type=\"start\"/></notations>"));
+    }
+}
@@ -0,0 +1,367 @@
+use crate::error::MidiError;
+
+// how deep elements nest, MusicXML itself needs about ten levels
+const MAX_DEPTH: usize = 100;
+
+// Just enough XML for MusicXML: elements, attributes, text, entities and
+// CDATA. The prolog, DOCTYPE, comments and processing instructions are
+// skipped, namespaces are kept as part of the name.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.child(name).is_some()
+    }
+
+    // the trimmed text of a child, e.g. "4" for <octave>4</octave>
+    pub fn text_of(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|c| c.text.trim())
+    }
+
+    pub fn error(&self, message: impl Into<String>) -> MidiError {
+        MidiError::Parse {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    // a number in a child, an error when it's there but not a number
+    pub fn number_of<T: std::str::FromStr>(&self, name: &str) -> Result<Option<T>, MidiError> {
+        match self.child(name) {
+            Some(child) => child
+                .text
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| child.error(format!("expected a number in <{name}>"))),
+            None => Ok(None),
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.index + i) == Some(&c))
+    }
+
+    fn error(&self, message: impl Into<String>) -> MidiError {
+        MidiError::Parse {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), MidiError> {
+        if !self.starts_with(text) {
+            return Err(self.error(format!("expected '{text}'")));
+        }
+        for _ in text.chars() {
+            self.bump();
+        }
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    // everything up to and including end
+    fn skip_past(&mut self, end: &str) -> Result<String, MidiError> {
+        let (line, column) = (self.line, self.column);
+        let mut skipped = String::new();
+        while !self.starts_with(end) {
+            match self.bump() {
+                Some(c) => skipped.push(c),
+                None => {
+                    return Err(MidiError::Parse {
+                        line,
+                        column,
+                        message: format!("missing '{end}'"),
+                    })
+                }
+            }
+        }
+        self.expect(end)?;
+        Ok(skipped)
+    }
+
+    // <!DOCTYPE ...> with an optional [internal subset]
+    fn skip_doctype(&mut self) -> Result<(), MidiError> {
+        let mut depth = 0;
+        loop {
+            match self.bump() {
+                Some('[') => depth += 1,
+                Some(']') => depth -= 1,
+                Some('>') if depth == 0 => return Ok(()),
+                Some(_) => {}
+                None => return Err(self.error("unfinished DOCTYPE")),
+            }
+        }
+    }
+
+    // comments, processing instructions and the DOCTYPE between elements
+    fn skip_misc(&mut self) -> Result<(), MidiError> {
+        loop {
+            self.skip_whitespace();
+            if self.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.starts_with("<!DOCTYPE") {
+                self.skip_doctype()?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, MidiError> {
+        let mut name = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+        {
+            name.push(c);
+            self.bump();
+        }
+        if name.is_empty() {
+            return Err(self.error("expected a name"));
+        }
+        Ok(name)
+    }
+
+    fn entity(&mut self) -> Result<char, MidiError> {
+        let (line, column) = (self.line, self.column);
+        self.bump();
+        let entity = self.skip_past(";")?;
+        let c = match entity.as_str() {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => entity.strip_prefix('#').and_then(|d| d.parse().ok()),
+            }
+            .and_then(char::from_u32),
+        };
+        c.ok_or(MidiError::Parse {
+            line,
+            column,
+            message: format!("unknown entity '&{entity};'"),
+        })
+    }
+
+    fn attribute_value(&mut self) -> Result<String, MidiError> {
+        let quote = match self.peek() {
+            Some(q @ ('"' | '\'')) => q,
+            _ => return Err(self.error("expected a quoted value")),
+        };
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                Some(c) if c == quote => {
+                    self.bump();
+                    return Ok(value);
+                }
+                Some('&') => value.push(self.entity()?),
+                Some(c) => {
+                    value.push(c);
+                    self.bump();
+                }
+                None => return Err(self.error("unfinished attribute value")),
+            }
+        }
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Element, MidiError> {
+        if depth == MAX_DEPTH {
+            return Err(self.error(format!("elements nested more than {MAX_DEPTH} deep")));
+        }
+        let mut element = Element {
+            line: self.line,
+            column: self.column,
+            ..Element::default()
+        };
+        self.expect("<")?;
+        element.name = self.name()?;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('/') => {
+                    self.expect("/>")?;
+                    return Ok(element);
+                }
+                Some('>') => {
+                    self.bump();
+                    break;
+                }
+                _ => {
+                    let key = self.name()?;
+                    self.skip_whitespace();
+                    self.expect("=")?;
+                    self.skip_whitespace();
+                    let value = self.attribute_value()?;
+                    element.attributes.push((key, value));
+                }
+            }
+        }
+
+        loop {
+            if self.starts_with("</") {
+                let (line, column) = (self.line, self.column);
+                self.expect("</")?;
+                let name = self.name()?;
+                if name != element.name {
+                    return Err(MidiError::Parse {
+                        line,
+                        column,
+                        message: format!("expected </{}>, found </{name}>", element.name),
+                    });
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                return Ok(element);
+            } else if self.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.starts_with("<![CDATA[") {
+                self.expect("<![CDATA[")?;
+                let data = self.skip_past("]]>")?;
+                element.text.push_str(&data);
+            } else if self.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.starts_with("<") {
+                element.children.push(self.element(depth + 1)?);
+            } else {
+                match self.peek() {
+                    Some('&') => element.text.push(self.entity()?),
+                    Some(c) => {
+                        element.text.push(c);
+                        self.bump();
+                    }
+                    None => {
+                        return Err(MidiError::Parse {
+                            line: element.line,
+                            column: element.column,
+                            message: format!("<{}> is never closed", element.name),
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
+
+// the root element of a document
+pub(crate) fn parse(text: &str) -> Result<Element, MidiError> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        index: 0,
+        line: 1,
+        column: 1,
+    };
+    // a byte order mark
+    if parser.peek() == Some('\u{feff}') {
+        parser.index += 1;
+    }
+    parser.skip_misc()?;
+    let root = parser.element(0)?;
+    parser.skip_misc()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("text after the root element"));
+    }
+    Ok(root)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn elements_attributes_and_errors() {
+        let root = parse(
+            "<?xml version=\"1.0\"?>\n<!DOCTYPE a [<!ENTITY x \"y\">]>\n\
+             <a id='1'><!-- skip --><b n=\"&lt;2&gt;\">x &amp; y</b><c/><![CDATA[<raw>]]></a>",
+        )
+        .unwrap();
+        assert_eq!(root.attribute("id"), Some("1"));
+        assert_eq!(root.child("b").unwrap().attribute("n"), Some("<2>"));
+        assert_eq!(root.text_of("b"), Some("x & y"));
+        assert!(root.has("c"));
+        assert_eq!(root.text, "<raw>");
+        assert_eq!(root.child("b").unwrap().line, 3);
+
+        assert_eq!(
+            parse("<a>\n  <b></c>\n</a>").unwrap_err(),
+            MidiError::Parse {
+                line: 2,
+                column: 6,
+                message: "expected </b>, found </c>".to_string()
+            }
+        );
+
+        let deep = "<a>".repeat(100_000);
+        assert!(matches!(
+            parse(&deep).unwrap_err(),
+            MidiError::Parse {
+                line: 1,
+                column: 301,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::error::MidiError;
+use crate::key::Spelling;
+use crate::meta::MetaEvent;
+use crate::span::NoteSpan;
+use crate::track::{EventType, Track};
+use crate::Note;
+
+// the most bars a score is written with, see MidiError::TooManyMeasures
+const MAX_MEASURES: usize = 10_000;
+
+// What the score writers share: songs cut into bars and tracks flattened
+// into one voice of chords and rests. Everything is in quantized units,
+// per_quarter of them to a quarter note.
+
+// the meta events of all tracks with quantized ticks, in tick order
+pub(crate) fn conductor(tracks: &[Track], quantize: impl Fn(u64) -> u64) -> Vec<(u64, MetaEvent)> {
+    let mut metas: Vec<(u64, MetaEvent)> = tracks
+        .iter()
+        .flat_map(|t| t.absolute_events())
+        .filter_map(|(tick, e)| match &e.event {
+            EventType::Meta(meta) => Some((quantize(tick), meta.clone())),
+            _ => None,
+        })
+        .collect();
+    metas.sort_by_key(|(tick, _)| *tick);
+    metas
+}
+
+// flats for flat keys, sharps otherwise
+pub(crate) fn key_spelling(sharps: i8) -> Spelling {
+    if sharps < 0 {
+        Spelling::Flats
+    } else {
+        Spelling::Sharps
+    }
+}
+
+// A bar of the score. time and key are only set where they change (and
+// always on the first one)
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Measure {
+    pub start: u64,
+    pub length: u64,
+    pub time: Option<(u8, u8)>,
+    pub key: Option<(i8, bool)>,
+    // (offset into the measure, beats per minute)
+    pub tempos: Vec<(u64, f64)>,
+}
+
+// the bars up to end, cut by the time signatures. A change that isn't on
+// a bar line ends the bar early
+pub(crate) fn measures(
+    metas: &[(u64, MetaEvent)],
+    end: u64,
+    per_quarter: u64,
+) -> Result<Vec<Measure>, MidiError> {
+    let times: Vec<(u64, u8, u8)> = metas
+        .iter()
+        .filter_map(|(tick, meta)| match meta {
+            MetaEvent::TimeSignature {
+                numerator,
+                denominator,
+                ..
+            } if *numerator > 0 && *denominator > 0 => Some((*tick, *numerator, *denominator)),
+            _ => None,
+        })
+        .collect();
+
+    let mut measures: Vec<Measure> = Vec::new();
+    let mut time = (4, 4);
+    let mut next = 0;
+    let mut tick = 0;
+    while measures.is_empty() || tick < end {
+        if measures.len() == MAX_MEASURES {
+            return Err(MidiError::TooManyMeasures);
+        }
+        let mut changed = measures.is_empty();
+        while next < times.len() && times[next].0 <= tick {
+            time = (times[next].1, times[next].2);
+            changed = true;
+            next += 1;
+        }
+        let mut length = (per_quarter * 4 * time.0 as u64 / time.1 as u64).max(1);
+        if let Some((at, _, _)) = times.get(next) {
+            length = length.min(at - tick);
+        }
+        measures.push(Measure {
+            start: tick,
+            length,
+            time: changed.then_some(time),
+            key: None,
+            tempos: Vec::new(),
+        });
+        tick += length;
+    }
+
+    measures[0].key = Some((0, true));
+    for (tick, meta) in metas {
+        let index = measures.iter().rposition(|m| m.start <= *tick).unwrap_or(0);
+        let measure = &mut measures[index];
+        match meta {
+            MetaEvent::KeySignature { sharps, is_major } => {
+                measure.key = Some((*sharps, *is_major));
+            }
+            MetaEvent::SetTempo(tempo) if *tempo > 0 => {
+                let bpm = (60_000_000.0 / *tempo as f64 * 100.0).round() / 100.0;
+                measure.tempos.push((tick - measure.start, bpm));
+            }
+            _ => {}
+        }
+    }
+    Ok(measures)
+}
+
+// A stretch of one voice: a chord, a single note or a rest when empty
+pub(crate) struct Segment {
+    pub start: u64,
+    pub end: u64,
+    pub notes: Vec<Note>,
+}
+
+// Notes starting together become a chord that lasts as long as its
+// shortest note, and a chord is cut where the next one starts, so the
+// voice is a row of segments with rests filling the gaps
+pub(crate) fn segments(spans: &[NoteSpan], quantize: impl Fn(u64) -> u64) -> Vec<Segment> {
+    let mut chords: Vec<Segment> = Vec::new();
+    for span in spans {
+        let start = quantize(span.start);
+        let end = quantize(span.end).max(start + 1);
+        match chords.last_mut() {
+            Some(chord) if chord.start == start => {
+                chord.end = chord.end.min(end);
+                if !chord.notes.contains(&span.note) {
+                    chord.notes.push(span.note);
+                }
+            }
+            _ => chords.push(Segment {
+                start,
+                end,
+                notes: vec![span.note],
+            }),
+        }
+    }
+
+    let mut voice = Vec::new();
+    let mut position = 0;
+    for i in 0..chords.len() {
+        let next = chords.get(i + 1).map(|c| c.start);
+        let chord = &mut chords[i];
+        if let Some(next) = next {
+            chord.end = chord.end.min(next);
+        }
+        if chord.start > position {
+            voice.push(Segment {
+                start: position,
+                end: chord.start,
+                notes: Vec::new(),
+            });
+        }
+        chord.notes.sort();
+        position = chord.end;
+        voice.push(Segment {
+            start: chord.start,
+            end: chord.end,
+            notes: std::mem::take(&mut chord.notes),
+        });
+    }
+    voice
+}
@@ -0,0 +1,299 @@
+use crate::error::MidiError;
+use crate::key::{MiddleC, Spelling};
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::meta::MetaEvent;
+use crate::smf::Smf;
+use crate::tonality::Key;
+use crate::track::{EventType, Track};
+use std::fmt::Write;
+
+use super::meter::Meters;
+
+// (note start, held, bar line, beat)
+const ASCII: [char; 4] = ['#', '=', '|', '.'];
+const UNICODE: [char; 4] = ['█', '▒', '│', '┊'];
+
+// How a track is printed as text, e.g.
+// AsciiRollOptions::default().with_columns_per_beat(2).with_unicode(true)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsciiRollOptions {
+    columns_per_beat: u32,
+    unicode: bool,
+    spelling: Spelling,
+    middle_c: MiddleC,
+}
+
+impl Default for AsciiRollOptions {
+    fn default() -> Self {
+        Self {
+            columns_per_beat: 4,
+            unicode: false,
+            spelling: Spelling::Sharps,
+            middle_c: MiddleC::default(),
+        }
+    }
+}
+
+impl AsciiRollOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the resolution, a column is a sixteenth with the default 4
+    pub fn with_columns_per_beat(mut self, columns: u32) -> Self {
+        self.columns_per_beat = columns.max(1);
+        self
+    }
+
+    // box drawing characters instead of plain ASCII
+    pub fn with_unicode(mut self, unicode: bool) -> Self {
+        self.unicode = unicode;
+        self
+    }
+
+    pub fn with_spelling(mut self, spelling: Spelling) -> Self {
+        self.spelling = spelling;
+        self
+    }
+
+    pub fn with_middle_c(mut self, middle_c: MiddleC) -> Self {
+        self.middle_c = middle_c;
+        self
+    }
+}
+
+impl Track {
+    // The notes of the track as rows of text, the highest on top, one
+    // column per 1/columns_per_beat of a beat:
+    //
+    //      1       2
+    //   E4 | . #===#=. . .
+    //   C4 # #=. . #=. . .
+    pub fn to_ascii_roll(&self, ticks_per_beat: u32, options: &AsciiRollOptions) -> String {
+        let [start_char, held, bar_char, beat_char] = if options.unicode { UNICODE } else { ASCII };
+        let spans = self.note_spans();
+        let meters = Meters::new(std::iter::once(self), ticks_per_beat);
+        let column_ticks = (ticks_per_beat as u64 / options.columns_per_beat as u64).max(1);
+        let end = spans
+            .iter()
+            .map(|s| s.end)
+            .max()
+            .unwrap_or(0)
+            .max(self.end_tick());
+        let columns = end.div_ceil(column_ticks) as usize;
+
+        let label = |note: u8| {
+            crate::Note::new(note)
+                .map(|n| n.display(options.spelling, options.middle_c).to_string())
+                .unwrap_or_default()
+        };
+        let width = 4;
+
+        // the grid every row starts from, and the bar numbers above it
+        let mut grid = vec![' '; columns];
+        let mut numbers = vec![' '; columns];
+        for (column, cell) in grid.iter_mut().enumerate() {
+            let tick = column as u64 * column_ticks;
+            let (bar, beat, into) = meters.position(tick);
+            if into == 0 && beat == 1 {
+                *cell = bar_char;
+                for (i, digit) in bar.to_string().chars().enumerate() {
+                    if let Some(slot) = numbers.get_mut(column + i) {
+                        *slot = digit;
+                    }
+                }
+            } else if into == 0 {
+                *cell = beat_char;
+            }
+        }
+
+        let mut text = String::new();
+        let numbers: String = numbers.into_iter().collect();
+        let _ = writeln!(text, "{:width$} {}", "", numbers.trim_end());
+        let (Some(lowest), Some(highest)) = (
+            spans.iter().map(|s| s.note.value()).min(),
+            spans.iter().map(|s| s.note.value()).max(),
+        ) else {
+            return text;
+        };
+        for note in (lowest..=highest).rev() {
+            let mut row = grid.clone();
+            for span in spans.iter().filter(|s| s.note.value() == note) {
+                let first = (span.start / column_ticks) as usize;
+                let last = (span.end.saturating_sub(1) / column_ticks) as usize;
+                for (column, cell) in row.iter_mut().enumerate().take(last + 1).skip(first) {
+                    if column == first {
+                        *cell = start_char;
+                    } else if *cell != start_char {
+                        *cell = held;
+                    }
+                }
+            }
+            let row: String = row.into_iter().collect();
+            let _ = writeln!(text, "{:>width$} {}", label(note), row.trim_end());
+        }
+        text
+    }
+
+    // One line per event, like mididump: the absolute tick, bar:beat:tick,
+    // the channel from 1 to 16 and what the event is
+    pub fn dump(&self, ticks_per_beat: u32) -> String {
+        let meters = Meters::new(std::iter::once(self), ticks_per_beat);
+        dump_track(self, &meters)
+    }
+}
+
+fn describe(event: &EventType) -> (String, String, String) {
+    match event {
+        EventType::Midi(MidiMessage::Channel { channel, message }) => {
+            let details = match message {
+                ChannelMessage::NoteOn { note, velocity }
+                | ChannelMessage::NoteOff { note, velocity } => {
+                    format!("{note} ({}) velocity {}", note.value(), velocity.value())
+                }
+                ChannelMessage::PolyphonicKeyPressure { note, pressure } => {
+                    format!("{note} ({}) pressure {}", note.value(), pressure.value())
+                }
+                ChannelMessage::ControlChange { control, value } => {
+                    format!("control {} value {}", control.value(), value.value())
+                }
+                ChannelMessage::ProgramChange { program } => format!("program {}", program.value()),
+                ChannelMessage::ChannelPressure { pressure } => {
+                    format!("pressure {}", pressure.value())
+                }
+                ChannelMessage::PitchBend { value } => format!("value {value}"),
+            };
+            (
+                (channel.value() + 1).to_string(),
+                format!("{:?}", message.kind()),
+                details,
+            )
+        }
+        EventType::Midi(other) => ("--".to_string(), format!("{other:?}"), String::new()),
+        EventType::Meta(meta) => {
+            let (name, details) = match meta {
+                MetaEvent::TrackName(name) => (
+                    "TrackName".to_string(),
+                    format!("{:?}", String::from_utf8_lossy(name)),
+                ),
+                MetaEvent::EndOfTrack => ("EndOfTrack".to_string(), String::new()),
+                MetaEvent::SetTempo(tempo) => (
+                    "SetTempo".to_string(),
+                    format!(
+                        "{:.2} bpm ({tempo} us per beat)",
+                        60_000_000.0 / (*tempo).max(1) as f64
+                    ),
+                ),
+                MetaEvent::TimeSignature {
+                    numerator,
+                    denominator,
+                    ..
+                } => (
+                    "TimeSignature".to_string(),
+                    format!("{numerator}/{denominator}"),
+                ),
+                MetaEvent::KeySignature { sharps, is_major } => (
+                    "KeySignature".to_string(),
+                    match Key::from_signature(*sharps, *is_major) {
+                        Some(key) => format!("{key} ({sharps})"),
+                        None => sharps.to_string(),
+                    },
+                ),
+                MetaEvent::Unknown { event_type, data } => {
+                    let text = String::from_utf8_lossy(data);
+                    let details = if !data.is_empty() && text.chars().all(|c| !c.is_control()) {
+                        format!("{text:?}")
+                    } else {
+                        data.iter()
+                            .map(|b| format!("{b:02X}"))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    };
+                    (format!("Meta 0x{event_type:02X}"), details)
+                }
+            };
+            ("--".to_string(), name, details)
+        }
+        EventType::Sysex(data) => (
+            "--".to_string(),
+            "Sysex".to_string(),
+            format!("{} bytes", data.len()),
+        ),
+    }
+}
+
+fn dump_track(track: &Track, meters: &Meters) -> String {
+    let mut text = String::new();
+    for (tick, event) in track.absolute_events() {
+        let (bar, beat, into) = meters.position(tick);
+        let (channel, name, details) = describe(&event.event);
+        let line =
+            format!("{tick:>8}  {bar:>4}:{beat}:{into:03}  {channel:>2}  {name:<22} {details}");
+        let _ = writeln!(text, "{}", line.trim_end());
+    }
+    text
+}
+
+impl Smf {
+    // Track::dump for every track under a heading, bars and beats follow
+    // the time signatures of the whole song
+    pub fn dump(&self) -> Result<String, MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)?;
+        let meters = Meters::new(self.tracks().iter(), ticks_per_beat);
+        let mut text = String::new();
+        for (index, track) in self.tracks().iter().enumerate() {
+            if index > 0 {
+                text.push('\n');
+            }
+            let _ = writeln!(text, "Track {index}: {:?}", track.name());
+            text.push_str(&dump_track(track, &meters));
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roll_in_ascii_and_unicode() {
+        let smf = Smf::from_text("l8 c r c4 e2 | [ce]4 r4 r2").unwrap();
+        let track = &smf.tracks()[0];
+        let options = AsciiRollOptions::new().with_columns_per_beat(2);
+
+        // C8 r8 C4 E2 | [CE]4 r4 r2, the bar line hides under notes
+        assert_eq!(
+            track.to_ascii_roll(480, &options),
+            "     1       2
+  E4 | . #===#=. . .
+ D#4 | . . . | . . .
+  D4 | . . . | . . .
+ C#4 | . . . | . . .
+  C4 # #=. . #=. . .
+"
+        );
+        let unicode = track.to_ascii_roll(480, &options.with_unicode(true));
+        assert!(unicode.contains("  C4 █ █▒┊ ┊ █▒┊ ┊ ┊\n"));
+    }
+
+    #[test]
+    fn dump_lines() {
+        let smf = Smf::from_text("track Lead t90 @5 !f c4").unwrap();
+        let dump = smf.dump().unwrap();
+        assert_eq!(
+            dump,
+            "Track 0: \"Lead\"
+       0     1:1:000  --  TrackName              \"Lead\"
+       0     1:1:000  --  SetTempo               90.00 bpm (666667 us per beat)
+       0     1:1:000   1  ProgramChange          program 5
+       0     1:1:000   1  NoteOn                 C4 (60) velocity 96
+     480     1:2:000   1  NoteOff                C4 (60) velocity 0
+     480     1:2:000  --  EndOfTrack
+"
+        );
+    }
+}
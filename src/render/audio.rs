@@ -0,0 +1,588 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Velocity};
+use crate::error::MidiError;
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::meta::MetaEvent;
+use crate::smf::Smf;
+use crate::track::EventType;
+use crate::transform::time::DEFAULT_TEMPO;
+
+// Offline playback shared by the audio renderers. The song is played in
+// real time order through an instrument that makes the voices, while the
+// channels keep track of program, bank, volume, pan, sustain and pitch
+// bend the way a General MIDI device would.
+
+const MAX_TAIL: f64 = 60.0;
+// the most 16 bit stereo frames a WAV file holds
+const MAX_FRAMES: f64 = ((u32::MAX - 36) / 4) as f64;
+
+// How a song is rendered to audio, e.g.
+// AudioOptions::default().with_gain(0.5).with_tail(2.0)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioOptions {
+    sample_rate: u32,
+    gain: f32,
+    tail: f64,
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44_100,
+            gain: 0.3,
+            tail: 1.0,
+        }
+    }
+}
+
+impl AudioOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate.max(1000);
+        self
+    }
+
+    // the master volume, the voices are added up so a busy song wants less
+    pub fn with_gain(mut self, gain: f32) -> Self {
+        self.gain = gain.max(0.0);
+        self
+    }
+
+    // seconds kept after the last event for the notes to ring out, up to
+    // a minute
+    pub fn with_tail(mut self, seconds: f64) -> Self {
+        self.tail = if seconds.is_nan() {
+            0.0
+        } else {
+            seconds.clamp(0.0, MAX_TAIL)
+        };
+        self
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+// Rendered stereo audio as [left, right] frames, full scale is 1.0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    sample_rate: u32,
+    frames: Vec<[f32; 2]>,
+}
+
+impl Audio {
+    pub fn new(sample_rate: u32, frames: Vec<[f32; 2]>) -> Self {
+        Self {
+            sample_rate,
+            frames,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn frames(&self) -> &[[f32; 2]] {
+        &self.frames
+    }
+
+    // in seconds
+    pub fn duration(&self) -> f64 {
+        self.frames.len() as f64 / self.sample_rate as f64
+    }
+
+    pub fn peak(&self) -> f32 {
+        self.frames
+            .iter()
+            .flatten()
+            .fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    // The audio as a 16 bit stereo PCM WAV file, anything past full scale
+    // is clipped. The header holds 32 bit sizes, which caps a file at
+    // about 4 GiB
+    pub fn to_wav(&self) -> Result<Vec<u8>, MidiError> {
+        let data = u32::try_from(self.frames.len())
+            .ok()
+            .and_then(|frames| frames.checked_mul(4))
+            .filter(|data| data.checked_add(36).is_some())
+            .ok_or(MidiError::WavTooLarge)?;
+        let byte_rate = self
+            .sample_rate
+            .checked_mul(4)
+            .ok_or(MidiError::WavTooLarge)?;
+        let mut bytes = Vec::with_capacity(44 + data as usize);
+        bytes.extend(b"RIFF");
+        bytes.extend((36 + data).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(2u16.to_le_bytes());
+        bytes.extend(self.sample_rate.to_le_bytes());
+        bytes.extend(byte_rate.to_le_bytes());
+        bytes.extend(4u16.to_le_bytes());
+        bytes.extend(16u16.to_le_bytes());
+        bytes.extend(b"data");
+        bytes.extend(data.to_le_bytes());
+        for sample in self.frames.iter().flatten() {
+            let value = (sample.clamp(-1.0, 1.0) * 32767.0).round() as i16;
+            bytes.extend(value.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+}
+
+// An ADSR envelope, times in seconds and sustain as a level up to 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Adsr {
+    pub attack: f64,
+    pub decay: f64,
+    pub sustain: f64,
+    pub release: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+// below this an envelope is silent
+const SILENCE: f64 = 1e-4;
+
+// The attack is a straight line, decay and release fall exponentially and
+// are down 60 dB after their time
+#[derive(Debug, Clone)]
+pub(crate) struct Envelope {
+    adsr: Adsr,
+    sample_rate: f64,
+    stage: Stage,
+    level: f64,
+}
+
+impl Envelope {
+    pub fn new(adsr: Adsr, sample_rate: f64) -> Self {
+        Self {
+            adsr,
+            sample_rate,
+            stage: Stage::Attack,
+            level: 0.0,
+        }
+    }
+
+    fn falling(&self, seconds: f64) -> f64 {
+        (-(1000.0f64).ln() / (seconds * self.sample_rate).max(1.0)).exp()
+    }
+
+    pub fn next(&mut self) -> f32 {
+        match self.stage {
+            Stage::Attack => {
+                self.level += 1.0 / (self.adsr.attack * self.sample_rate).max(1.0);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                let sustain = self.adsr.sustain.clamp(0.0, 1.0);
+                self.level = sustain + (self.level - sustain) * self.falling(self.adsr.decay);
+                if self.level - sustain < SILENCE {
+                    self.level = sustain;
+                    self.stage = if sustain < SILENCE {
+                        Stage::Done
+                    } else {
+                        Stage::Sustain
+                    };
+                }
+            }
+            Stage::Sustain => {}
+            Stage::Release => {
+                self.level *= self.falling(self.adsr.release);
+                if self.level < SILENCE {
+                    self.stage = Stage::Done;
+                }
+            }
+            Stage::Done => self.level = 0.0,
+        }
+        self.level as f32
+    }
+
+    pub fn release(&mut self) {
+        if self.stage != Stage::Done {
+            self.stage = Stage::Release;
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.stage == Stage::Done
+    }
+}
+
+const NO_RPN: (u8, u8) = (127, 127);
+
+// What a channel has been told so far
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ChannelState {
+    pub program: u8,
+    // bank select, the MSB in the high 7 bits
+    pub bank: u16,
+    pub volume: u8,
+    pub expression: u8,
+    pub pan: u8,
+    pub sustain: bool,
+    pub bend: u16,
+    // in semitones either way, set with RPN 0
+    pub bend_range: f64,
+    rpn: (u8, u8),
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            program: 0,
+            bank: 0,
+            volume: 100,
+            expression: 127,
+            pan: 64,
+            sustain: false,
+            bend: 8192,
+            bend_range: 2.0,
+            rpn: NO_RPN,
+        }
+    }
+}
+
+impl ChannelState {
+    fn control(&mut self, control: u8, value: u8) {
+        match control {
+            0 => self.bank = (value as u16) << 7 | (self.bank & 0x7F),
+            32 => self.bank = (self.bank & !0x7F) | value as u16,
+            7 => self.volume = value,
+            10 => self.pan = value,
+            11 => self.expression = value,
+            64 => self.sustain = value >= 64,
+            101 => self.rpn.0 = value,
+            100 => self.rpn.1 = value,
+            6 if self.rpn == (0, 0) => {
+                self.bend_range = value as f64 + self.bend_range.fract();
+            }
+            38 if self.rpn == (0, 0) => {
+                self.bend_range = self.bend_range.trunc() + value.min(99) as f64 / 100.0;
+            }
+            // reset all controllers leaves volume, pan and the program
+            121 => {
+                self.expression = 127;
+                self.sustain = false;
+                self.bend = 8192;
+                self.rpn = NO_RPN;
+            }
+            _ => {}
+        }
+    }
+
+    // the pitch bend as a frequency ratio
+    pub fn pitch_ratio(&self) -> f64 {
+        let semitones = (self.bend as f64 - 8192.0) / 8192.0 * self.bend_range;
+        (semitones / 12.0).exp2()
+    }
+
+    // left and right gain from volume, expression and an equal power pan
+    fn gains(&self) -> [f32; 2] {
+        let level = (self.volume as f32 / 127.0 * self.expression as f32 / 127.0).powi(2);
+        let angle = (self.pan.max(1) - 1) as f32 / 126.0 * std::f32::consts::FRAC_PI_2;
+        [level * angle.cos(), level * angle.sin()]
+    }
+}
+
+// One sounding note
+pub(crate) trait Voice {
+    // the next [left, right] frame, pitch_ratio is the channel's bend
+    fn next(&mut self, pitch_ratio: f64) -> [f32; 2];
+    fn release(&mut self);
+    fn finished(&self) -> bool;
+}
+
+// Makes the voices, None when it has nothing for the note
+pub(crate) trait Instrument {
+    type Voice: Voice;
+
+    fn voice(
+        &self,
+        channel: Channel,
+        state: &ChannelState,
+        note: Note,
+        velocity: Velocity,
+        sample_rate: f64,
+    ) -> Option<Self::Voice>;
+}
+
+struct Playing<V> {
+    channel: usize,
+    note: Note,
+    // released while sustain is down, the release waits for it to go up
+    held: bool,
+    released: bool,
+    voice: V,
+}
+
+impl<V: Voice> Playing<V> {
+    fn release(&mut self) {
+        self.held = false;
+        self.released = true;
+        self.voice.release();
+    }
+}
+
+// everything sounding up to (but not including) frame `until`
+fn mix<V: Voice>(
+    frames: &mut Vec<[f32; 2]>,
+    until: usize,
+    voices: &mut Vec<Playing<V>>,
+    channels: &[ChannelState; 16],
+    gain: f32,
+) {
+    let ratios: Vec<f64> = channels.iter().map(|c| c.pitch_ratio()).collect();
+    let gains: Vec<[f32; 2]> = channels
+        .iter()
+        .map(|c| c.gains().map(|g| g * gain))
+        .collect();
+    while frames.len() < until {
+        let mut frame = [0.0; 2];
+        for playing in voices.iter_mut() {
+            let [left, right] = playing.voice.next(ratios[playing.channel]);
+            frame[0] += left * gains[playing.channel][0];
+            frame[1] += right * gains[playing.channel][1];
+        }
+        frames.push(frame);
+    }
+    voices.retain(|p| !p.voice.finished());
+}
+
+fn note_off<V: Voice>(
+    voices: &mut [Playing<V>],
+    state: &ChannelState,
+    channel: usize,
+    note: Option<Note>,
+) {
+    for playing in voices
+        .iter_mut()
+        .filter(|p| p.channel == channel && !p.released && !p.held)
+        .filter(|p| note.is_none_or(|n| p.note == n))
+    {
+        if state.sustain {
+            playing.held = true;
+        } else {
+            playing.release();
+        }
+    }
+}
+
+// The whole song through the instrument, every track mixed together. The
+// tempo changes can be on any track
+pub(crate) fn play<I: Instrument>(
+    smf: &Smf,
+    instrument: &I,
+    options: &AudioOptions,
+) -> Result<Audio, MidiError> {
+    let ticks_per_beat = smf
+        .header()
+        .ticks_per_beat()
+        .ok_or(MidiError::UnsupportedDivision)? as f64;
+    let rate = options.sample_rate as f64;
+
+    // releases go first, so a note ending where the same one starts again
+    // doesn't cut the new one
+    let mut events: Vec<(u64, &EventType)> = smf
+        .tracks()
+        .iter()
+        .flat_map(|t| t.absolute_events())
+        .map(|(tick, e)| (tick, &e.event))
+        .collect();
+    events.sort_by_key(|(tick, event)| {
+        let starts = !matches!(
+            event,
+            EventType::Midi(MidiMessage::Channel {
+                message: ChannelMessage::NoteOff { .. },
+                ..
+            })
+        ) && !matches!(
+            event,
+            EventType::Midi(MidiMessage::Channel {
+                message: ChannelMessage::NoteOn { velocity, .. },
+                ..
+            }) if velocity.value() == 0
+        );
+        (*tick, starts)
+    });
+
+    // when each event happens, so a song too long for a WAV file is an
+    // error before any frame is mixed
+    let mut times = Vec::with_capacity(events.len());
+    let (mut seconds, mut last, mut tempo) = (0.0, 0, DEFAULT_TEMPO);
+    for (tick, event) in &events {
+        seconds += (tick - last) as f64 / ticks_per_beat * tempo as f64 / 1_000_000.0;
+        last = *tick;
+        times.push(seconds);
+        if let EventType::Meta(MetaEvent::SetTempo(value @ 1..)) = event {
+            tempo = *value;
+        }
+    }
+    let end = ((seconds + options.tail) * rate).round();
+    if end > MAX_FRAMES {
+        return Err(MidiError::WavTooLarge);
+    }
+
+    let mut frames = Vec::new();
+    let mut channels = [ChannelState::default(); 16];
+    let mut voices: Vec<Playing<I::Voice>> = Vec::new();
+    for ((_, event), seconds) in events.into_iter().zip(times) {
+        mix(
+            &mut frames,
+            (seconds * rate).round() as usize,
+            &mut voices,
+            &channels,
+            options.gain,
+        );
+
+        let (channel, message) = match event {
+            EventType::Midi(MidiMessage::Channel { channel, message }) => (*channel, message),
+            _ => continue,
+        };
+        let index = channel.value() as usize;
+        let state = &mut channels[index];
+        match *message {
+            ChannelMessage::NoteOn { note, velocity } if velocity.value() > 0 => {
+                // the same note again on the channel replaces the old one
+                for playing in voices
+                    .iter_mut()
+                    .filter(|p| p.channel == index && p.note == note && !p.released)
+                {
+                    playing.release();
+                }
+                if let Some(voice) = instrument.voice(channel, state, note, velocity, rate) {
+                    voices.push(Playing {
+                        channel: index,
+                        note,
+                        held: false,
+                        released: false,
+                        voice,
+                    });
+                }
+            }
+            ChannelMessage::NoteOn { note, .. } | ChannelMessage::NoteOff { note, .. } => {
+                note_off(&mut voices, state, index, Some(note));
+            }
+            ChannelMessage::ControlChange { control, value } => {
+                state.control(control.value(), value.value());
+                match control.value() {
+                    // all sound off
+                    120 => voices.retain(|p| p.channel != index),
+                    // all notes off
+                    123 => note_off(&mut voices, state, index, None),
+                    _ => {}
+                }
+                if !state.sustain {
+                    for playing in voices.iter_mut().filter(|p| p.channel == index && p.held) {
+                        playing.release();
+                    }
+                }
+            }
+            ChannelMessage::ProgramChange { program } => state.program = program.value(),
+            ChannelMessage::PitchBend { value } => state.bend = value.min(16383),
+            _ => {}
+        }
+    }
+
+    mix(
+        &mut frames,
+        end as usize,
+        &mut voices,
+        &channels,
+        options.gain,
+    );
+    Ok(Audio::new(options.sample_rate, frames))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::track::{Track, TrackEvent};
+
+    #[test]
+    fn wav_header_and_samples() {
+        let audio = Audio::new(44_100, vec![[0.0, 1.0], [-2.0, 0.5]]);
+        let wav = audio.to_wav().unwrap();
+
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 44);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        // PCM, stereo, 44.1 kHz, 4 bytes a frame of 16 bit samples
+        assert_eq!(&wav[20..24], &[1, 0, 2, 0]);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 44_100);
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 176_400);
+        assert_eq!(&wav[32..36], &[4, 0, 16, 0]);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 8);
+        let samples: Vec<i16> = wav[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        assert_eq!(samples, vec![0, 32767, -32767, 16384]);
+        assert_eq!(audio.peak(), 2.0);
+
+        // the byte rate, four times the sample rate, has to fit the header
+        let fast = Audio::new(u32::MAX / 4 + 1, vec![[0.0; 2]]);
+        assert_eq!(fast.to_wav(), Err(MidiError::WavTooLarge));
+    }
+
+    #[test]
+    fn length_limits() {
+        let options = AudioOptions::new().with_tail(f64::INFINITY);
+        assert_eq!(options.tail, MAX_TAIL);
+        assert_eq!(AudioOptions::new().with_tail(f64::NAN).tail, 0.0);
+
+        // about 58 hours at 120 bpm, too long for a WAV file
+        let mut smf = Smf::from_text("c").unwrap();
+        smf.tracks_mut()[0] =
+            Track::from_absolute_events(vec![(200_000_000, TrackEvent::end_track())]).unwrap();
+        assert_eq!(smf.render_audio(&options), Err(MidiError::WavTooLarge));
+    }
+
+    #[test]
+    fn channel_controls() {
+        let mut state = ChannelState::default();
+        state.control(0, 1);
+        state.control(32, 2);
+        assert_eq!(state.bank, 130);
+
+        // bend range to 12 semitones, then bend all the way up
+        state.control(101, 0);
+        state.control(100, 0);
+        state.control(6, 12);
+        state.bend = 16383;
+        assert!((state.pitch_ratio() - 2.0).abs() < 0.001);
+
+        state.control(7, 127);
+        state.control(10, 0);
+        assert_eq!(state.gains(), [1.0, 0.0]);
+        state.control(10, 64);
+        let [left, right] = state.gains();
+        assert!(
+            (left - right).abs() < 1e-6 && (left - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.001
+        );
+
+        state.control(64, 127);
+        state.control(121, 0);
+        assert!(!state.sustain);
+        assert_eq!(state.bend, 8192);
+        assert_eq!(state.volume, 127);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::meta::MetaEvent;
+use crate::track::{EventType, Track};
+
+// (tick, bar at that tick, numerator, denominator)
+type Meter = (u64, u64, u64, u64);
+
+// Bars and beats of a song from its time signatures, 4/4 until the first
+// one. A change in the middle of a bar starts a new bar
+pub(crate) struct Meters {
+    ticks_per_beat: u64,
+    meters: Vec<Meter>,
+}
+
+impl Meters {
+    pub(crate) fn new<'a>(tracks: impl Iterator<Item = &'a Track>, ticks_per_beat: u32) -> Self {
+        let ticks_per_beat = ticks_per_beat.max(1) as u64;
+        let mut signatures: Vec<(u64, u64, u64)> = tracks
+            .flat_map(|t| t.absolute_events())
+            .filter_map(|(tick, e)| match e.event {
+                EventType::Meta(MetaEvent::TimeSignature {
+                    numerator,
+                    denominator,
+                    ..
+                }) if numerator > 0 && denominator > 0 => {
+                    Some((tick, numerator as u64, denominator as u64))
+                }
+                _ => None,
+            })
+            .collect();
+        signatures.sort_by_key(|(tick, ..)| *tick);
+
+        let mut meters = vec![(0, 1, 4, 4)];
+        for (tick, numerator, denominator) in signatures {
+            let last = meters.last_mut().unwrap();
+            if tick == last.0 {
+                (last.2, last.3) = (numerator, denominator);
+                continue;
+            }
+            let bars = (tick - last.0).div_ceil(Self::bar_length(ticks_per_beat, Some(last)));
+            let bar = last.1 + bars;
+            meters.push((tick, bar, numerator, denominator));
+        }
+        Self {
+            ticks_per_beat,
+            meters,
+        }
+    }
+
+    fn beat_length(ticks_per_beat: u64, meter: Option<&Meter>) -> u64 {
+        let denominator = meter.map_or(4, |m| m.3);
+        (ticks_per_beat * 4 / denominator).max(1)
+    }
+
+    fn bar_length(ticks_per_beat: u64, meter: Option<&Meter>) -> u64 {
+        let numerator = meter.map_or(4, |m| m.2);
+        Self::beat_length(ticks_per_beat, meter) * numerator
+    }
+
+    // (bar, beat, tick into the beat), bar and beat counted from 1
+    pub(crate) fn position(&self, tick: u64) -> (u64, u64, u64) {
+        let meter = self.meters.iter().rev().find(|m| m.0 <= tick);
+        let (start, bar, ..) = *meter.unwrap_or(&self.meters[0]);
+        let beat = Self::beat_length(self.ticks_per_beat, meter);
+        let bar_length = Self::bar_length(self.ticks_per_beat, meter);
+        let into = tick - start;
+        (
+            bar + into / bar_length,
+            into % bar_length / beat + 1,
+            into % beat,
+        )
+    }
+
+    // every beat up to and including end, with the bar number where a bar
+    // starts on it
+    pub(crate) fn beats(&self, end: u64) -> impl Iterator<Item = (u64, Option<u64>)> + '_ {
+        self.meters.iter().enumerate().flat_map(move |(i, meter)| {
+            let until = self.meters.get(i + 1).map_or(end + 1, |next| next.0);
+            let beat = Self::beat_length(self.ticks_per_beat, Some(meter));
+            let (start, bar, numerator, _) = *meter;
+            (0..)
+                .map(move |n| (start + n * beat, n))
+                .take_while(move |(tick, _)| *tick < until.min(end + 1))
+                .map(move |(tick, n)| (tick, (n % numerator == 0).then_some(bar + n / numerator)))
+        })
+    }
+}
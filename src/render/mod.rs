@@ -0,0 +1,12 @@
+pub mod ascii;
+pub mod audio;
+mod meter;
+mod sampler;
+pub mod soundfont;
+pub mod svg;
+mod synth;
+
+pub use ascii::*;
+pub use audio::*;
+pub use soundfont::*;
+pub use svg::*;
@@ -0,0 +1,335 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Velocity};
+use crate::error::MidiError;
+use crate::render::audio::{
+    play, Adsr, Audio, AudioOptions, ChannelState, Envelope, Instrument, Voice,
+};
+use crate::render::soundfont::*;
+use crate::smf::Smf;
+use std::f32::consts::FRAC_PI_2;
+
+// Plays songs through a SoundFont. Every preset zone and instrument zone
+// taking the note and velocity adds a layer to the voice, a looping,
+// linearly interpolated sample with the volume envelope of its zone. The
+// delay and hold of the envelope and the filter and modulation envelopes
+// are left out.
+
+// timecents are the SF2 unit for envelope times
+fn seconds(timecents: i32) -> f64 {
+    (timecents as f64 / 1200.0).exp2()
+}
+
+// centibels of attenuation as a gain
+fn attenuation(centibels: i32) -> f32 {
+    10f32.powf(-(centibels.clamp(0, 1440) as f32) / 200.0)
+}
+
+// a generator of the instrument zone with the preset zone's added on, the
+// way SF2 sums the two levels
+fn value(zone: &Zone, preset: &Zone, number: u16, default: i32) -> i32 {
+    zone.generator(number).map_or(default, i32::from)
+        + preset.generator(number).map_or(0, i32::from)
+}
+
+// an address generator, fine and coarse (32768 points) together
+fn offset(zone: &Zone, fine: u16, coarse: u16) -> i64 {
+    zone.generator(fine).map_or(0, i64::from) + zone.generator(coarse).map_or(0, i64::from) * 32768
+}
+
+struct Layer<'a> {
+    data: &'a [i16],
+    position: f64,
+    end: usize,
+    loop_start: usize,
+    loop_end: usize,
+    looping: bool,
+    // loops while the key is down, then plays on to the end of the sample
+    until_release: bool,
+    released: bool,
+    step: f64,
+    gains: [f32; 2],
+    envelope: Envelope,
+    done: bool,
+}
+
+impl Layer<'_> {
+    fn next(&mut self, pitch_ratio: f64) -> [f32; 2] {
+        if self.done {
+            return [0.0; 2];
+        }
+        let looping = self.looping && !(self.until_release && self.released);
+        let index = self.position as usize;
+        let after = if looping && index + 1 >= self.loop_end {
+            self.loop_start
+        } else {
+            index + 1
+        };
+        if index >= self.end || after >= self.end {
+            self.done = true;
+            return [0.0; 2];
+        }
+
+        let fraction = (self.position - index as f64) as f32;
+        let (a, b) = (self.data[index] as f32, self.data[after] as f32);
+        let point = (a + (b - a) * fraction) / 32768.0 * self.envelope.next();
+        self.position += self.step * pitch_ratio;
+        // a high note can step over the whole loop at once
+        if looping && self.position >= self.loop_end as f64 {
+            let length = (self.loop_end - self.loop_start) as f64;
+            self.position = self.loop_start as f64
+                + (self.position - self.loop_start as f64).rem_euclid(length);
+        }
+        self.done = self.envelope.finished();
+        [point * self.gains[0], point * self.gains[1]]
+    }
+}
+
+struct SamplerVoice<'a> {
+    layers: Vec<Layer<'a>>,
+}
+
+impl Voice for SamplerVoice<'_> {
+    fn next(&mut self, pitch_ratio: f64) -> [f32; 2] {
+        self.layers
+            .iter_mut()
+            .fold([0.0; 2], |[left, right], layer| {
+                let [l, r] = layer.next(pitch_ratio);
+                [left + l, right + r]
+            })
+    }
+
+    fn release(&mut self) {
+        for layer in &mut self.layers {
+            layer.released = true;
+            layer.envelope.release();
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.layers.iter().all(|l| l.done)
+    }
+}
+
+struct Sampler<'a> {
+    font: &'a SoundFont,
+}
+
+impl<'a> Sampler<'a> {
+    fn layer(&self, zone: &Zone, preset: &Zone, key: u8, velocity: u8, rate: f64) -> Layer<'a> {
+        let data = self.font.data();
+        let sample = &self.font.samples()[zone.index()];
+        let address = |base: u32, fine: u16, coarse: u16| {
+            (base as i64 + offset(zone, fine, coarse)).clamp(0, data.len() as i64) as usize
+        };
+        let start = address(sample.start, START_OFFSET, START_COARSE_OFFSET);
+        let end = address(sample.end, END_OFFSET, END_COARSE_OFFSET).max(start);
+        let loop_start = address(
+            sample.loop_start,
+            LOOP_START_OFFSET,
+            LOOP_START_COARSE_OFFSET,
+        )
+        .clamp(start, end);
+        let loop_end =
+            address(sample.loop_end, LOOP_END_OFFSET, LOOP_END_COARSE_OFFSET).clamp(start, end);
+        let modes = zone.generator(SAMPLE_MODES).unwrap_or(0) & 3;
+
+        // a fixed key or velocity in the zone wins over the played one
+        let key = match zone.generator(KEY) {
+            Some(fixed @ 0..=127) => fixed as i32,
+            _ => key as i32,
+        };
+        let velocity = match zone.generator(VELOCITY) {
+            Some(fixed @ 1..=127) => fixed as f32,
+            _ => velocity as f32,
+        };
+        let root = match zone.generator(ROOT_KEY) {
+            Some(root @ 0..=127) => root as i32,
+            _ => sample.original_pitch.min(127) as i32,
+        };
+        let cents = (key - root) * value(zone, preset, SCALE_TUNING, 100)
+            + value(zone, preset, COARSE_TUNE, 0) * 100
+            + value(zone, preset, FINE_TUNE, 0)
+            + sample.pitch_correction as i32;
+
+        let level = attenuation(value(zone, preset, ATTENUATION, 0)) * (velocity / 127.0).powi(2);
+        let pan = value(zone, preset, PAN, 0).clamp(-500, 500) as f32;
+        let angle = (pan + 500.0) / 1000.0 * FRAC_PI_2;
+        let adsr = Adsr {
+            attack: seconds(value(zone, preset, ATTACK, -12000)),
+            decay: seconds(value(zone, preset, DECAY, -12000)),
+            sustain: attenuation(value(zone, preset, SUSTAIN, 0)) as f64,
+            release: seconds(value(zone, preset, RELEASE, -12000)),
+        };
+
+        Layer {
+            data,
+            position: start as f64,
+            end,
+            loop_start,
+            loop_end,
+            looping: (modes == 1 || modes == 3) && loop_end > loop_start + 1,
+            until_release: modes == 3,
+            released: false,
+            step: (cents as f64 / 1200.0).exp2() * sample.sample_rate as f64 / rate,
+            gains: [level * angle.cos(), level * angle.sin()],
+            envelope: Envelope::new(adsr, rate),
+            done: false,
+        }
+    }
+}
+
+impl<'a> Instrument for Sampler<'a> {
+    type Voice = SamplerVoice<'a>;
+
+    fn voice(
+        &self,
+        channel: Channel,
+        state: &ChannelState,
+        note: Note,
+        velocity: Velocity,
+        sample_rate: f64,
+    ) -> Option<SamplerVoice<'a>> {
+        // percussion is always the drum bank, otherwise the bank MSB picks
+        // the bank unless only the LSB was sent
+        let bank = match (channel == Channel::DRUMS, state.bank >> 7) {
+            (true, _) => 128,
+            (false, 0) => state.bank & 0x7F,
+            (false, msb) => msb,
+        };
+        let preset = self.font.preset(bank, state.program)?;
+        let (key, velocity) = (note.value(), velocity.value());
+
+        let mut layers = Vec::new();
+        for preset_zone in preset.zones().iter().filter(|z| z.contains(key, velocity)) {
+            let instrument = &self.font.instruments()[preset_zone.index()];
+            for zone in instrument
+                .zones()
+                .iter()
+                .filter(|z| z.contains(key, velocity))
+            {
+                layers.push(self.layer(zone, preset_zone, key, velocity, sample_rate));
+            }
+        }
+        (!layers.is_empty()).then_some(SamplerVoice { layers })
+    }
+}
+
+impl Smf {
+    // The song played through the presets of a SoundFont, chosen by the
+    // program changes and bank selects of each channel
+    pub fn render_soundfont(
+        &self,
+        font: &SoundFont,
+        options: &AudioOptions,
+    ) -> Result<Audio, MidiError> {
+        play(self, &Sampler { font }, options)
+    }
+
+    // The song played through a SoundFont as a 16 bit WAV file
+    pub fn to_wav_with_soundfont(
+        &self,
+        font: &SoundFont,
+        options: &AudioOptions,
+    ) -> Result<Vec<u8>, MidiError> {
+        self.render_soundfont(font, options)?.to_wav()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::{Control, Program};
+    use crate::message::{ChannelMessage, MidiMessage};
+    use crate::render::soundfont::test::font_bytes;
+    use crate::track::{EventType, Track, TrackEvent, Vql};
+
+    // one note held for a second at 120 bpm, after the program and controls
+    fn song(channel: u8, controls: &[(u8, u8)], program: u8, note: u8) -> Smf {
+        let channel = Channel::new(channel).unwrap();
+        let message = |message| {
+            TrackEvent::new(
+                Vql::zero(),
+                EventType::Midi(MidiMessage::Channel { channel, message }),
+            )
+        };
+        let mut events: Vec<(u64, TrackEvent)> = controls
+            .iter()
+            .map(|(control, value)| {
+                let message = message(ChannelMessage::ControlChange {
+                    control: Control::new(*control).unwrap(),
+                    value: Control::new(*value).unwrap(),
+                });
+                (0, message)
+            })
+            .collect();
+        let program = Program::new(program).unwrap();
+        let (note, velocity) = (Note::new(note).unwrap(), Velocity::new(127).unwrap());
+        events.push((0, message(ChannelMessage::ProgramChange { program })));
+        events.push((0, TrackEvent::note_on(Vql::zero(), channel, note, velocity)));
+        events.push((
+            960,
+            TrackEvent::note_off(Vql::zero(), channel, note, velocity),
+        ));
+        events.push((960, TrackEvent::end_track()));
+
+        let mut smf = Smf::from_text("c").unwrap();
+        smf.tracks_mut()[0] = Track::from_absolute_events(events).unwrap();
+        smf
+    }
+
+    fn side(frames: &[[f32; 2]], side: usize) -> bool {
+        frames.iter().any(|f| f[side].abs() > 0.001)
+    }
+
+    #[test]
+    fn presets_from_programs_and_banks() {
+        let font = SoundFont::from_bytes(&font_bytes()).unwrap();
+        let options = AudioOptions::new().with_sample_rate(8000).with_tail(0.0);
+
+        // "Lead" is panned left and its loop keeps a 4 ms sample going
+        let audio = song(0, &[], 0, 69)
+            .render_soundfont(&font, &options)
+            .unwrap();
+        let frames = audio.frames();
+        assert_eq!(frames.len(), 8000);
+        assert!(side(frames, 0) && !side(frames, 1));
+        assert!(side(&frames[7000..], 0));
+        // A 440 crosses zero twice a cycle
+        let crossings = frames[..4000]
+            .windows(2)
+            .filter(|w| (w[0][0] > 0.0) != (w[1][0] > 0.0))
+            .count();
+        assert!((420..=460).contains(&crossings), "{crossings}");
+
+        // bank 1 program 5 is "Right"
+        let audio = song(1, &[(0, 1), (32, 0)], 5, 69)
+            .render_soundfont(&font, &options)
+            .unwrap();
+        assert!(!side(audio.frames(), 0) && side(audio.frames(), 1));
+
+        // the kit doesn't loop, played low its 32 points last 123 frames
+        let audio = song(9, &[], 0, 36)
+            .render_soundfont(&font, &options)
+            .unwrap();
+        assert!(side(&audio.frames()[..10], 1));
+        assert!(!side(&audio.frames()[200..], 1));
+
+        // out of the instrument's key range
+        let audio = song(0, &[], 0, 100)
+            .render_soundfont(&font, &options)
+            .unwrap();
+        assert_eq!(audio.peak(), 0.0);
+
+        // stepping further than the loop is long stays inside it
+        let zone = &font.instruments()[0].zones()[0];
+        let mut layer = Sampler { font: &font }.layer(zone, zone, 127, 127, 8000.0);
+        assert!(layer.step > 32.0);
+        assert!((0..1000).any(|_| layer.next(1.0)[0] != 0.0));
+        assert!(!layer.done);
+
+        let wav = song(0, &[], 0, 60)
+            .to_wav_with_soundfont(&font, &options)
+            .unwrap();
+        assert_eq!(wav.len(), 44 + 8000 * 4);
+    }
+}
@@ -0,0 +1,572 @@
+use crate::error::MidiError;
+
+// SoundFont 2 banks, see the SoundFont Technical Specification 2.04
+//
+// A font is a RIFF file with the sample data and the "hydra": presets made
+// of zones pointing at instruments, which are made of zones pointing at
+// samples. The global zone of a preset or instrument is folded into its
+// other zones while loading, so every zone carries all its generators.
+// Modulators are not read, the sampler does what the default ones would.
+
+// the generators with a meaning of their own here, by SF2 number
+pub(crate) const START_OFFSET: u16 = 0;
+pub(crate) const END_OFFSET: u16 = 1;
+pub(crate) const LOOP_START_OFFSET: u16 = 2;
+pub(crate) const LOOP_END_OFFSET: u16 = 3;
+pub(crate) const START_COARSE_OFFSET: u16 = 4;
+pub(crate) const END_COARSE_OFFSET: u16 = 12;
+pub(crate) const PAN: u16 = 17;
+pub(crate) const ATTACK: u16 = 34;
+pub(crate) const DECAY: u16 = 36;
+pub(crate) const SUSTAIN: u16 = 37;
+pub(crate) const RELEASE: u16 = 38;
+const INSTRUMENT: u16 = 41;
+const KEY_RANGE: u16 = 43;
+const VELOCITY_RANGE: u16 = 44;
+pub(crate) const LOOP_START_COARSE_OFFSET: u16 = 45;
+pub(crate) const KEY: u16 = 46;
+pub(crate) const VELOCITY: u16 = 47;
+pub(crate) const ATTENUATION: u16 = 48;
+pub(crate) const LOOP_END_COARSE_OFFSET: u16 = 50;
+pub(crate) const COARSE_TUNE: u16 = 51;
+pub(crate) const FINE_TUNE: u16 = 52;
+const SAMPLE: u16 = 53;
+pub(crate) const SAMPLE_MODES: u16 = 54;
+pub(crate) const SCALE_TUNING: u16 = 56;
+pub(crate) const ROOT_KEY: u16 = 58;
+
+// samples in ROM aren't in the file
+const ROM_SAMPLE: u16 = 0x8000;
+
+// A zone of a preset or an instrument: the keys and velocities it plays
+// and its generators as (number, amount). index is the instrument of a
+// preset zone or the sample of an instrument zone
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    keys: (u8, u8),
+    velocities: (u8, u8),
+    generators: Vec<(u16, i16)>,
+    index: usize,
+}
+
+impl Zone {
+    pub fn key_range(&self) -> (u8, u8) {
+        self.keys
+    }
+
+    pub fn velocity_range(&self) -> (u8, u8) {
+        self.velocities
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn generator(&self, number: u16) -> Option<i16> {
+        self.generators
+            .iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, amount)| *amount)
+    }
+
+    pub fn contains(&self, key: u8, velocity: u8) -> bool {
+        (self.keys.0..=self.keys.1).contains(&key)
+            && (self.velocities.0..=self.velocities.1).contains(&velocity)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    name: String,
+    bank: u16,
+    program: u16,
+    zones: Vec<Zone>,
+}
+
+impl Preset {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // 128 is the percussion bank
+    pub fn bank(&self) -> u16 {
+        self.bank
+    }
+
+    pub fn program(&self) -> u16 {
+        self.program
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoundFontInstrument {
+    name: String,
+    zones: Vec<Zone>,
+}
+
+impl SoundFontInstrument {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+}
+
+// Where a sample sits in the sample data, in sample points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub start: u32,
+    pub end: u32,
+    pub loop_start: u32,
+    pub loop_end: u32,
+    pub sample_rate: u32,
+    pub original_pitch: u8,
+    // in cents
+    pub pitch_correction: i8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SoundFont {
+    name: String,
+    presets: Vec<Preset>,
+    instruments: Vec<SoundFontInstrument>,
+    samples: Vec<Sample>,
+    data: Vec<i16>,
+}
+
+fn invalid(message: &str) -> MidiError {
+    MidiError::InvalidSoundFont(message.to_string())
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+// names are zero padded to 20 bytes
+fn name_at(bytes: &[u8], at: usize) -> String {
+    let name = &bytes[at..at + 20];
+    let end = name.iter().position(|b| *b == 0).unwrap_or(20);
+    String::from_utf8_lossy(&name[..end]).trim().to_string()
+}
+
+// a RIFF chunk's id and data
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+// the chunks one after the other, each padded to an even length
+fn chunks(mut bytes: &[u8]) -> Result<Vec<Chunk<'_>>, MidiError> {
+    let mut chunks = Vec::new();
+    while bytes.len() >= 8 {
+        let id: [u8; 4] = bytes[..4].try_into().unwrap();
+        let length = u32_at(bytes, 4) as usize;
+        let data = bytes
+            .get(8..8 + length)
+            .ok_or_else(|| invalid("a chunk runs past the end of the file"))?;
+        chunks.push((id, data));
+        bytes = bytes.get(8 + length + length % 2..).unwrap_or(&[]);
+    }
+    Ok(chunks)
+}
+
+// the records of a hydra chunk, which ends with a terminal one
+fn records<'a>(pdta: &[Chunk<'a>], id: &[u8; 4], size: usize) -> Result<Vec<&'a [u8]>, MidiError> {
+    let name = String::from_utf8_lossy(id);
+    let (_, data) = pdta
+        .iter()
+        .find(|(i, _)| i == id)
+        .ok_or_else(|| invalid(&format!("no {name} chunk")))?;
+    if data.len() % size != 0 || data.len() < size * 2 {
+        return Err(invalid(&format!("the {name} chunk has the wrong size")));
+    }
+    Ok(data.chunks(size).collect())
+}
+
+// The zones of the presets or instruments in headers, where bag_at finds
+// the first bag of a header. A zone must end with the generator in
+// terminal, the first zone without it is the global one
+fn zones(
+    headers: &[&[u8]],
+    bag_at: usize,
+    bags: &[&[u8]],
+    generators: &[&[u8]],
+    terminal: u16,
+) -> Result<Vec<Vec<Zone>>, MidiError> {
+    let mut all = Vec::new();
+    for pair in headers.windows(2) {
+        let (first, last) = (
+            u16_at(pair[0], bag_at) as usize,
+            u16_at(pair[1], bag_at) as usize,
+        );
+        if first > last || last >= bags.len() {
+            return Err(invalid("a zone index is out of range"));
+        }
+
+        let mut global: Option<Zone> = None;
+        let mut zones = Vec::new();
+        for number in first..last {
+            let (from, to) = (
+                u16_at(bags[number], 0) as usize,
+                u16_at(bags[number + 1], 0) as usize,
+            );
+            if from > to || to >= generators.len() {
+                return Err(invalid("a generator index is out of range"));
+            }
+            let mut zone = global.clone().unwrap_or(Zone {
+                keys: (0, 127),
+                velocities: (0, 127),
+                generators: Vec::new(),
+                index: 0,
+            });
+            let mut index = None;
+            for generator in &generators[from..to] {
+                let (number, amount) = (u16_at(generator, 0), u16_at(generator, 2));
+                match number {
+                    KEY_RANGE => zone.keys = (generator[2], generator[3]),
+                    VELOCITY_RANGE => zone.velocities = (generator[2], generator[3]),
+                    n if n == terminal => index = Some(amount as usize),
+                    _ => {
+                        zone.generators.retain(|(n, _)| *n != number);
+                        zone.generators.push((number, amount as i16));
+                    }
+                }
+            }
+            match index {
+                Some(index) => {
+                    zone.index = index;
+                    zones.push(zone);
+                }
+                None if number == first => global = Some(zone),
+                // a zone going nowhere is ignored
+                None => {}
+            }
+        }
+        all.push(zones);
+    }
+    Ok(all)
+}
+
+impl SoundFont {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MidiError> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"sfbk" {
+            return Err(invalid("not a RIFF sfbk file"));
+        }
+        // the length counts the "sfbk" too
+        let length = u32_at(bytes, 4) as usize;
+        if length < 4 {
+            return Err(invalid("the RIFF chunk is too short"));
+        }
+        let body = bytes.get(12..8 + length).unwrap_or(&bytes[12..]);
+        let mut lists = std::collections::HashMap::new();
+        for (id, data) in chunks(body)? {
+            if &id == b"LIST" && data.len() >= 4 {
+                lists.insert([data[0], data[1], data[2], data[3]], chunks(&data[4..])?);
+            }
+        }
+
+        let name = lists
+            .get(b"INFO")
+            .and_then(|info| info.iter().find(|(id, _)| id == b"INAM"))
+            .map(|(_, data)| {
+                let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+                String::from_utf8_lossy(&data[..end]).trim().to_string()
+            })
+            .unwrap_or_default();
+        let data: Vec<i16> = lists
+            .get(b"sdta")
+            .and_then(|sdta| sdta.iter().find(|(id, _)| id == b"smpl"))
+            .map(|(_, data)| {
+                data.chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let pdta = lists.get(b"pdta").ok_or_else(|| invalid("no pdta list"))?;
+
+        let phdr = records(pdta, b"phdr", 38)?;
+        let pbag = records(pdta, b"pbag", 4)?;
+        let pgen = records(pdta, b"pgen", 4)?;
+        let inst = records(pdta, b"inst", 22)?;
+        let ibag = records(pdta, b"ibag", 4)?;
+        let igen = records(pdta, b"igen", 4)?;
+        let shdr = records(pdta, b"shdr", 46)?;
+
+        let samples: Vec<Sample> = shdr[..shdr.len() - 1]
+            .iter()
+            .map(|r| Sample {
+                name: name_at(r, 0),
+                start: u32_at(r, 20),
+                end: u32_at(r, 24),
+                loop_start: u32_at(r, 28),
+                loop_end: u32_at(r, 32),
+                sample_rate: u32_at(r, 36),
+                original_pitch: r[40],
+                pitch_correction: r[41] as i8,
+            })
+            .collect();
+        // ROM samples and ones past the data can't be played, the zones
+        // using them are dropped
+        let playable: Vec<bool> = shdr[..shdr.len() - 1]
+            .iter()
+            .zip(&samples)
+            .map(|(r, s)| {
+                u16_at(r, 44) & ROM_SAMPLE == 0
+                    && s.start < s.end
+                    && s.end as usize <= data.len()
+                    && s.sample_rate > 0
+            })
+            .collect();
+
+        let instruments = zones(&inst, 20, &ibag, &igen, SAMPLE)?
+            .into_iter()
+            .zip(&inst)
+            .map(|(zones, r)| SoundFontInstrument {
+                name: name_at(r, 0),
+                zones: zones
+                    .into_iter()
+                    .filter(|z| playable.get(z.index) == Some(&true))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        let presets = zones(&phdr, 24, &pbag, &pgen, INSTRUMENT)?
+            .into_iter()
+            .zip(&phdr)
+            .map(|(zones, r)| Preset {
+                name: name_at(r, 0),
+                program: u16_at(r, 20),
+                bank: u16_at(r, 22),
+                zones: zones
+                    .into_iter()
+                    .filter(|z| z.index < instruments.len())
+                    .collect(),
+            })
+            .collect();
+
+        Ok(Self {
+            name,
+            presets,
+            instruments,
+            samples,
+            data,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn presets(&self) -> &[Preset] {
+        &self.presets
+    }
+
+    pub fn instruments(&self) -> &[SoundFontInstrument] {
+        &self.instruments
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    // all the sample points of the font, the samples are slices of it
+    pub fn data(&self) -> &[i16] {
+        &self.data
+    }
+
+    // The preset for a bank and program, like a General MIDI player falls
+    // back: the same program in the first bank (or the standard kit for
+    // percussion), then the first preset of the bank
+    pub fn preset(&self, bank: u16, program: u8) -> Option<&Preset> {
+        let find = |bank: u16, program: u16| {
+            self.presets
+                .iter()
+                .find(|p| p.bank == bank && p.program == program)
+        };
+        let first_bank = if bank >= 128 { 128 } else { 0 };
+        find(bank, program as u16)
+            .or_else(|| find(first_bank, program as u16))
+            .or_else(|| find(first_bank, 0))
+            .or_else(|| self.presets.iter().find(|p| p.bank == first_bank))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn list(kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = kind.to_vec();
+        data.extend(chunks.concat());
+        chunk(b"LIST", &data)
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(20, 0);
+        bytes
+    }
+
+    fn generators(generators: &[(u16, i16)]) -> Vec<u8> {
+        generators
+            .iter()
+            .flat_map(|(n, a)| [n.to_le_bytes(), a.to_le_bytes()].concat())
+            .collect()
+    }
+
+    fn bags(starts: &[u16]) -> Vec<u8> {
+        starts
+            .iter()
+            .flat_map(|s| [s.to_le_bytes(), [0, 0]].concat())
+            .collect()
+    }
+
+    fn range(low: u8, high: u8) -> i16 {
+        i16::from_le_bytes([low, high])
+    }
+
+    // A square wave sample looped all the way, 32 points at 14080 Hz so it
+    // plays A 440 at its root key 69. "Lead" (0:0) has it hard left in a
+    // global zone, "Right" (1:5) and "Kit" (128:0) hard right, the kit
+    // without the loop
+    pub(crate) fn font_bytes() -> Vec<u8> {
+        let smpl: Vec<u8> = (0..32)
+            .flat_map(|i| (if i < 16 { 16000i16 } else { -16000 }).to_le_bytes())
+            .collect();
+
+        let mut phdr = Vec::new();
+        for (preset, program, bank, bag) in [
+            ("Lead", 0u16, 0u16, 0u16),
+            ("Right", 5, 1, 2),
+            ("Kit", 0, 128, 3),
+            ("EOP", 0, 0, 4),
+        ] {
+            phdr.extend(name(preset));
+            phdr.extend([program.to_le_bytes(), bank.to_le_bytes(), bag.to_le_bytes()].concat());
+            phdr.extend([0; 12]);
+        }
+        let pgen = generators(&[
+            (PAN, -500),
+            (KEY_RANGE, range(0, 127)),
+            (INSTRUMENT, 0),
+            (PAN, 500),
+            (INSTRUMENT, 0),
+            (PAN, 500),
+            (INSTRUMENT, 1),
+            (0, 0),
+        ]);
+
+        let mut inst = Vec::new();
+        for (instrument, bag) in [("Square", 0u16), ("Drum", 1), ("EOI", 2)] {
+            inst.extend(name(instrument));
+            inst.extend(bag.to_le_bytes());
+        }
+        let igen = generators(&[
+            (KEY_RANGE, range(40, 90)),
+            (SAMPLE_MODES, 1),
+            (SAMPLE, 0),
+            (SAMPLE, 0),
+            (0, 0),
+        ]);
+
+        let mut shdr = Vec::new();
+        for (sample, end) in [("Square", 32u32), ("EOS", 0)] {
+            shdr.extend(name(sample));
+            for value in [0, end, 0, end, if end > 0 { 14080 } else { 0 }] {
+                shdr.extend(value.to_le_bytes());
+            }
+            shdr.extend([69, 0, 0, 0, 1, 0]);
+        }
+
+        let mut body = b"sfbk".to_vec();
+        body.extend(list(
+            b"INFO",
+            &[chunk(b"ifil", &[2, 0, 1, 0]), chunk(b"INAM", b"Test\0")],
+        ));
+        body.extend(list(b"sdta", &[chunk(b"smpl", &smpl)]));
+        body.extend(list(
+            b"pdta",
+            &[
+                chunk(b"phdr", &phdr),
+                chunk(b"pbag", &bags(&[0, 1, 3, 5, 7])),
+                chunk(b"pmod", &[0; 10]),
+                chunk(b"pgen", &pgen),
+                chunk(b"inst", &inst),
+                chunk(b"ibag", &bags(&[0, 3, 4])),
+                chunk(b"imod", &[0; 10]),
+                chunk(b"igen", &igen),
+                chunk(b"shdr", &shdr),
+            ],
+        ));
+        chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn presets_instruments_and_samples() {
+        let font = SoundFont::from_bytes(&font_bytes()).unwrap();
+        assert_eq!(font.name(), "Test");
+        assert_eq!(font.data().len(), 32);
+
+        let names: Vec<(&str, u16, u16)> = font
+            .presets()
+            .iter()
+            .map(|p| (p.name(), p.bank(), p.program()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("Lead", 0, 0), ("Right", 1, 5), ("Kit", 128, 0)]
+        );
+        // the global zone is folded into the other one
+        let lead = &font.presets()[0];
+        assert_eq!(lead.zones().len(), 1);
+        assert_eq!(lead.zones()[0].generator(PAN), Some(-500));
+        assert_eq!(lead.zones()[0].index(), 0);
+
+        let square = &font.instruments()[0];
+        assert_eq!(square.name(), "Square");
+        assert_eq!(square.zones()[0].key_range(), (40, 90));
+        assert!(!square.zones()[0].contains(91, 100));
+        assert_eq!(square.zones()[0].generator(SAMPLE_MODES), Some(1));
+        assert_eq!(
+            font.instruments()[1].zones()[0].generator(SAMPLE_MODES),
+            None
+        );
+
+        let sample = &font.samples()[0];
+        assert_eq!((sample.start, sample.end, sample.loop_end), (0, 32, 32));
+        assert_eq!((sample.sample_rate, sample.original_pitch), (14080, 69));
+
+        assert_eq!(font.preset(1, 5).unwrap().name(), "Right");
+        assert_eq!(font.preset(3, 0).unwrap().name(), "Lead");
+        assert_eq!(font.preset(128, 25).unwrap().name(), "Kit");
+
+        assert!(matches!(
+            SoundFont::from_bytes(b"RIFF\x04\x00\x00\x00WAVE"),
+            Err(MidiError::InvalidSoundFont(_))
+        ));
+        assert!(matches!(
+            SoundFont::from_bytes(b"RIFF\0\0\0\0sfbk"),
+            Err(MidiError::InvalidSoundFont(_))
+        ));
+        let mut broken = font_bytes();
+        broken.truncate(broken.len() - 60);
+        assert!(SoundFont::from_bytes(&broken).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use crate::error::MidiError;
+use crate::meta::MetaEvent;
+use crate::smf::Smf;
+use crate::track::EventType;
+use std::fmt::Write;
+
+use super::meter::Meters;
+
+// one per track or channel, going round when there are more
+const PALETTE: [&str; 12] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf", "#393b79", "#637939",
+];
+const MARKER: u8 = 0x06;
+const LEFT: f64 = 36.0;
+const TOP: f64 = 32.0;
+const MARGIN: f64 = 8.0;
+// beat and bar lines drawn at most, past them the roll has no grid
+const MAX_GRID_LINES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorBy {
+    #[default]
+    Track,
+    Channel,
+}
+
+// How a piano roll is drawn, e.g.
+// PianoRollOptions::default().with_pixels_per_beat(60.0).with_color_by(ColorBy::Channel)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PianoRollOptions {
+    pixels_per_beat: f64,
+    note_height: f64,
+    color_by: ColorBy,
+}
+
+impl Default for PianoRollOptions {
+    fn default() -> Self {
+        Self {
+            pixels_per_beat: 40.0,
+            note_height: 6.0,
+            color_by: ColorBy::Track,
+        }
+    }
+}
+
+impl PianoRollOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_pixels_per_beat(mut self, pixels: f64) -> Self {
+        self.pixels_per_beat = pixels.max(1.0);
+        self
+    }
+
+    pub fn with_note_height(mut self, pixels: f64) -> Self {
+        self.note_height = pixels.max(1.0);
+        self
+    }
+
+    pub fn with_color_by(mut self, color_by: ColorBy) -> Self {
+        self.color_by = color_by;
+        self
+    }
+}
+
+// numbers with at most two decimals, so the output is stable for snapshots
+fn num(value: f64) -> String {
+    let text = format!("{:.2}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Smf {
+    // The song as an SVG piano roll: pitch goes up, time goes right with a
+    // line on every beat and a stronger one on every bar as the time
+    // signatures say. Notes are coloured by track or channel and get more
+    // opaque the louder they are, tempo changes and markers are written
+    // above the roll.
+    pub fn to_svg(&self, options: &PianoRollOptions) -> Result<String, MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)? as f64;
+        let x = |tick: u64| LEFT + tick as f64 / ticks_per_beat * options.pixels_per_beat;
+
+        let mut notes = Vec::new();
+        for (index, track) in self.tracks().iter().enumerate() {
+            notes.extend(track.note_spans().into_iter().map(|s| (index, s)));
+        }
+        let end = self
+            .tracks()
+            .iter()
+            .map(|t| t.end_tick())
+            .chain(notes.iter().map(|(_, s)| s.end))
+            .max()
+            .unwrap_or(0);
+        let lowest = notes
+            .iter()
+            .map(|(_, s)| s.note.value())
+            .min()
+            .unwrap_or(60);
+        let highest = notes
+            .iter()
+            .map(|(_, s)| s.note.value())
+            .max()
+            .unwrap_or(72);
+        let rows = (highest - lowest) as f64 + 1.0;
+        let y = |note: u8| TOP + (highest - note) as f64 * options.note_height;
+
+        let mut metas: Vec<(u64, MetaEvent)> = self
+            .tracks()
+            .iter()
+            .flat_map(|t| t.absolute_events())
+            .filter_map(|(tick, e)| match &e.event {
+                EventType::Meta(meta) => Some((tick, meta.clone())),
+                _ => None,
+            })
+            .collect();
+        metas.sort_by_key(|(tick, _)| *tick);
+
+        let width = x(end) + MARGIN;
+        let height = TOP + rows * options.note_height + MARGIN;
+        let bottom = TOP + rows * options.note_height;
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"9\">",
+            w = num(width),
+            h = num(height)
+        );
+        let _ = writeln!(
+            svg,
+            "<rect width=\"{}\" height=\"{}\" fill=\"#ffffff\"/>",
+            num(width),
+            num(height)
+        );
+
+        // black keys shaded, C labelled
+        for note in lowest..=highest {
+            if [1, 3, 6, 8, 10].contains(&(note % 12)) {
+                let _ = writeln!(
+                    svg,
+                    "<rect class=\"key\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#f0f0f0\"/>",
+                    num(LEFT),
+                    num(y(note)),
+                    num(x(end) - LEFT),
+                    num(options.note_height)
+                );
+            }
+            if note % 12 == 0 {
+                let _ = writeln!(
+                    svg,
+                    "<text x=\"2\" y=\"{}\">C{}</text>",
+                    num(y(note) + options.note_height),
+                    note as i32 / 12 - 1
+                );
+            }
+        }
+
+        // beats and bars, from the time signatures. A change in the
+        // middle of a bar starts a new one
+        let meters = Meters::new(self.tracks().iter(), ticks_per_beat as u32);
+        for (tick, bar) in meters.beats(end).take(MAX_GRID_LINES) {
+            match bar {
+                Some(bar) => {
+                    let _ = writeln!(
+                        svg,
+                        "<line class=\"bar\" x1=\"{x}\" y1=\"{}\" x2=\"{x}\" y2=\"{}\" stroke=\"#888888\" stroke-width=\"1\"/>",
+                        num(TOP),
+                        num(bottom),
+                        x = num(x(tick))
+                    );
+                    let _ = writeln!(
+                        svg,
+                        "<text x=\"{}\" y=\"{}\">{bar}</text>",
+                        num(x(tick) + 2.0),
+                        num(TOP - 2.0)
+                    );
+                }
+                None => {
+                    let _ = writeln!(
+                        svg,
+                        "<line class=\"beat\" x1=\"{x}\" y1=\"{}\" x2=\"{x}\" y2=\"{}\" stroke=\"#dddddd\" stroke-width=\"0.5\"/>",
+                        num(TOP),
+                        num(bottom),
+                        x = num(x(tick))
+                    );
+                }
+            }
+        }
+
+        for (index, span) in &notes {
+            let color = match options.color_by {
+                ColorBy::Track => PALETTE[index % PALETTE.len()],
+                ColorBy::Channel => PALETTE[span.channel.value() as usize % PALETTE.len()],
+            };
+            // quiet notes stay visible
+            let opacity = 0.25 + 0.75 * span.velocity.value() as f64 / 127.0;
+            let _ = writeln!(
+                svg,
+                "<rect class=\"note\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{color}\" fill-opacity=\"{}\"/>",
+                num(x(span.start)),
+                num(y(span.note.value())),
+                num((x(span.end) - x(span.start)).max(1.0)),
+                num(options.note_height),
+                num(opacity)
+            );
+        }
+
+        // tempo on the first line above the roll, markers on the second
+        for (tick, meta) in &metas {
+            match meta {
+                MetaEvent::SetTempo(tempo) if *tempo > 0 => {
+                    let _ = writeln!(
+                        svg,
+                        "<text class=\"tempo\" x=\"{}\" y=\"10\">\u{2669}={}</text>",
+                        num(x(*tick) + 2.0),
+                        num(60_000_000.0 / *tempo as f64)
+                    );
+                }
+                MetaEvent::Unknown { event_type, data } if *event_type == MARKER => {
+                    let _ = writeln!(
+                        svg,
+                        "<line class=\"marker\" x1=\"{x}\" y1=\"12\" x2=\"{x}\" y2=\"{}\" stroke=\"#d62728\" stroke-dasharray=\"2,2\"/>",
+                        num(bottom),
+                        x = num(x(*tick))
+                    );
+                    let _ = writeln!(
+                        svg,
+                        "<text class=\"marker\" x=\"{}\" y=\"20\" fill=\"#d62728\">{}</text>",
+                        num(x(*tick) + 2.0),
+                        escape(&String::from_utf8_lossy(data))
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        svg.push_str("</svg>\n");
+        Ok(svg)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::track::TrackEvent;
+
+    fn song() -> Smf {
+        let mut smf = Smf::from_text("t100 l4 c e g > c\ntrack Bass ch3 o2 !pp c1").unwrap();
+        let conductor = &mut smf.tracks_mut()[0];
+        let mut events: Vec<(u64, TrackEvent)> = conductor
+            .absolute_events()
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+        events.push((
+            960,
+            TrackEvent::meta_event(MetaEvent::Unknown {
+                event_type: MARKER,
+                data: b"Verse & more".to_vec(),
+            }),
+        ));
+        events.push((
+            0,
+            TrackEvent::meta_event(MetaEvent::TimeSignature {
+                numerator: 2,
+                denominator: 4,
+                clocks_per_tick: 24,
+                thirty_seconds_per_24_clocks: 8,
+            }),
+        ));
+        conductor.set_absolute_events(events).unwrap();
+        smf
+    }
+
+    #[test]
+    fn notes_grid_and_annotations() {
+        let svg = song().to_svg(&PianoRollOptions::default()).unwrap();
+
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"204\""));
+        assert_eq!(svg.matches("class=\"note\"").count(), 5);
+        // four beats in 2/4: bars at 0 and 2, and the closing line at 4
+        assert_eq!(svg.matches("class=\"bar\"").count(), 3);
+        assert_eq!(svg.matches("class=\"beat\"").count(), 2);
+        assert!(svg.contains(">\u{2669}=100</text>"));
+        assert!(svg.contains(">Verse &amp; more</text>"));
+        // the bass is on the second track and a lot quieter
+        assert!(svg.contains("fill=\"#ff7f0e\" fill-opacity=\"0.44\""));
+        assert!(svg.contains("fill=\"#1f77b4\" fill-opacity=\"0.84\""));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn colour_by_channel_and_scale() {
+        let smf = song();
+        let options = PianoRollOptions::new()
+            .with_color_by(ColorBy::Channel)
+            .with_pixels_per_beat(10.0)
+            .with_note_height(4.0);
+        let svg = smf.to_svg(&options).unwrap();
+        // channel 2 is the third colour, a whole note is 40 pixels
+        assert!(svg.contains("width=\"40\" height=\"4\" fill=\"#2ca02c\""));
+        assert_eq!(svg, smf.to_svg(&options).unwrap());
+    }
+
+    #[test]
+    fn grid_is_capped() {
+        let mut smf = Smf::from_text("c4").unwrap();
+        let track = &mut smf.tracks_mut()[0];
+        let mut events: Vec<(u64, TrackEvent)> = track
+            .absolute_events()
+            .filter(|(_, e)| e.event != EventType::Meta(MetaEvent::EndOfTrack))
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+        events.push((480 * 500_000, TrackEvent::end_track()));
+        track.set_absolute_events(events).unwrap();
+
+        let svg = smf.to_svg(&PianoRollOptions::default()).unwrap();
+        let lines = svg.matches("class=\"bar\"").count() + svg.matches("class=\"beat\"").count();
+        assert_eq!(lines, MAX_GRID_LINES);
+    }
+}
@@ -0,0 +1,357 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Velocity};
+use crate::error::MidiError;
+use crate::render::audio::{
+    play, Adsr, Audio, AudioOptions, ChannelState, Envelope, Instrument, Voice,
+};
+use crate::smf::Smf;
+use std::f64::consts::TAU;
+
+// A small built-in synthesizer, good enough to audition a song. Each
+// General MIDI program family (eight programs) gets an oscillator and an
+// envelope, and channel 10 is a drum kit made of noise and pitch sweeps.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wave {
+    Sine,
+    Triangle,
+    Square,
+    Saw,
+    Noise,
+}
+
+impl Wave {
+    // the bright waves are a lot louder than a sine
+    fn level(&self) -> f32 {
+        match self {
+            Wave::Sine | Wave::Triangle => 1.0,
+            Wave::Square => 0.35,
+            Wave::Saw => 0.45,
+            Wave::Noise => 0.3,
+        }
+    }
+}
+
+const fn patch(wave: Wave, attack: f64, decay: f64, sustain: f64, release: f64) -> (Wave, Adsr) {
+    (
+        wave,
+        Adsr {
+            attack,
+            decay,
+            sustain,
+            release,
+        },
+    )
+}
+
+// by program / 8: piano, chromatic percussion, organ, guitar, bass,
+// strings, ensemble, brass, reed, pipe, synth lead, synth pad, synth
+// effects, ethnic, percussive and sound effects
+const PATCHES: [(Wave, Adsr); 16] = [
+    patch(Wave::Triangle, 0.005, 1.5, 0.0, 0.3),
+    patch(Wave::Sine, 0.002, 1.0, 0.0, 0.5),
+    patch(Wave::Square, 0.01, 0.05, 0.9, 0.08),
+    patch(Wave::Saw, 0.005, 0.8, 0.0, 0.2),
+    patch(Wave::Triangle, 0.005, 0.4, 0.6, 0.1),
+    patch(Wave::Saw, 0.15, 0.2, 0.8, 0.4),
+    patch(Wave::Saw, 0.2, 0.3, 0.8, 0.5),
+    patch(Wave::Saw, 0.04, 0.2, 0.7, 0.15),
+    patch(Wave::Square, 0.03, 0.1, 0.8, 0.1),
+    patch(Wave::Sine, 0.05, 0.1, 0.9, 0.15),
+    patch(Wave::Square, 0.01, 0.1, 0.8, 0.1),
+    patch(Wave::Triangle, 0.4, 0.5, 0.8, 0.8),
+    patch(Wave::Sine, 0.2, 0.5, 0.6, 1.0),
+    patch(Wave::Saw, 0.005, 0.5, 0.2, 0.3),
+    patch(Wave::Sine, 0.002, 0.3, 0.0, 0.2),
+    patch(Wave::Noise, 0.05, 0.2, 0.5, 0.3),
+];
+
+fn frequency(note: Note) -> f64 {
+    440.0 * ((note.value() as f64 - 69.0) / 12.0).exp2()
+}
+
+struct SynthVoice {
+    wave: Wave,
+    frequency: f64,
+    // Hz above frequency at the start, falling away for the drum thumps
+    sweep: f64,
+    sweep_fall: f64,
+    // how much noise is mixed in with the tone, and whether it is thinned
+    // out to a hiss
+    noise: f32,
+    hiss: bool,
+    seed: u32,
+    last_noise: f32,
+    // drums play out whatever the note off says and don't bend
+    one_shot: bool,
+    phase: f64,
+    sample_rate: f64,
+    level: f32,
+    envelope: Envelope,
+}
+
+impl SynthVoice {
+    fn new(wave: Wave, frequency: f64, adsr: Adsr, level: f32, sample_rate: f64) -> Self {
+        Self {
+            wave,
+            frequency,
+            sweep: 0.0,
+            sweep_fall: 0.0,
+            noise: if wave == Wave::Noise { 1.0 } else { 0.0 },
+            hiss: false,
+            seed: 0x9E37_79B9 ^ (frequency.to_bits() as u32 | 1),
+            last_noise: 0.0,
+            one_shot: false,
+            phase: 0.0,
+            sample_rate,
+            level: level * wave.level(),
+            envelope: Envelope::new(adsr, sample_rate),
+        }
+    }
+
+    // an envelope that only decays, over seconds
+    fn drum(frequency: f64, seconds: f64, level: f32, sample_rate: f64) -> Self {
+        let adsr = Adsr {
+            attack: 0.001,
+            decay: seconds,
+            sustain: 0.0,
+            release: seconds,
+        };
+        let mut voice = Self::new(Wave::Sine, frequency, adsr, level, sample_rate);
+        voice.one_shot = true;
+        voice
+    }
+
+    fn with_sweep(mut self, hertz: f64, seconds: f64) -> Self {
+        self.sweep = hertz;
+        self.sweep_fall = (-1.0 / (seconds * self.sample_rate)).exp();
+        self
+    }
+
+    fn with_noise(mut self, amount: f32, hiss: bool) -> Self {
+        self.noise = amount;
+        self.hiss = hiss;
+        self
+    }
+
+    // xorshift, so renders come out the same every time
+    fn white(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+impl Voice for SynthVoice {
+    fn next(&mut self, pitch_ratio: f64) -> [f32; 2] {
+        let ratio = if self.one_shot { 1.0 } else { pitch_ratio };
+        let frequency = (self.frequency + self.sweep) * ratio;
+        self.sweep *= self.sweep_fall;
+        self.phase = (self.phase + frequency / self.sample_rate).fract();
+
+        let p = self.phase;
+        let tone = match self.wave {
+            Wave::Sine | Wave::Noise => (p * TAU).sin(),
+            Wave::Triangle => 1.0 - 4.0 * (p - 0.5).abs(),
+            Wave::Square => {
+                if p < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Wave::Saw => 2.0 * p - 1.0,
+        } as f32;
+        let mut noise = 0.0;
+        if self.noise > 0.0 {
+            let white = self.white();
+            noise = if self.hiss {
+                // the difference of two samples keeps only the top end
+                let high = (white - self.last_noise) * 0.5;
+                self.last_noise = white;
+                high
+            } else {
+                white
+            };
+        }
+
+        let sample =
+            (tone * (1.0 - self.noise) + noise * self.noise) * self.level * self.envelope.next();
+        [sample, sample]
+    }
+
+    fn release(&mut self) {
+        if !self.one_shot {
+            self.envelope.release();
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.envelope.finished()
+    }
+}
+
+// The General MIDI kit, roughly: kicks and toms are sine thumps, snares
+// and claps noise over a tone, hats and cymbals hiss
+fn drum(note: Note, level: f32, rate: f64) -> SynthVoice {
+    match note.value() {
+        35 | 36 => SynthVoice::drum(50.0, 0.35, level * 1.5, rate).with_sweep(120.0, 0.03),
+        38 | 40 => SynthVoice::drum(190.0, 0.2, level, rate).with_noise(0.65, false),
+        37 | 39 => SynthVoice::drum(1000.0, 0.08, level, rate).with_noise(0.9, false),
+        // toms go up with the note
+        41 | 43 | 45 | 47 | 48 | 50 => {
+            let pitch = 80.0 + (note.value() - 41) as f64 * 15.0;
+            SynthVoice::drum(pitch, 0.3, level, rate).with_sweep(pitch * 0.6, 0.05)
+        }
+        42 | 44 => SynthVoice::drum(0.0, 0.05, level, rate).with_noise(1.0, true),
+        46 => SynthVoice::drum(0.0, 0.35, level, rate).with_noise(1.0, true),
+        49 | 52 | 55 | 57 => SynthVoice::drum(0.0, 1.2, level * 0.8, rate).with_noise(1.0, true),
+        51 | 53 | 59 => SynthVoice::drum(600.0, 0.6, level * 0.6, rate).with_noise(0.8, true),
+        _ => SynthVoice::drum(400.0, 0.15, level * 0.7, rate).with_noise(0.7, false),
+    }
+}
+
+struct Synth;
+
+impl Instrument for Synth {
+    type Voice = SynthVoice;
+
+    fn voice(
+        &self,
+        channel: Channel,
+        state: &ChannelState,
+        note: Note,
+        velocity: Velocity,
+        sample_rate: f64,
+    ) -> Option<SynthVoice> {
+        let level = (velocity.value() as f32 / 127.0).powi(2);
+        if channel == Channel::DRUMS {
+            return Some(drum(note, level, sample_rate));
+        }
+        let (wave, adsr) = PATCHES[state.program as usize / 8 % PATCHES.len()];
+        Some(SynthVoice::new(
+            wave,
+            frequency(note),
+            adsr,
+            level,
+            sample_rate,
+        ))
+    }
+}
+
+impl Smf {
+    // The song played through the built-in synth, following the tempo
+    // map, programs, pitch bend, volume, expression, pan and sustain
+    pub fn render_audio(&self, options: &AudioOptions) -> Result<Audio, MidiError> {
+        play(self, &Synth, options)
+    }
+
+    // The song played through the built-in synth as a 16 bit WAV file
+    pub fn to_wav(&self, options: &AudioOptions) -> Result<Vec<u8>, MidiError> {
+        self.render_audio(options)?.to_wav()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::domain::Control;
+    use crate::message::{ChannelMessage, MidiMessage};
+    use crate::track::{EventType, TrackEvent, Vql};
+
+    fn loud(frames: &[[f32; 2]]) -> bool {
+        frames.iter().flatten().any(|s| s.abs() > 0.001)
+    }
+
+    fn control(channel: Channel, control: u8, value: u8) -> TrackEvent {
+        TrackEvent::new(
+            Vql::zero(),
+            EventType::Midi(MidiMessage::Channel {
+                channel,
+                message: ChannelMessage::ControlChange {
+                    control: Control::new(control).unwrap(),
+                    value: Control::new(value).unwrap(),
+                },
+            }),
+        )
+    }
+
+    #[test]
+    fn tempo_and_pan() {
+        // a beat is a second, so the note sounds from 1 s to 2 s
+        let mut smf = Smf::from_text("t60 l4 r o5 c").unwrap();
+        let channel = Channel::new(0).unwrap();
+        let track = &mut smf.tracks_mut()[0];
+        let mut events: Vec<(u64, TrackEvent)> = track
+            .absolute_events()
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+        events.push((0, control(channel, 10, 0)));
+        track.set_absolute_events(events).unwrap();
+
+        let options = AudioOptions::new().with_sample_rate(8000).with_tail(0.5);
+        let audio = smf.render_audio(&options).unwrap();
+        assert_eq!(audio.sample_rate(), 8000);
+        assert_eq!(audio.frames().len(), 20_000);
+        let frames = audio.frames();
+        assert!(!loud(&frames[..8000]));
+        assert!(loud(&frames[8000..8100]));
+        // panned hard left
+        assert!(frames.iter().all(|[_, right]| *right == 0.0));
+        assert!(audio.peak() <= 1.0);
+
+        let wav = smf.to_wav(&options).unwrap();
+        assert_eq!(wav.len(), 44 + 20_000 * 4);
+        assert_eq!(smf.to_wav(&options).unwrap(), wav);
+    }
+
+    #[test]
+    fn sustain_and_drums() {
+        // an organ holds its level, so it only stops once released
+        let channel = Channel::new(0).unwrap();
+        let note = Note::new(60).unwrap();
+        let velocity = Velocity::new(100).unwrap();
+        let events = vec![
+            (0, control(channel, 64, 127)),
+            (
+                0,
+                TrackEvent::new(
+                    Vql::zero(),
+                    EventType::Midi(MidiMessage::Channel {
+                        channel,
+                        message: ChannelMessage::ProgramChange {
+                            program: crate::domain::Program::new(16).unwrap(),
+                        },
+                    }),
+                ),
+            ),
+            (0, TrackEvent::note_on(Vql::zero(), channel, note, velocity)),
+            (
+                480,
+                TrackEvent::note_off(Vql::zero(), channel, note, velocity),
+            ),
+            (1440, control(channel, 64, 0)),
+            (1920, TrackEvent::end_track()),
+        ];
+        let mut smf = Smf::from_text("c").unwrap();
+        smf.tracks_mut()[0] = crate::track::Track::from_absolute_events(events).unwrap();
+
+        let options = AudioOptions::new().with_sample_rate(8000).with_tail(0.0);
+        let audio = smf.render_audio(&options).unwrap();
+        // 120 bpm: note off at 0.5 s, sustain up at 1.5 s
+        let frames = audio.frames();
+        assert_eq!(frames.len(), 16_000);
+        assert!(loud(&frames[10_000..11_000]));
+        assert!(!loud(&frames[14_000..]));
+
+        let kick = drum(Note::new(36).unwrap(), 1.0, 8000.0);
+        let hat = drum(Note::new(42).unwrap(), 1.0, 8000.0);
+        for mut voice in [kick, hat] {
+            voice.release();
+            let samples: Vec<f32> = (0..8000).map(|_| voice.next(2.0)[0]).collect();
+            assert!(samples[..400].iter().any(|s| s.abs() > 0.05));
+            assert!(voice.finished());
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::channel::Channel;
+use crate::domain::{Control, Velocity};
+use crate::error::MidiError;
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::meta::MetaEvent;
+use crate::span::{note_edge, pair_notes, sort_releases_first, NoteEdge};
+use crate::track::{EventType, Track, TrackEvent, Vql};
+use std::collections::HashMap;
+
+// What an event leaves set for the events after it. Only the last one
+// of each matters, e.g. the program a channel is on at some tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Setting {
+    Program(Channel),
+    Control(Channel, Control),
+    PitchBend(Channel),
+    Tempo,
+    TimeSignature,
+    KeySignature,
+    TrackName,
+}
+
+fn setting(event: &TrackEvent) -> Option<Setting> {
+    match &event.event {
+        EventType::Midi(MidiMessage::Channel { channel, message }) => match message {
+            ChannelMessage::ProgramChange { .. } => Some(Setting::Program(*channel)),
+            ChannelMessage::ControlChange { control, .. } => {
+                Some(Setting::Control(*channel, *control))
+            }
+            ChannelMessage::PitchBend { .. } => Some(Setting::PitchBend(*channel)),
+            _ => None,
+        },
+        EventType::Meta(MetaEvent::SetTempo(_)) => Some(Setting::Tempo),
+        EventType::Meta(MetaEvent::TimeSignature { .. }) => Some(Setting::TimeSignature),
+        EventType::Meta(MetaEvent::KeySignature { .. }) => Some(Setting::KeySignature),
+        EventType::Meta(MetaEvent::TrackName(_)) => Some(Setting::TrackName),
+        _ => None,
+    }
+}
+
+fn is_end_of_track(event: &TrackEvent) -> bool {
+    event.event == EventType::Meta(MetaEvent::EndOfTrack)
+}
+
+// the index of the last event of every setting inside [start, end)
+fn last_settings(timed: &[(u64, &TrackEvent)], start: u64, end: u64) -> Vec<usize> {
+    let mut last: HashMap<Setting, usize> = HashMap::new();
+    for (index, (tick, event)) in timed.iter().enumerate() {
+        if (start..end).contains(tick) {
+            if let Some(setting) = setting(event) {
+                last.insert(setting, index);
+            }
+        }
+    }
+    let mut indexes: Vec<usize> = last.into_values().collect();
+    indexes.sort_unstable();
+    indexes
+}
+
+// Edits by absolute tick. Ranges are [start, end) like NoteSpan.
+impl Track {
+    // A copy of [start, end) moved to tick 0. Notes still sounding at end
+    // are closed there, notes started before start are left out, and the
+    // last program, controllers, pitch bend, tempo and signatures set
+    // before start are repeated at tick 0 so the slice plays the same.
+    pub fn slice(&self, start: u64, end: u64) -> Result<Track, MidiError> {
+        let end = end.max(start);
+        let timed: Vec<(u64, &TrackEvent)> = self.absolute_events().collect();
+        let mut keep: Vec<Option<u64>> = timed
+            .iter()
+            .map(|(tick, event)| {
+                let inside = (start..end).contains(tick)
+                    && note_edge(event).is_none()
+                    && !is_end_of_track(event);
+                inside.then(|| tick - start)
+            })
+            .collect();
+
+        let mut closing: Vec<(u64, TrackEvent)> = Vec::new();
+        for (on, off) in pair_notes(timed.iter().map(|(_, e)| *e)) {
+            let on_tick = timed[on].0;
+            if !(start..end).contains(&on_tick) {
+                continue;
+            }
+            keep[on] = Some(on_tick - start);
+            match off {
+                Some(off) if timed[off].0 <= end => keep[off] = Some(timed[off].0 - start),
+                _ => {
+                    if let Some((channel, note, _)) = note_edge(timed[on].1) {
+                        let release =
+                            TrackEvent::note_off(Vql::zero(), channel, note, Velocity::new(0)?);
+                        closing.push((end - start, release));
+                    }
+                }
+            }
+        }
+
+        let mut events: Vec<(u64, TrackEvent)> = last_settings(&timed, 0, start)
+            .into_iter()
+            .map(|index| (0, timed[index].1.clone()))
+            .collect();
+        events.extend(
+            keep.iter()
+                .zip(&timed)
+                .filter_map(|(tick, (_, event))| Some(((*tick)?, (*event).clone()))),
+        );
+        events.extend(closing);
+        events.push((end - start, TrackEvent::end_track()));
+        sort_releases_first(&mut events);
+
+        let mut slice = Track::from_absolute_events(events)?;
+        slice.set_name(self.name());
+        Ok(slice)
+    }
+
+    // Cuts [start, end) out and pulls everything after it back. Notes
+    // started in the range go with it, notes started before it end at
+    // start at the latest, and settings changed in the range still take
+    // effect at start.
+    pub fn remove_range(&mut self, start: u64, end: u64) -> Result<(), MidiError> {
+        let end = end.max(start);
+        let length = end - start;
+        let map = |tick: u64| {
+            if tick < start {
+                tick
+            } else if tick < end {
+                start
+            } else {
+                tick - length
+            }
+        };
+
+        let timed: Vec<(u64, &TrackEvent)> = self.absolute_events().collect();
+        let mut keep: Vec<bool> = timed
+            .iter()
+            .map(|(tick, event)| {
+                !(start..end).contains(tick) || note_edge(event).is_some() || is_end_of_track(event)
+            })
+            .collect();
+        for index in last_settings(&timed, start, end) {
+            keep[index] = true;
+        }
+        for (on, off) in pair_notes(timed.iter().map(|(_, e)| *e)) {
+            if (start..end).contains(&timed[on].0) {
+                keep[on] = false;
+                if let Some(off) = off {
+                    keep[off] = false;
+                }
+            }
+        }
+
+        let mut events: Vec<(u64, TrackEvent)> = timed
+            .iter()
+            .zip(keep)
+            .filter(|(_, keep)| *keep)
+            .map(|((tick, event), _)| (map(*tick), (*event).clone()))
+            .collect();
+        sort_releases_first(&mut events);
+        self.set_absolute_events(events)
+    }
+
+    // Pushes everything from `at` on by length ticks, except the notes
+    // ending right at `at`. A note sounding across `at` gets longer.
+    pub fn insert_silence(&mut self, at: u64, length: u64) -> Result<(), MidiError> {
+        let events: Vec<(u64, TrackEvent)> = self
+            .absolute_events()
+            .map(|(tick, e)| {
+                let release = matches!(note_edge(e), Some((_, _, NoteEdge::Off)));
+                let moves = tick > at || (tick == at && !release);
+                let tick = if moves { tick + length } else { tick };
+                (tick, e.clone())
+            })
+            .collect();
+        self.set_absolute_events(events)
+    }
+
+    // Makes room at `at` and drops the whole of other in it, its own
+    // name and EndOfTrack left out
+    pub fn splice(&mut self, at: u64, other: &Track) -> Result<(), MidiError> {
+        self.insert_silence(at, other.end_tick())?;
+        let mut events: Vec<(u64, TrackEvent)> = self
+            .absolute_events()
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+        events.extend(
+            other
+                .absolute_events()
+                .filter(|(_, e)| !is_end_of_track(e) && setting(e) != Some(Setting::TrackName))
+                .map(|(tick, e)| (tick + at, e.clone())),
+        );
+        sort_releases_first(&mut events);
+        self.set_absolute_events(events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{note, spans, track_of};
+    use crate::Program;
+
+    fn channel() -> Channel {
+        Channel::new(0).unwrap()
+    }
+
+    fn program(value: u8) -> TrackEvent {
+        TrackEvent::new(
+            Vql::zero(),
+            EventType::Midi(MidiMessage::Channel {
+                channel: channel(),
+                message: ChannelMessage::ProgramChange {
+                    program: Program::new(value).unwrap(),
+                },
+            }),
+        )
+    }
+
+    fn programs(track: &Track) -> Vec<(u64, u8)> {
+        track
+            .absolute_events()
+            .filter_map(|(tick, e)| match e.event {
+                EventType::Midi(MidiMessage::Channel {
+                    message: ChannelMessage::ProgramChange { program },
+                    ..
+                }) => Some((tick, program.value())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slice_closes_notes_and_carries_state() {
+        let track = track_of(
+            &[note(0, 100, 60), note(100, 300, 62), note(200, 250, 64)],
+            vec![(0, program(1)), (50, program(5))],
+        );
+        let slice = track.slice(100, 240).unwrap();
+        assert_eq!(
+            spans(&slice.note_spans()),
+            vec![(0, 140, 62), (100, 140, 64)]
+        );
+        assert_eq!(programs(&slice), vec![(0, 5)]);
+        assert_eq!(slice.end_tick(), 140);
+    }
+
+    #[test]
+    fn remove_range_and_insert_silence() {
+        let mut track = track_of(
+            &[note(0, 150, 60), note(100, 200, 62), note(200, 300, 64)],
+            vec![(120, program(7))],
+        );
+        track.remove_range(100, 200).unwrap();
+        assert_eq!(
+            spans(&track.note_spans()),
+            vec![(0, 100, 60), (100, 200, 64)]
+        );
+        assert_eq!(programs(&track), vec![(100, 7)]);
+
+        track.insert_silence(100, 50).unwrap();
+        assert_eq!(
+            spans(&track.note_spans()),
+            vec![(0, 100, 60), (150, 250, 64)]
+        );
+        assert_eq!(track.end_tick(), 250);
+    }
+
+    #[test]
+    fn splice() {
+        let mut track = track_of(&[note(0, 100, 60), note(100, 200, 62)], vec![]);
+        let fill = track_of(&[note(0, 50, 70), note(50, 100, 72)], vec![]);
+        track.splice(100, &fill).unwrap();
+        assert_eq!(
+            spans(&track.note_spans()),
+            vec![(0, 100, 60), (100, 150, 70), (150, 200, 72), (200, 300, 62)]
+        );
+        assert_eq!(track.end_tick(), 300);
+    }
+}
@@ -1,6 +1,11 @@
 use crate::chunktype::ChunkType;
 use crate::error::MidiError;
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "RawHeader", into = "RawHeader")
+)]
 pub struct Header {
     chunk_type: ChunkType, // b"MThd",
     length: u32,
@@ -45,7 +50,7 @@ impl Header {
             return Err(MidiError::InvalidHeaderByte);
         }
 
-        if &bytes[0..4] != ChunkType::Header.as_bytes() {
+        if bytes[0..4] != ChunkType::Header.as_bytes() {
             return Err(MidiError::InvalidHeaderByte);
         }
 
@@ -72,16 +77,78 @@ impl Header {
     pub fn track_count(&self) -> usize {
         self.track_count as usize
     }
+
+    pub fn format(&self) -> MidiFormat {
+        self.format
+    }
+
+    // raw division word, negative values are SMPTE based
+    pub fn division(&self) -> i16 {
+        self.division
+    }
+
+    pub(crate) fn set_division(&mut self, division: i16) {
+        self.division = division;
+    }
+
+    // None when the division is SMPTE (frames per second / ticks per frame)
+    pub fn ticks_per_beat(&self) -> Option<u32> {
+        if self.division > 0 {
+            Some(self.division as u32)
+        } else {
+            None
+        }
+    }
+}
+
+// same fields as the MThd chunk body, deserialized values go through Header::new
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RawHeader {
+    format: MidiFormat,
+    track_count: u16,
+    division: i16,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawHeader> for Header {
+    type Error = MidiError;
+
+    fn try_from(raw: RawHeader) -> Result<Self, Self::Error> {
+        Header::new(raw.format, raw.track_count, raw.division)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Header> for RawHeader {
+    fn from(header: Header) -> Self {
+        Self {
+            format: header.format,
+            track_count: header.track_count,
+            division: header.division,
+        }
+    }
 }
 
 #[repr(u16)]
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u16", into = "u16")
+)]
 pub enum MidiFormat {
     SingleTrack = 0,
     MultipleTrack = 1,
     MultipleSong = 2,
 }
 
+impl From<MidiFormat> for u16 {
+    fn from(value: MidiFormat) -> Self {
+        value as u16
+    }
+}
+
 impl TryFrom<u16> for MidiFormat {
     type Error = MidiError;
 
@@ -107,6 +174,7 @@ mod test {
     }
 
     #[test]
+    #[allow(unused_variables)]
     fn header_maker() {
         let smf_header = Header::new(MidiFormat::SingleTrack, 1, 1);
         // assert_eq!(smf_header.to_bytes(),);
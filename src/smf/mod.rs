@@ -1,9 +1,13 @@
 pub mod chunktype;
+pub mod edit;
 pub mod header;
+#[allow(clippy::module_inception)]
 pub mod smf;
+pub mod span;
 pub mod track;
 
 pub use chunktype::*;
 pub use header::*;
 pub use smf::*;
+pub use span::*;
 pub use track::*;
@@ -7,10 +7,45 @@ use crate::track::Track;
 // then you can just do to_bytes and you'll have your midi file
 // At this moment is just an encoding, but later I could add also the
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "RawSmf", into = "RawSmf")
+)]
 pub struct Smf {
     header: Header,
     tracks: Vec<Track>,
 }
+
+// deserialized songs need as many tracks as their header counts
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RawSmf {
+    header: Header,
+    tracks: Vec<Track>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawSmf> for Smf {
+    type Error = crate::error::MidiError;
+
+    fn try_from(raw: RawSmf) -> Result<Self, Self::Error> {
+        if raw.header.track_count() != raw.tracks.len() {
+            return Err(crate::error::MidiError::InvalidHeaderByte);
+        }
+        Ok(Smf::new(raw.header, raw.tracks))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Smf> for RawSmf {
+    fn from(smf: Smf) -> Self {
+        Self {
+            header: smf.header,
+            tracks: smf.tracks,
+        }
+    }
+}
 impl Smf {
     pub fn new(header: Header, tracks: Vec<Track>) -> Self {
         Self { header, tracks }
@@ -23,6 +58,22 @@ impl Smf {
         }
     }
 
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub(crate) fn header_mut(&mut self) -> &mut Header {
+        &mut self.header
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn tracks_mut(&mut self) -> &mut [Track] {
+        &mut self.tracks
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         //14 is the fixed header bytes len
         let mut bytes: Vec<u8> =
@@ -0,0 +1,158 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Velocity};
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::smf::Smf;
+use crate::track::{EventType, Track, TrackEvent};
+use std::collections::{HashMap, VecDeque};
+
+// A sounding note: the NoteOn and its matching NoteOff folded together,
+// with absolute ticks. end is exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteSpan {
+    pub start: u64,
+    pub end: u64,
+    pub channel: Channel,
+    pub note: Note,
+    pub velocity: Velocity,
+}
+
+impl NoteSpan {
+    pub fn duration(&self) -> u64 {
+        self.end - self.start
+    }
+
+    // how many ticks of this note fall inside [start, end)
+    pub fn overlap(&self, start: u64, end: u64) -> u64 {
+        self.end.min(end).saturating_sub(self.start.max(start))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NoteEdge {
+    On(Velocity),
+    Off,
+}
+
+// NoteOn with velocity 0 is a NoteOff, as the spec says
+pub(crate) fn note_edge(event: &TrackEvent) -> Option<(Channel, Note, NoteEdge)> {
+    match event.event {
+        EventType::Midi(MidiMessage::Channel { channel, message }) => match message {
+            ChannelMessage::NoteOn { note, velocity } if velocity.value() > 0 => {
+                Some((channel, note, NoteEdge::On(velocity)))
+            }
+            ChannelMessage::NoteOn { note, .. } | ChannelMessage::NoteOff { note, .. } => {
+                Some((channel, note, NoteEdge::Off))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Indexes of (NoteOn, NoteOff) pairs in the given events, in NoteOn order.
+// Repeated NoteOns of the same key are closed first in first out,
+// a NoteOn that never gets its NoteOff has None.
+pub(crate) fn pair_notes<'a, I>(events: I) -> Vec<(usize, Option<usize>)>
+where
+    I: IntoIterator<Item = &'a TrackEvent>,
+{
+    let mut pairs: Vec<(usize, Option<usize>)> = Vec::new();
+    let mut open: HashMap<(Channel, Note), VecDeque<usize>> = HashMap::new();
+
+    for (index, event) in events.into_iter().enumerate() {
+        match note_edge(event) {
+            Some((channel, note, NoteEdge::On(_))) => {
+                open.entry((channel, note))
+                    .or_default()
+                    .push_back(pairs.len());
+                pairs.push((index, None));
+            }
+            Some((channel, note, NoteEdge::Off)) => {
+                if let Some(pair) = open.get_mut(&(channel, note)).and_then(|q| q.pop_front()) {
+                    pairs[pair].1 = Some(index);
+                }
+            }
+            None => {}
+        }
+    }
+    pairs
+}
+
+// Stable sort by tick where, on the same tick, releases go first, then
+// everything else, then new notes. Keeps a retriggered key from being
+// cut by the NoteOff of the previous one.
+pub(crate) fn sort_releases_first(events: &mut [(u64, TrackEvent)]) {
+    events.sort_by_key(|(tick, event)| {
+        let rank = match note_edge(event) {
+            Some((_, _, NoteEdge::Off)) => 0,
+            Some((_, _, NoteEdge::On(_))) => 2,
+            None => 1,
+        };
+        (*tick, rank)
+    });
+}
+
+impl Track {
+    // Notes left hanging are closed at the end of the track.
+    // Sorted by start tick.
+    pub fn note_spans(&self) -> Vec<NoteSpan> {
+        let timed: Vec<(u64, &TrackEvent)> = self.absolute_events().collect();
+        let end = self.end_tick();
+
+        pair_notes(timed.iter().map(|(_, e)| *e))
+            .into_iter()
+            .filter_map(|(on, off)| {
+                let (start, event) = timed[on];
+                let (channel, note, edge) = note_edge(event)?;
+                let NoteEdge::On(velocity) = edge else {
+                    return None;
+                };
+                Some(NoteSpan {
+                    start,
+                    end: off.map(|i| timed[i].0).unwrap_or(end),
+                    channel,
+                    note,
+                    velocity,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Smf {
+    // spans of every track merged together, sorted by start tick
+    pub fn note_spans(&self) -> Vec<NoteSpan> {
+        let mut spans: Vec<NoteSpan> = self.tracks().iter().flat_map(|t| t.note_spans()).collect();
+        spans.sort_by_key(|s| (s.start, s.note));
+        spans
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::track::Vql;
+
+    #[test]
+    fn spans_from_track() {
+        let channel = Channel::new(0).unwrap();
+        let c4 = Note::new(60).unwrap();
+        let e4 = Note::new(64).unwrap();
+        let loud = Velocity::new(100).unwrap();
+        let silent = Velocity::new(0).unwrap();
+
+        let mut track = Track::default();
+        track
+            .note_on(Vql::zero(), channel, c4, loud)
+            .note_on(Vql::try_from(240).unwrap(), channel, e4, loud)
+            // NoteOn with velocity 0 ends the C
+            .note_on(Vql::try_from(240).unwrap(), channel, c4, silent)
+            .note_on(Vql::zero(), channel, c4, loud)
+            .note_off(Vql::try_from(480).unwrap(), channel, e4, silent);
+
+        let spans = track.note_spans();
+        let ranges: Vec<_> = spans.iter().map(|s| (s.note, s.start, s.end)).collect();
+        assert_eq!(ranges, vec![(c4, 0, 480), (e4, 240, 960), (c4, 480, 960)]);
+        assert_eq!(spans[1].overlap(0, 480), 240);
+    }
+}
@@ -9,6 +9,11 @@ use std::convert::TryFrom;
 //need to add the status running
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "RawTrack", into = "RawTrack")
+)]
 pub struct Track {
     chunk_type: ChunkType,
     length: u32,
@@ -76,6 +81,68 @@ impl Track {
         self.length as usize
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn events(&self) -> &[TrackEvent] {
+        &self.events
+    }
+
+    // (absolute tick, event) pairs, the deltas summed up from the start of the track
+    pub fn absolute_events(&self) -> impl Iterator<Item = (u64, &TrackEvent)> + '_ {
+        self.events.iter().scan(0u64, |tick, event| {
+            *tick += event.v_time.value() as u64;
+            Some((*tick, event))
+        })
+    }
+
+    // tick of the last event (usually the EndOfTrack)
+    pub fn end_tick(&self) -> u64 {
+        self.events.iter().map(|e| e.v_time.value() as u64).sum()
+    }
+
+    pub fn from_absolute_events(events: Vec<(u64, TrackEvent)>) -> Result<Self, MidiError> {
+        let mut track = Track::default();
+        track.set_absolute_events(events)?;
+        Ok(track)
+    }
+
+    // Replaces the events with (absolute tick, event) pairs: they are stably
+    // sorted by tick and the deltas re-derived. An EndOfTrack is kept only
+    // once and always moved after everything else.
+    pub fn set_absolute_events(
+        &mut self,
+        mut events: Vec<(u64, TrackEvent)>,
+    ) -> Result<(), MidiError> {
+        let is_end = |e: &TrackEvent| e.event == EventType::Meta(MetaEvent::EndOfTrack);
+        let end_of_track = events
+            .iter()
+            .filter(|(_, e)| is_end(e))
+            .map(|(t, _)| *t)
+            .max();
+        events.retain(|(_, e)| !is_end(e));
+        events.sort_by_key(|(tick, _)| *tick);
+        if let Some(end) = end_of_track {
+            let last = events.last().map_or(0, |(tick, _)| *tick);
+            events.push((end.max(last), TrackEvent::end_track()));
+        }
+
+        let mut previous = 0;
+        let mut rebuilt = Vec::with_capacity(events.len());
+        for (tick, mut event) in events {
+            let delta = tick - previous;
+            let delta = u32::try_from(delta).map_err(|_| MidiError::InvalidVqlInput(u32::MAX))?;
+            event.v_time = Vql::try_from(delta)?;
+            previous = tick;
+            rebuilt.push(event);
+        }
+
+        self.length = rebuilt.iter().map(|e| e.to_bytes().len() as u32).sum();
+        self.events = rebuilt;
+        Ok(())
+    }
+
     pub fn note_on(
         &mut self,
         delta_time: Vql,
@@ -97,6 +164,28 @@ impl Track {
         self.add_event(TrackEvent::note_off(delta_time, channel, note, velocity));
         self
     }
+
+    // All the notes start together after delta_time and are released together
+    // after duration, e.g. track.chord(delta, duration, channel, &chord.notes()?, velocity)
+    pub fn chord(
+        &mut self,
+        delta_time: Vql,
+        duration: Vql,
+        channel: Channel,
+        notes: &[Note],
+        velocity: Velocity,
+    ) -> &mut Self {
+        let release = Velocity::new(0).unwrap();
+        for (i, note) in notes.iter().enumerate() {
+            let delta = if i == 0 { delta_time } else { Vql::zero() };
+            self.note_on(delta, channel, *note, velocity);
+        }
+        for (i, note) in notes.iter().enumerate() {
+            let delta = if i == 0 { duration } else { Vql::zero() };
+            self.note_off(delta, channel, *note, release);
+        }
+        self
+    }
 }
 
 // need to put track_name first and EOT
@@ -118,7 +207,37 @@ impl Default for Track {
     }
 }
 
+// serde goes through this shape so the chunk bookkeeping (chunk_type, length)
+// is always rebuilt by Track::new instead of being trusted from the input
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RawTrack {
+    #[serde(default)]
+    name: String,
+    events: Vec<TrackEvent>,
+}
+
+#[cfg(feature = "serde")]
+impl From<RawTrack> for Track {
+    fn from(raw: RawTrack) -> Self {
+        let mut track = Track::new(raw.events);
+        track.set_name(raw.name);
+        track
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Track> for RawTrack {
+    fn from(track: Track) -> Self {
+        Self {
+            name: track.name,
+            events: track.events,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EventType {
     Midi(MidiMessage),
     Meta(MetaEvent),
@@ -183,6 +302,7 @@ impl EventType {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrackEvent {
     pub v_time: Vql,
     pub event: EventType,
@@ -242,6 +362,11 @@ impl TrackEvent {
 // Meaning: it's the delta time beetwen THIS track event
 // and the previous one.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u32", into = "u32")
+)]
 pub struct Vql(u32);
 
 impl Vql {
@@ -266,6 +391,7 @@ impl Vql {
             }
         }
 
+        #[allow(clippy::needless_range_loop)]
         for j in i..3 {
             //set MSB 1
             buffer[j] |= 0x80;
@@ -293,6 +419,12 @@ impl Vql {
     }
 }
 
+impl From<Vql> for u32 {
+    fn from(value: Vql) -> Self {
+        value.0
+    }
+}
+
 impl TryFrom<u32> for Vql {
     type Error = MidiError;
 
@@ -356,6 +488,85 @@ mod tests {
         assert_eq!(bytes, vec![0x81, 0x80, 0x80, 0x00]);
     }
 
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let channel = Channel::new(9).unwrap();
+        let mut track = Track::default().with_name("Kick");
+        track
+            .note_on(
+                Vql::zero(),
+                channel,
+                Note::new(36).unwrap(),
+                Velocity::new(127).unwrap(),
+            )
+            .note_off(
+                Vql::try_from(120).unwrap(),
+                channel,
+                Note::new(36).unwrap(),
+                Velocity::new(0).unwrap(),
+            );
+
+        let json = serde_json::to_string(&track).unwrap();
+        let decoded: Track = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.name, "Kick");
+        assert_eq!(decoded.to_bytes(), track.to_bytes());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_rejects_out_of_range_values() {
+        let note_on = r#"{"v_time":0,"event":{"Midi":{"Channel":{"channel":0,"message":{"NoteOn":{"note":200,"velocity":64}}}}}}"#;
+        assert!(serde_json::from_str::<TrackEvent>(note_on).is_err());
+
+        let channel = r#"{"v_time":0,"event":{"Midi":{"Channel":{"channel":16,"message":{"NoteOn":{"note":60,"velocity":64}}}}}}"#;
+        assert!(serde_json::from_str::<TrackEvent>(channel).is_err());
+
+        assert!(serde_json::from_str::<Vql>("268435456").is_err());
+
+        let bend = r#"{"Channel":{"channel":0,"message":{"PitchBend":{"value":16384}}}}"#;
+        assert!(serde_json::from_str::<MidiMessage>(bend).is_err());
+        assert!(serde_json::from_str::<MetaEvent>(r#"{"SetTempo":16777216}"#).is_err());
+        let signature = r#"{"TimeSignature":{"numerator":4,"denominator":3,"clocks_per_tick":24,"thirty_seconds_per_24_clocks":8}}"#;
+        assert!(serde_json::from_str::<MetaEvent>(signature).is_err());
+        let in_range = r#"{"TimeSignature":{"numerator":4,"denominator":4,"clocks_per_tick":24,"thirty_seconds_per_24_clocks":8}}"#;
+        assert!(serde_json::from_str::<MetaEvent>(in_range).is_ok());
+
+        // the header counts two tracks, there is one
+        let smf = crate::Smf::new(
+            crate::Header::new(crate::MidiFormat::MultipleTrack, 1, 96).unwrap(),
+            vec![Track::default()],
+        );
+        let json = serde_json::to_string(&smf).unwrap();
+        assert!(serde_json::from_str::<crate::Smf>(&json).is_ok());
+        let json = json.replace(r#""track_count":1"#, r#""track_count":2"#);
+        assert!(serde_json::from_str::<crate::Smf>(&json).is_err());
+    }
+
+    #[test]
+    fn chord_events() {
+        let channel = Channel::new(0).unwrap();
+        let notes = [Note::new(60).unwrap(), Note::new(64).unwrap()];
+        let mut track = Track::default();
+        track.chord(
+            Vql::try_from(10).unwrap(),
+            Vql::try_from(480).unwrap(),
+            channel,
+            &notes,
+            Velocity::new(90).unwrap(),
+        );
+
+        let deltas: Vec<u32> = track.events.iter().map(|e| e.v_time.value()).collect();
+        assert_eq!(deltas, vec![10, 0, 480, 0]);
+        assert!(matches!(
+            track.events[3].event,
+            EventType::Midi(MidiMessage::Channel {
+                message: ChannelMessage::NoteOff { .. },
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn encode_len_correctness() {
         assert_eq!(Vql::try_from(0x7F).unwrap().encode_len(), 1);
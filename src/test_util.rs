@@ -0,0 +1,47 @@
+// Fixtures shared by the tests of several modules
+
+use crate::channel::Channel;
+use crate::domain::{Note, Velocity};
+use crate::span::{sort_releases_first, NoteSpan};
+use crate::track::{Track, TrackEvent, Vql};
+
+// A note on channel 0 at velocity 100, change either with
+// NoteSpan { channel, ..note(start, end, note) }
+pub(crate) fn note(start: u64, end: u64, note: u8) -> NoteSpan {
+    NoteSpan {
+        start,
+        end,
+        channel: Channel::new(0).unwrap(),
+        note: Note::new(note).unwrap(),
+        velocity: Velocity::new(100).unwrap(),
+    }
+}
+
+// A track playing the notes, with the other events in between and an
+// EndOfTrack on the last tick
+pub(crate) fn track_of(notes: &[NoteSpan], others: Vec<(u64, TrackEvent)>) -> Track {
+    let mut events = others;
+    for span in notes {
+        let (channel, note) = (span.channel, span.note);
+        events.push((
+            span.start,
+            TrackEvent::note_on(Vql::zero(), channel, note, span.velocity),
+        ));
+        events.push((
+            span.end,
+            TrackEvent::note_off(Vql::zero(), channel, note, Velocity::new(0).unwrap()),
+        ));
+    }
+    let end = events.iter().map(|(t, _)| *t).max().unwrap_or(0);
+    events.push((end, TrackEvent::end_track()));
+    sort_releases_first(&mut events);
+    Track::from_absolute_events(events).unwrap()
+}
+
+// (start, end, note) of each span, to compare against
+pub(crate) fn spans(spans: &[NoteSpan]) -> Vec<(u64, u64, u8)> {
+    spans
+        .iter()
+        .map(|s| (s.start, s.end, s.note.value()))
+        .collect()
+}
@@ -0,0 +1,367 @@
+use crate::domain::Note;
+use crate::error::MidiError;
+use crate::key::{MiddleC, Spelling};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Sixth,
+    Minor6,
+    Dominant7,
+    Major7,
+    Minor7,
+    MinorMajor7,
+    HalfDiminished7,
+    Diminished7,
+    Dominant7Sus4,
+    Dominant9,
+    Major9,
+    Minor9,
+    Dominant11,
+    Minor11,
+    Dominant13,
+    Major13,
+    Minor13,
+    Add9,
+}
+
+impl ChordQuality {
+    pub const ALL: [ChordQuality; 24] = [
+        ChordQuality::Major,
+        ChordQuality::Minor,
+        ChordQuality::Diminished,
+        ChordQuality::Augmented,
+        ChordQuality::Sus2,
+        ChordQuality::Sus4,
+        ChordQuality::Sixth,
+        ChordQuality::Minor6,
+        ChordQuality::Dominant7,
+        ChordQuality::Major7,
+        ChordQuality::Minor7,
+        ChordQuality::MinorMajor7,
+        ChordQuality::HalfDiminished7,
+        ChordQuality::Diminished7,
+        ChordQuality::Dominant7Sus4,
+        ChordQuality::Dominant9,
+        ChordQuality::Major9,
+        ChordQuality::Minor9,
+        ChordQuality::Dominant11,
+        ChordQuality::Minor11,
+        ChordQuality::Dominant13,
+        ChordQuality::Major13,
+        ChordQuality::Minor13,
+        ChordQuality::Add9,
+    ];
+
+    // semitones above the root, in close position
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            ChordQuality::Major => &[0, 4, 7],
+            ChordQuality::Minor => &[0, 3, 7],
+            ChordQuality::Diminished => &[0, 3, 6],
+            ChordQuality::Augmented => &[0, 4, 8],
+            ChordQuality::Sus2 => &[0, 2, 7],
+            ChordQuality::Sus4 => &[0, 5, 7],
+            ChordQuality::Sixth => &[0, 4, 7, 9],
+            ChordQuality::Minor6 => &[0, 3, 7, 9],
+            ChordQuality::Dominant7 => &[0, 4, 7, 10],
+            ChordQuality::Major7 => &[0, 4, 7, 11],
+            ChordQuality::Minor7 => &[0, 3, 7, 10],
+            ChordQuality::MinorMajor7 => &[0, 3, 7, 11],
+            ChordQuality::HalfDiminished7 => &[0, 3, 6, 10],
+            ChordQuality::Diminished7 => &[0, 3, 6, 9],
+            ChordQuality::Dominant7Sus4 => &[0, 5, 7, 10],
+            ChordQuality::Dominant9 => &[0, 4, 7, 10, 14],
+            ChordQuality::Major9 => &[0, 4, 7, 11, 14],
+            ChordQuality::Minor9 => &[0, 3, 7, 10, 14],
+            ChordQuality::Dominant11 => &[0, 4, 7, 10, 14, 17],
+            ChordQuality::Minor11 => &[0, 3, 7, 10, 14, 17],
+            ChordQuality::Dominant13 => &[0, 4, 7, 10, 14, 17, 21],
+            ChordQuality::Major13 => &[0, 4, 7, 11, 14, 17, 21],
+            ChordQuality::Minor13 => &[0, 3, 7, 10, 14, 17, 21],
+            ChordQuality::Add9 => &[0, 4, 7, 14],
+        }
+    }
+
+    // the spelling used when printing a chord
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "",
+            ChordQuality::Minor => "m",
+            ChordQuality::Diminished => "dim",
+            ChordQuality::Augmented => "aug",
+            ChordQuality::Sus2 => "sus2",
+            ChordQuality::Sus4 => "sus4",
+            ChordQuality::Sixth => "6",
+            ChordQuality::Minor6 => "m6",
+            ChordQuality::Dominant7 => "7",
+            ChordQuality::Major7 => "maj7",
+            ChordQuality::Minor7 => "m7",
+            ChordQuality::MinorMajor7 => "mMaj7",
+            ChordQuality::HalfDiminished7 => "m7b5",
+            ChordQuality::Diminished7 => "dim7",
+            ChordQuality::Dominant7Sus4 => "7sus4",
+            ChordQuality::Dominant9 => "9",
+            ChordQuality::Major9 => "maj9",
+            ChordQuality::Minor9 => "m9",
+            ChordQuality::Dominant11 => "11",
+            ChordQuality::Minor11 => "m11",
+            ChordQuality::Dominant13 => "13",
+            ChordQuality::Major13 => "maj13",
+            ChordQuality::Minor13 => "m13",
+            ChordQuality::Add9 => "add9",
+        }
+    }
+
+    // accepts the printed symbol plus the usual alternatives (M7, -7, °, +, ø, Δ ...)
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        let quality = match symbol {
+            "" | "M" | "maj" => ChordQuality::Major,
+            "m" | "min" | "-" => ChordQuality::Minor,
+            "dim" | "°" | "o" => ChordQuality::Diminished,
+            "aug" | "+" => ChordQuality::Augmented,
+            "sus2" => ChordQuality::Sus2,
+            "sus4" | "sus" => ChordQuality::Sus4,
+            "6" => ChordQuality::Sixth,
+            "m6" | "min6" | "-6" => ChordQuality::Minor6,
+            "7" | "dom7" => ChordQuality::Dominant7,
+            "maj7" | "M7" | "Δ7" | "Δ" => ChordQuality::Major7,
+            "m7" | "min7" | "-7" => ChordQuality::Minor7,
+            "mMaj7" | "mM7" | "minmaj7" | "-Δ7" => ChordQuality::MinorMajor7,
+            "m7b5" | "ø" | "ø7" | "min7b5" | "-7b5" => ChordQuality::HalfDiminished7,
+            "dim7" | "°7" | "o7" => ChordQuality::Diminished7,
+            "7sus4" | "7sus" => ChordQuality::Dominant7Sus4,
+            "9" => ChordQuality::Dominant9,
+            "maj9" | "M9" | "Δ9" => ChordQuality::Major9,
+            "m9" | "min9" | "-9" => ChordQuality::Minor9,
+            "11" => ChordQuality::Dominant11,
+            "m11" | "min11" | "-11" => ChordQuality::Minor11,
+            "13" => ChordQuality::Dominant13,
+            "maj13" | "M13" | "Δ13" => ChordQuality::Major13,
+            "m13" | "min13" | "-13" => ChordQuality::Minor13,
+            "add9" | "add2" => ChordQuality::Add9,
+            _ => return None,
+        };
+        Some(quality)
+    }
+}
+
+// How the chord tones are stacked when turned into notes.
+// Drop voicings take the close position and move the 2nd (or 3rd)
+// highest voice down one octave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Voicing {
+    Close,
+    Inversion(u8),
+    Drop2,
+    Drop3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Chord {
+    root: Note,
+    quality: ChordQuality,
+    // slash chords, the bass is always voiced under everything else
+    bass: Option<u8>,
+}
+
+impl Chord {
+    pub fn new(root: Note, quality: ChordQuality) -> Self {
+        Self {
+            root,
+            quality,
+            bass: None,
+        }
+    }
+
+    // C/E, only the pitch class of bass is kept
+    pub fn over(mut self, bass: Note) -> Self {
+        self.bass = Some(bass.pitch_class());
+        self
+    }
+
+    // "Cmaj7/E", "F#m7b5", "Bbsus4" ... the root is placed in the given octave
+    // (middle C is C4), a slash bass goes below the chord
+    pub fn parse(symbol: &str, octave: i8) -> Result<Self, MidiError> {
+        let (body, bass) = match symbol.split_once('/') {
+            Some((body, bass)) => (body, Some(bass)),
+            None => (symbol, None),
+        };
+
+        let (root_name, quality) = split_root(body)?;
+        let root = Note::parse(&format!("{root_name}{octave}"), MiddleC::C4)
+            .map_err(|_| MidiError::InvalidChord)?;
+        let quality = ChordQuality::from_symbol(quality).ok_or(MidiError::InvalidChord)?;
+
+        let mut chord = Chord::new(root, quality);
+        if let Some(bass) = bass {
+            let (bass_name, rest) = split_root(bass)?;
+            if !rest.is_empty() {
+                return Err(MidiError::InvalidChord);
+            }
+            // the octave only matters to get a valid Note, we keep the pitch class
+            let bass = Note::parse(&format!("{bass_name}4"), MiddleC::C4)
+                .map_err(|_| MidiError::InvalidChord)?;
+            chord = chord.over(bass);
+        }
+        Ok(chord)
+    }
+
+    pub fn root(&self) -> Note {
+        self.root
+    }
+
+    pub fn quality(&self) -> ChordQuality {
+        self.quality
+    }
+
+    pub fn bass(&self) -> Option<u8> {
+        self.bass
+    }
+
+    // close position from the root, plus the slash bass under it
+    pub fn notes(&self) -> Result<Vec<Note>, MidiError> {
+        self.voicing(Voicing::Close)
+    }
+
+    pub fn voicing(&self, voicing: Voicing) -> Result<Vec<Note>, MidiError> {
+        let mut values: Vec<i32> = self
+            .quality
+            .intervals()
+            .iter()
+            .map(|&i| self.root.value() as i32 + i as i32)
+            .collect();
+
+        match voicing {
+            Voicing::Close => {}
+            Voicing::Inversion(n) => {
+                let n = n as usize;
+                if n >= values.len() {
+                    return Err(MidiError::InvalidChord);
+                }
+                values.iter_mut().take(n).for_each(|v| *v += 12);
+                values.sort_unstable();
+            }
+            Voicing::Drop2 | Voicing::Drop3 => {
+                let from_top = if voicing == Voicing::Drop2 { 2 } else { 3 };
+                if values.len() < from_top {
+                    return Err(MidiError::InvalidChord);
+                }
+                let index = values.len() - from_top;
+                values[index] -= 12;
+                values.sort_unstable();
+            }
+        }
+
+        if let Some(bass) = self.bass {
+            let lowest = values[0];
+            // highest note with that pitch class strictly below the chord
+            let below = lowest - 1 - (lowest - 1 - bass as i32).rem_euclid(12);
+            values.insert(0, below);
+        }
+
+        values
+            .into_iter()
+            .map(|v| {
+                if (0..=127).contains(&v) {
+                    Note::new(v as u8)
+                } else {
+                    Err(MidiError::InvalidChord)
+                }
+            })
+            .collect()
+    }
+}
+
+// "C#m7" -> ("C#", "m7"), accidentals are '#' or 'b' right after the letter
+fn split_root(symbol: &str) -> Result<(&str, &str), MidiError> {
+    let mut chars = symbol.char_indices();
+    match chars.next() {
+        Some((_, 'A'..='G')) => {}
+        _ => return Err(MidiError::InvalidChord),
+    }
+    let end = chars
+        .find(|(_, c)| !matches!(c, '#' | 'b' | '♯' | '♭'))
+        .map(|(i, _)| i)
+        .unwrap_or(symbol.len());
+    Ok(symbol.split_at(end))
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            self.root.name(Spelling::Sharps),
+            self.quality.symbol()
+        )?;
+        if let Some(bass) = self.bass {
+            let bass = Note::new(bass).unwrap();
+            write!(f, "/{}", bass.name(Spelling::Sharps))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Chord {
+    type Err = MidiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Chord::parse(s, 4)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn notes(names: &[&str]) -> Vec<Note> {
+        names.iter().map(|n| Note::try_from(*n).unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_symbols() {
+        let chord: Chord = "Cmaj7/E".parse().unwrap();
+        assert_eq!(chord.quality(), ChordQuality::Major7);
+        assert_eq!(
+            chord.notes().unwrap(),
+            notes(&["E3", "C4", "E4", "G4", "B4"])
+        );
+        assert_eq!(chord.to_string(), "Cmaj7/E");
+
+        let chord = Chord::parse("F#m7b5", 3).unwrap();
+        assert_eq!(chord.notes().unwrap(), notes(&["F#3", "A3", "C4", "E4"]));
+
+        let chord: Chord = "Bbsus4".parse().unwrap();
+        assert_eq!(chord.notes().unwrap(), notes(&["Bb4", "Eb5", "F5"]));
+
+        assert!("H7".parse::<Chord>().is_err());
+        assert!("Cmaj8".parse::<Chord>().is_err());
+        assert!("C/Em".parse::<Chord>().is_err());
+    }
+
+    #[test]
+    fn voicings() {
+        let c7 = Chord::new(Note::try_from("C4").unwrap(), ChordQuality::Dominant7);
+        assert_eq!(
+            c7.voicing(Voicing::Inversion(1)).unwrap(),
+            notes(&["E4", "G4", "Bb4", "C5"])
+        );
+        assert_eq!(
+            c7.voicing(Voicing::Drop2).unwrap(),
+            notes(&["G3", "C4", "E4", "Bb4"])
+        );
+        assert_eq!(
+            c7.voicing(Voicing::Drop3).unwrap(),
+            notes(&["E3", "C4", "G4", "Bb4"])
+        );
+        assert!(c7.voicing(Voicing::Inversion(4)).is_err());
+    }
+}
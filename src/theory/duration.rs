@@ -0,0 +1,224 @@
+use crate::error::MidiError;
+use std::fmt;
+use std::str::FromStr;
+
+// A musical length as a fraction of a whole note: 1/4 is a quarter,
+// 1/8T an eighth note triplet, 1/4. a dotted quarter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Duration {
+    numerator: u32,
+    denominator: u32,
+}
+
+impl Duration {
+    pub const WHOLE: Duration = Duration::fraction(1, 1);
+    pub const HALF: Duration = Duration::fraction(1, 2);
+    pub const QUARTER: Duration = Duration::fraction(1, 4);
+    pub const EIGHTH: Duration = Duration::fraction(1, 8);
+    pub const SIXTEENTH: Duration = Duration::fraction(1, 16);
+    pub const THIRTY_SECOND: Duration = Duration::fraction(1, 32);
+
+    const fn fraction(numerator: u32, denominator: u32) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    pub fn new(numerator: u32, denominator: u32) -> Result<Self, MidiError> {
+        Self::reduced(numerator as u128, denominator as u128)
+    }
+
+    // the fraction in lowest terms, as long as that fits
+    fn reduced(numerator: u128, denominator: u128) -> Result<Self, MidiError> {
+        if numerator == 0 || denominator == 0 {
+            return Err(MidiError::InvalidDuration);
+        }
+        let d = gcd(numerator, denominator);
+        match (u32::try_from(numerator / d), u32::try_from(denominator / d)) {
+            (Ok(numerator), Ok(denominator)) => Ok(Self::fraction(numerator, denominator)),
+            _ => Err(MidiError::InvalidDuration),
+        }
+    }
+
+    pub fn numerator(&self) -> u32 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> u32 {
+        self.denominator
+    }
+
+    // one and a half times as long
+    pub fn dotted(self) -> Result<Self, MidiError> {
+        self.scaled(3, 2)
+    }
+
+    // three in the time of two
+    pub fn triplet(self) -> Result<Self, MidiError> {
+        self.tuplet(3, 2)
+    }
+
+    // n notes in the time of m, 5:4 quintuplets are tuplet(5, 4)
+    pub fn tuplet(self, n: u32, m: u32) -> Result<Self, MidiError> {
+        self.scaled(m, n)
+    }
+
+    // n times this duration
+    pub fn times(self, n: u32) -> Result<Self, MidiError> {
+        self.scaled(n, 1)
+    }
+
+    // InvalidDuration when the result can't be written in 32 bits
+    fn scaled(self, numerator: u32, denominator: u32) -> Result<Self, MidiError> {
+        Duration::reduced(
+            self.numerator as u128 * numerator as u128,
+            self.denominator as u128 * denominator as u128,
+        )
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Result<Self, MidiError> {
+        let (a, b) = (self.numerator as u128, self.denominator as u128);
+        let (c, d) = (rhs.numerator as u128, rhs.denominator as u128);
+        Duration::reduced(a * d + c * b, b * d)
+    }
+
+    // length in ticks for a division of ticks_per_beat per quarter note,
+    // rounded, InvalidDuration past u32::MAX ticks
+    pub fn ticks(&self, ticks_per_beat: u32) -> Result<u32, MidiError> {
+        let whole = ticks_per_beat as u128 * 4 * self.numerator as u128;
+        let denominator = self.denominator as u128;
+        u32::try_from((whole + denominator / 2) / denominator)
+            .map_err(|_| MidiError::InvalidDuration)
+    }
+}
+
+// more dots than this are no real note value
+const MAX_DOTS: usize = 4;
+
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// "1/4", "1/8T", "1/4.", "3/8", "1/16..", a missing numerator means 1 ("8T")
+impl FromStr for Duration {
+    type Err = MidiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (body, triplet) = match s.strip_suffix(['T', 't']) {
+            Some(body) => (body, true),
+            None => (s, false),
+        };
+        let dots = body.len() - body.trim_end_matches('.').len();
+        let body = body.trim_end_matches('.');
+        if dots > MAX_DOTS {
+            return Err(MidiError::InvalidDuration);
+        }
+
+        let (numerator, denominator) = match body.split_once('/') {
+            Some((n, d)) => (n, d),
+            None => ("1", body),
+        };
+        let numerator: u32 = numerator.parse().map_err(|_| MidiError::InvalidDuration)?;
+        let denominator: u32 = denominator
+            .parse()
+            .map_err(|_| MidiError::InvalidDuration)?;
+
+        let mut duration = Duration::new(numerator, denominator)?;
+        // every dot adds half of the previous addition: 1/4.. = 1/4 + 1/8 + 1/16
+        let base = duration;
+        for dot in 1..=dots as u32 {
+            let extra =
+                Duration::reduced(base.numerator as u128, (base.denominator as u128) << dot)?;
+            duration = duration.checked_add(extra)?;
+        }
+        if triplet {
+            duration = duration.triplet()?;
+        }
+        Ok(duration)
+    }
+}
+
+impl std::ops::Add for Duration {
+    type Output = Duration;
+
+    // like the integers, panics when the sum doesn't fit, see checked_add
+    fn add(self, rhs: Self) -> Self::Output {
+        self.checked_add(rhs).expect("duration overflow")
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_ticks() {
+        let quarter: Duration = "1/4".parse().unwrap();
+        assert_eq!(quarter, Duration::QUARTER);
+        assert_eq!(quarter.ticks(480), Ok(480));
+        let ticks = |s: &str| s.parse::<Duration>().unwrap().ticks(480).unwrap();
+        assert_eq!(ticks("1/8T"), 160);
+        assert_eq!(ticks("1/4."), 720);
+        assert_eq!(ticks("1/4.."), 840);
+        assert_eq!("16".parse::<Duration>().unwrap(), Duration::SIXTEENTH);
+        assert_eq!("3/8".parse::<Duration>().unwrap().ticks(96), Ok(144));
+        assert!("1/0".parse::<Duration>().is_err());
+        assert!("quarter".parse::<Duration>().is_err());
+        let many_dots = format!("1/4{}", ".".repeat(40));
+        assert_eq!(
+            many_dots.parse::<Duration>(),
+            Err(MidiError::InvalidDuration)
+        );
+        assert_eq!(
+            "1/65536.".parse::<Duration>().unwrap().to_string(),
+            "3/131072"
+        );
+        assert!(format!("1/{}..", u32::MAX).parse::<Duration>().is_err());
+        assert_eq!(
+            Duration::new(1, u32::MAX)
+                .unwrap()
+                .checked_add(Duration::new(1, u32::MAX - 1).unwrap()),
+            Err(MidiError::InvalidDuration)
+        );
+        // the cross products alone are past u64
+        let (a, b) = (u32::MAX, u32::MAX - 1);
+        assert_eq!(
+            Duration::new(a, b)
+                .unwrap()
+                .checked_add(Duration::new(a - 2, b - 2).unwrap()),
+            Err(MidiError::InvalidDuration)
+        );
+        // too fine to dot or too long to count in ticks
+        let fine = Duration::new(1, u32::MAX - 1).unwrap();
+        assert_eq!(fine.dotted(), Err(MidiError::InvalidDuration));
+        let long = Duration::new(u32::MAX, 1).unwrap();
+        assert_eq!(long.ticks(u32::MAX), Err(MidiError::InvalidDuration));
+        assert_eq!(long.times(2), Err(MidiError::InvalidDuration));
+    }
+
+    #[test]
+    fn tuplets() {
+        assert_eq!(Duration::EIGHTH.triplet().unwrap().to_string(), "1/12");
+        assert_eq!(
+            Duration::SIXTEENTH.tuplet(5, 4).unwrap().to_string(),
+            "1/20"
+        );
+        assert_eq!(Duration::QUARTER.dotted(), "3/8".parse());
+        assert_eq!(
+            Duration::QUARTER.tuplet(0, 2),
+            Err(MidiError::InvalidDuration)
+        );
+    }
+}
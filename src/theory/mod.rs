@@ -0,0 +1,7 @@
+pub mod chord;
+pub mod duration;
+pub mod scale;
+
+pub use chord::*;
+pub use duration::*;
+pub use scale::*;
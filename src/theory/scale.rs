@@ -0,0 +1,205 @@
+use crate::domain::Note;
+use crate::error::MidiError;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScaleKind {
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    MelodicMinor,
+    Ionian,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Aeolian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    WholeTone,
+    Chromatic,
+}
+
+impl ScaleKind {
+    // semitones above the root, always starting with 0
+    pub fn intervals(&self) -> &'static [u8] {
+        match self {
+            ScaleKind::Major | ScaleKind::Ionian => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleKind::NaturalMinor | ScaleKind::Aeolian => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleKind::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            // ascending form, the descending one is just the natural minor
+            ScaleKind::MelodicMinor => &[0, 2, 3, 5, 7, 9, 11],
+            ScaleKind::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleKind::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            ScaleKind::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            ScaleKind::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleKind::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            ScaleKind::MajorPentatonic => &[0, 2, 4, 7, 9],
+            ScaleKind::MinorPentatonic => &[0, 3, 5, 7, 10],
+            ScaleKind::Blues => &[0, 3, 5, 6, 7, 10],
+            ScaleKind::WholeTone => &[0, 2, 4, 6, 8, 10],
+            ScaleKind::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+}
+
+// A scale is a root note plus the semitone offsets of its degrees inside one octave.
+// Degrees are counted from 0 (the root) and keep going across octaves,
+// so in C major degree 7 is the C above and degree -1 the B below.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Scale {
+    root: Note,
+    intervals: Vec<u8>,
+}
+
+impl Scale {
+    pub fn new(root: Note, kind: ScaleKind) -> Self {
+        Self {
+            root,
+            intervals: kind.intervals().to_vec(),
+        }
+    }
+
+    // Intervals must start at 0, be strictly increasing and stay below 12
+    pub fn custom(root: Note, intervals: &[u8]) -> Result<Self, MidiError> {
+        let valid = intervals.first() == Some(&0)
+            && intervals.windows(2).all(|w| w[0] < w[1])
+            && intervals.iter().all(|&i| i < 12);
+        if !valid {
+            return Err(MidiError::InvalidScale);
+        }
+        Ok(Self {
+            root,
+            intervals: intervals.to_vec(),
+        })
+    }
+
+    pub fn root(&self) -> Note {
+        self.root
+    }
+
+    pub fn intervals(&self) -> &[u8] {
+        &self.intervals
+    }
+
+    // number of degrees in one octave
+    pub fn size(&self) -> usize {
+        self.intervals.len()
+    }
+
+    // None when the degree falls outside 0..=127
+    pub fn degree(&self, degree: i32) -> Option<Note> {
+        let value = self.degree_value(degree)?;
+        if (0..=127).contains(&value) {
+            Note::new(value as u8).ok()
+        } else {
+            None
+        }
+    }
+
+    // the MIDI number of a degree, even when it's out of range, None only
+    // when it doesn't fit an i32
+    pub(crate) fn degree_value(&self, degree: i32) -> Option<i32> {
+        let size = self.size() as i32;
+        let octave = degree.div_euclid(size);
+        let step = degree.rem_euclid(size) as usize;
+        octave
+            .checked_mul(12)?
+            .checked_add(self.root.value() as i32 + self.intervals[step] as i32)
+    }
+
+    // Inverse of degree, None if the note is not in the scale
+    pub fn degree_of(&self, note: Note) -> Option<i32> {
+        let distance = note.value() as i32 - self.root.value() as i32;
+        let octave = distance.div_euclid(12);
+        let offset = distance.rem_euclid(12) as u8;
+        self.intervals
+            .iter()
+            .position(|&i| i == offset)
+            .map(|step| octave * self.size() as i32 + step as i32)
+    }
+
+    // membership is by pitch class, the octave doesn't matter
+    pub fn contains(&self, note: Note) -> bool {
+        let offset = (note.value() as i32 - self.root.value() as i32).rem_euclid(12) as u8;
+        self.intervals.contains(&offset)
+    }
+
+    // Nearest scale tone, on a tie the lower one wins
+    pub fn snap(&self, note: Note) -> Note {
+        let value = note.value() as i32;
+        for distance in 0..12 {
+            for candidate in [value - distance, value + distance] {
+                if !(0..=127).contains(&candidate) {
+                    continue;
+                }
+                let n = Note::new(candidate as u8).unwrap();
+                if self.contains(n) {
+                    return n;
+                }
+            }
+        }
+        note
+    }
+
+    // every scale tone between start and end, both included, going up
+    pub fn notes(&self, range: RangeInclusive<Note>) -> impl Iterator<Item = Note> + '_ {
+        let (start, end) = range.into_inner();
+        (start.value()..=end.value())
+            .map(|v| Note::new(v).unwrap())
+            .filter(move |n| self.contains(*n))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn note(name: &str) -> Note {
+        Note::try_from(name).unwrap()
+    }
+
+    #[test]
+    fn degrees() {
+        let c_major = Scale::new(note("C4"), ScaleKind::Major);
+        assert_eq!(c_major.degree(0), Some(note("C4")));
+        assert_eq!(c_major.degree(4), Some(note("G4")));
+        assert_eq!(c_major.degree(7), Some(note("C5")));
+        assert_eq!(c_major.degree(-1), Some(note("B3")));
+        assert_eq!(c_major.degree_of(note("B3")), Some(-1));
+        assert_eq!(c_major.degree_of(note("C#4")), None);
+        assert_eq!(Scale::new(note("G9"), ScaleKind::Major).degree(1), None);
+        assert_eq!(c_major.degree(i32::MAX), None);
+        assert_eq!(c_major.degree(i32::MIN), None);
+    }
+
+    #[test]
+    fn membership_and_snap() {
+        let a_minor = Scale::new(note("A3"), ScaleKind::HarmonicMinor);
+        assert!(a_minor.contains(note("G#7")));
+        assert!(!a_minor.contains(note("G2")));
+
+        let pentatonic = Scale::new(note("C4"), ScaleKind::MajorPentatonic);
+        assert_eq!(pentatonic.snap(note("F4")), note("E4"));
+        assert_eq!(pentatonic.snap(note("F#4")), note("G4"));
+        assert_eq!(pentatonic.snap(note("D4")), note("D4"));
+    }
+
+    #[test]
+    fn range_iteration() {
+        let whole = Scale::new(note("C4"), ScaleKind::WholeTone);
+        let notes: Vec<_> = whole.notes(note("B3")..=note("E4")).collect();
+        assert_eq!(notes, vec![note("C4"), note("D4"), note("E4")]);
+    }
+
+    #[test]
+    fn custom_scales() {
+        let hirajoshi = Scale::custom(note("D4"), &[0, 2, 3, 7, 8]).unwrap();
+        assert_eq!(hirajoshi.degree(3), Some(note("A4")));
+        assert!(Scale::custom(note("C4"), &[2, 4]).is_err());
+        assert!(Scale::custom(note("C4"), &[0, 4, 4]).is_err());
+        assert!(Scale::custom(note("C4"), &[0, 12]).is_err());
+    }
+}
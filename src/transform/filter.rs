@@ -0,0 +1,254 @@
+use crate::channel::Channel;
+use crate::domain::{Control, Note};
+use crate::error::MidiError;
+use crate::message::{ChannelMessage, MessageKind, MidiMessage};
+use crate::smf::Smf;
+use crate::track::{EventType, Track, TrackEvent};
+use std::ops::RangeInclusive;
+
+// One edit applied to every event. Rules only look at channel messages,
+// meta events and sysex go through untouched (except with Map).
+#[derive(Debug, Clone)]
+pub enum Rule {
+    // everything on `from` moves to `to`
+    MapChannel {
+        from: Channel,
+        to: Channel,
+    },
+    DropChannel(Channel),
+    // None drops the kind on every channel
+    DropKind {
+        channel: Option<Channel>,
+        kind: MessageKind,
+    },
+    // notes outside the range go away, NoteOn, NoteOff and key pressure alike
+    KeepNotes(RangeInclusive<Note>),
+    DropControl {
+        channel: Option<Channel>,
+        control: Control,
+    },
+    // anything else, returning None drops the event
+    Map(fn(EventType) -> Option<EventType>),
+}
+
+impl Rule {
+    fn apply(&self, event: EventType) -> Option<EventType> {
+        if let Rule::Map(f) = self {
+            return f(event);
+        }
+        let EventType::Midi(MidiMessage::Channel { channel, message }) = event else {
+            return Some(event);
+        };
+
+        let keep = match self {
+            Rule::MapChannel { from, to } => {
+                let channel = if channel == *from { *to } else { channel };
+                return Some(EventType::Midi(MidiMessage::Channel { channel, message }));
+            }
+            Rule::DropChannel(dropped) => channel != *dropped,
+            Rule::DropKind {
+                channel: only,
+                kind,
+            } => !(message.kind() == *kind && only.is_none_or(|c| c == channel)),
+            Rule::KeepNotes(range) => match message {
+                ChannelMessage::NoteOn { note, .. }
+                | ChannelMessage::NoteOff { note, .. }
+                | ChannelMessage::PolyphonicKeyPressure { note, .. } => range.contains(&note),
+                _ => true,
+            },
+            Rule::DropControl {
+                channel: only,
+                control,
+            } => match message {
+                ChannelMessage::ControlChange { control: c, .. } => {
+                    !(c == *control && only.is_none_or(|o| o == channel))
+                }
+                _ => true,
+            },
+            Rule::Map(_) => unreachable!(),
+        };
+        keep.then_some(EventType::Midi(MidiMessage::Channel { channel, message }))
+    }
+}
+
+// Rules run in the order they were added, each one sees what the
+// previous ones left
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    rules: Vec<Rule>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn map_channel(self, from: Channel, to: Channel) -> Self {
+        self.rule(Rule::MapChannel { from, to })
+    }
+
+    pub fn drop_channel(self, channel: Channel) -> Self {
+        self.rule(Rule::DropChannel(channel))
+    }
+
+    pub fn drop_kind(self, kind: MessageKind) -> Self {
+        self.rule(Rule::DropKind {
+            channel: None,
+            kind,
+        })
+    }
+
+    pub fn keep_notes(self, notes: RangeInclusive<Note>) -> Self {
+        self.rule(Rule::KeepNotes(notes))
+    }
+
+    pub fn drop_control(self, channel: Option<Channel>, control: Control) -> Self {
+        self.rule(Rule::DropControl { channel, control })
+    }
+
+    pub fn map(self, f: fn(EventType) -> Option<EventType>) -> Self {
+        self.rule(Rule::Map(f))
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn apply(&self, event: EventType) -> Option<EventType> {
+        self.rules
+            .iter()
+            .try_fold(event, |event, rule| rule.apply(event))
+    }
+}
+
+impl Track {
+    // Dropped events give their time to the next one, so nothing that's
+    // kept moves
+    pub fn apply_pipeline(&mut self, pipeline: &Pipeline) -> Result<(), MidiError> {
+        let events: Vec<(u64, TrackEvent)> = self
+            .absolute_events()
+            .filter_map(|(tick, e)| {
+                let event = pipeline.apply(e.event.clone())?;
+                Some((tick, TrackEvent::new(e.v_time, event)))
+            })
+            .collect();
+        self.set_absolute_events(events)
+    }
+}
+
+impl Smf {
+    pub fn apply_pipeline(&mut self, pipeline: &Pipeline) -> Result<(), MidiError> {
+        self.try_each_track(|t| t.apply_pipeline(pipeline))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Pressure, Velocity, Vql};
+
+    fn channel(value: u8) -> Channel {
+        Channel::new(value).unwrap()
+    }
+
+    fn message(channel_value: u8, message: ChannelMessage) -> TrackEvent {
+        TrackEvent::new(
+            Vql::try_from(10).unwrap(),
+            EventType::Midi(MidiMessage::Channel {
+                channel: channel(channel_value),
+                message,
+            }),
+        )
+    }
+
+    fn note_on(channel_value: u8, note: u8) -> TrackEvent {
+        message(
+            channel_value,
+            ChannelMessage::NoteOn {
+                note: Note::new(note).unwrap(),
+                velocity: Velocity::new(100).unwrap(),
+            },
+        )
+    }
+
+    fn control(channel_value: u8, control: u8) -> TrackEvent {
+        message(
+            channel_value,
+            ChannelMessage::ControlChange {
+                control: Control::new(control).unwrap(),
+                value: Control::new(64).unwrap(),
+            },
+        )
+    }
+
+    fn summary(track: &Track) -> Vec<(u64, u8, MessageKind)> {
+        track
+            .absolute_events()
+            .filter_map(|(tick, e)| match e.event {
+                EventType::Midi(MidiMessage::Channel { channel, message }) => {
+                    Some((tick, channel.value(), message.kind()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pipeline() {
+        let mut track = Track::new(vec![
+            note_on(3, 36),
+            message(
+                0,
+                ChannelMessage::PolyphonicKeyPressure {
+                    note: Note::new(40).unwrap(),
+                    pressure: Pressure::new(10).unwrap(),
+                },
+            ),
+            control(0, 1),
+            control(1, 1),
+            note_on(0, 90),
+            note_on(0, 48),
+        ]);
+
+        let pipeline = Pipeline::new()
+            .map_channel(channel(3), Channel::DRUMS)
+            .drop_kind(MessageKind::PolyphonicKeyPressure)
+            .keep_notes(Note::new(36).unwrap()..=Note::new(60).unwrap())
+            .drop_control(Some(channel(0)), Control::new(1).unwrap());
+        track.apply_pipeline(&pipeline).unwrap();
+
+        assert_eq!(
+            summary(&track),
+            vec![
+                (10, 9, MessageKind::NoteOn),
+                (40, 1, MessageKind::ControlChange),
+                (60, 0, MessageKind::NoteOn),
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_map_sees_earlier_rules() {
+        fn no_drums(event: EventType) -> Option<EventType> {
+            match event {
+                EventType::Midi(MidiMessage::Channel { channel, .. })
+                    if channel == Channel::DRUMS =>
+                {
+                    None
+                }
+                _ => Some(event),
+            }
+        }
+        let mut track = Track::new(vec![note_on(3, 36), note_on(4, 38)]);
+        let pipeline = Pipeline::new()
+            .map_channel(channel(3), Channel::DRUMS)
+            .map(no_drums);
+        track.apply_pipeline(&pipeline).unwrap();
+        assert_eq!(summary(&track), vec![(20, 4, MessageKind::NoteOn)]);
+    }
+}
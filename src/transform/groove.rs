@@ -0,0 +1,169 @@
+use crate::error::MidiError;
+use crate::smf::Smf;
+use crate::span::{pair_notes, sort_releases_first, NoteSpan};
+use crate::theory::Duration;
+use crate::track::{Track, TrackEvent};
+use crate::transform::humanize::nudge_velocity;
+
+// The feel of one grid step: how far off the grid it's played, as a
+// fraction of the step, and how much louder or softer than average
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GrooveStep {
+    pub timing: f32,
+    pub velocity: f32,
+    // how many notes were averaged, steps with no hits are left untouched
+    pub hits: u32,
+}
+
+// Per step deviations measured on a track, e.g. one bar of 16ths,
+// that can be laid over another track with the same grid
+#[derive(Debug, Clone, PartialEq)]
+pub struct Groove {
+    grid: Duration,
+    steps: Vec<GrooveStep>,
+}
+
+impl Groove {
+    // steps is how many grid steps the template spans before repeating,
+    // 16 for a bar of 16ths in 4/4
+    pub fn extract(
+        track: &Track,
+        ticks_per_beat: u32,
+        grid: Duration,
+        steps: usize,
+    ) -> Result<Self, MidiError> {
+        let step = grid.ticks(ticks_per_beat)? as u64;
+        if step == 0 || steps == 0 {
+            return Err(MidiError::InvalidDuration);
+        }
+
+        let spans: Vec<NoteSpan> = track.note_spans();
+        let mean_velocity = spans.iter().map(|s| s.velocity.value() as f32).sum::<f32>()
+            / spans.len().max(1) as f32;
+
+        let mut template = vec![GrooveStep::default(); steps];
+        for span in &spans {
+            let index = (span.start + step / 2) / step;
+            let slot = &mut template[index as usize % steps];
+            slot.timing += (span.start as f32 - (index * step) as f32) / step as f32;
+            slot.velocity += span.velocity.value() as f32 - mean_velocity;
+            slot.hits += 1;
+        }
+        for slot in template.iter_mut().filter(|s| s.hits > 0) {
+            slot.timing /= slot.hits as f32;
+            slot.velocity /= slot.hits as f32;
+        }
+
+        Ok(Self {
+            grid,
+            steps: template,
+        })
+    }
+
+    pub fn grid(&self) -> Duration {
+        self.grid
+    }
+
+    pub fn steps(&self) -> &[GrooveStep] {
+        &self.steps
+    }
+}
+
+impl Track {
+    // Every NoteOn goes to its grid line plus the groove offset of that step,
+    // amount (0..=100) blends between the original and the grooved playing.
+    // Notes keep their length.
+    pub fn apply_groove(
+        &mut self,
+        groove: &Groove,
+        ticks_per_beat: u32,
+        amount: u8,
+    ) -> Result<(), MidiError> {
+        let step = groove.grid.ticks(ticks_per_beat)? as u64;
+        if step == 0 {
+            return Err(MidiError::InvalidDuration);
+        }
+        let amount = amount.min(100) as f32 / 100.0;
+
+        let mut events: Vec<(u64, TrackEvent)> = self
+            .absolute_events()
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+
+        for (on, off) in pair_notes(events.iter().map(|(_, e)| e)) {
+            let start = events[on].0;
+            let index = (start + step / 2) / step;
+            let slot = groove.steps[index as usize % groove.steps.len()];
+            if slot.hits == 0 {
+                continue;
+            }
+
+            let target = (index * step) as f32 + slot.timing * step as f32;
+            let new_start = (start as f32 + (target - start as f32) * amount)
+                .round()
+                .max(0.0) as u64;
+            events[on].0 = new_start;
+            if let Some(off) = off {
+                events[off].0 = events[off].0 + new_start - start;
+            }
+            nudge_velocity(&mut events[on].1, (slot.velocity * amount).round() as i64)?;
+        }
+
+        sort_releases_first(&mut events);
+        self.set_absolute_events(events)
+    }
+}
+
+impl Smf {
+    pub fn apply_groove(&mut self, groove: &Groove, amount: u8) -> Result<(), MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)?;
+        self.try_each_track(|t| t.apply_groove(groove, ticks_per_beat, amount))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Channel, Note, Velocity, Vql};
+
+    // eighth notes, (offset from the grid, velocity) for each
+    fn loop_of(hits: &[(i64, u8)]) -> Track {
+        let channel = Channel::DRUMS;
+        let hat = Note::new(42).unwrap();
+        let mut events = Vec::new();
+        for (i, &(offset, velocity)) in hits.iter().enumerate() {
+            let start = (i as i64 * 240 + offset) as u64;
+            events.push((
+                start,
+                TrackEvent::note_on(Vql::zero(), channel, hat, Velocity::new(velocity).unwrap()),
+            ));
+            events.push((
+                start + 100,
+                TrackEvent::note_off(Vql::zero(), channel, hat, Velocity::new(0).unwrap()),
+            ));
+        }
+        Track::from_absolute_events(events).unwrap()
+    }
+
+    #[test]
+    fn extract_and_apply() {
+        // a lazy hat: off-beats late and soft
+        let feel = loop_of(&[(0, 110), (30, 70), (0, 110), (30, 70)]);
+        let groove = Groove::extract(&feel, 480, Duration::EIGHTH, 2).unwrap();
+        assert_eq!(groove.steps()[0].hits, 2);
+        assert_eq!(groove.steps()[1].timing, 0.125);
+        assert_eq!(groove.steps()[1].velocity, -20.0);
+
+        let mut robotic = loop_of(&[(0, 127), (0, 127), (0, 127)]);
+        robotic.apply_groove(&groove, 480, 100).unwrap();
+        let starts: Vec<(u64, u8)> = robotic
+            .note_spans()
+            .iter()
+            .map(|s| (s.start, s.velocity.value()))
+            .collect();
+        assert_eq!(starts, vec![(0, 127), (270, 107), (480, 127)]);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::domain::Velocity;
+use crate::error::MidiError;
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::smf::Smf;
+use crate::span::{pair_notes, sort_releases_first};
+use crate::track::{EventType, Track, TrackEvent};
+
+// Random nudges to timing and velocity. The same seed always gives
+// the same result, so a humanized render can be reproduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Humanize {
+    // notes move up to this many ticks earlier or later
+    pub timing: u32,
+    // velocities change by up to this much, up or down
+    pub velocity: u8,
+    pub seed: u64,
+}
+
+impl Humanize {
+    pub fn new(timing: u32, velocity: u8, seed: u64) -> Self {
+        Self {
+            timing,
+            velocity,
+            seed,
+        }
+    }
+}
+
+// SplitMix64, tiny and good enough to wobble some notes
+pub(crate) struct Random(u64);
+
+impl Random {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // uniform in -range..=range
+    pub(crate) fn spread(&mut self, range: u32) -> i64 {
+        if range == 0 {
+            return 0;
+        }
+        let width = 2 * range as u64 + 1;
+        (self.next_u64() % width) as i64 - range as i64
+    }
+}
+
+// the velocity of a NoteOn moved by delta, never below 1 so it stays a NoteOn
+pub(crate) fn nudge_velocity(event: &mut TrackEvent, delta: i64) -> Result<(), MidiError> {
+    if let EventType::Midi(MidiMessage::Channel {
+        message: ChannelMessage::NoteOn { velocity, .. },
+        ..
+    }) = &mut event.event
+    {
+        let value = (velocity.value() as i64 + delta).clamp(1, 127);
+        *velocity = Velocity::new(value as u8)?;
+    }
+    Ok(())
+}
+
+impl Track {
+    // Notes keep their length, only where they start moves
+    pub fn humanize(&mut self, humanize: &Humanize) -> Result<(), MidiError> {
+        let mut random = Random::new(humanize.seed);
+        let mut events: Vec<(u64, TrackEvent)> = self
+            .absolute_events()
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+
+        for (on, off) in pair_notes(events.iter().map(|(_, e)| e)) {
+            let shift = random.spread(humanize.timing);
+            let start = events[on].0;
+            let new_start = (start as i64 + shift).max(0) as u64;
+            events[on].0 = new_start;
+            if let Some(off) = off {
+                events[off].0 = events[off].0 + new_start - start;
+            }
+            nudge_velocity(&mut events[on].1, random.spread(humanize.velocity as u32))?;
+        }
+
+        sort_releases_first(&mut events);
+        self.set_absolute_events(events)
+    }
+}
+
+impl Smf {
+    // every track gets its own sequence, derived from the seed and the track index
+    pub fn humanize(&mut self, humanize: &Humanize) -> Result<(), MidiError> {
+        let mut tracks = self.tracks().to_vec();
+        for (i, track) in tracks.iter_mut().enumerate() {
+            let seed = humanize.seed.wrapping_add(i as u64);
+            track.humanize(&Humanize { seed, ..*humanize })?;
+        }
+        self.tracks_mut().clone_from_slice(&tracks);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Channel, Note, Vql};
+
+    fn hats() -> Track {
+        let channel = Channel::DRUMS;
+        let hat = Note::new(42).unwrap();
+        let mut track = Track::default();
+        for i in 0..16 {
+            let delta = if i == 0 { 0 } else { 60 };
+            track
+                .note_on(
+                    Vql::try_from(delta).unwrap(),
+                    channel,
+                    hat,
+                    Velocity::new(100).unwrap(),
+                )
+                .note_off(
+                    Vql::try_from(60).unwrap(),
+                    channel,
+                    hat,
+                    Velocity::new(0).unwrap(),
+                );
+        }
+        track
+    }
+
+    #[test]
+    fn reproducible_and_bounded() {
+        let humanize = Humanize::new(10, 8, 42);
+        let mut a = hats();
+        let mut b = hats();
+        a.humanize(&humanize).unwrap();
+        b.humanize(&humanize).unwrap();
+        assert_eq!(a.events(), b.events());
+
+        let original = hats().note_spans();
+        let spans = a.note_spans();
+        assert_eq!(spans.len(), original.len());
+        for (before, after) in original.iter().zip(&spans) {
+            assert!(before.start.abs_diff(after.start) <= 10);
+            assert_eq!(before.duration(), after.duration());
+            assert!(before.velocity.value().abs_diff(after.velocity.value()) <= 8);
+        }
+        assert_ne!(original, spans);
+
+        let mut c = hats();
+        c.humanize(&Humanize::new(10, 8, 7)).unwrap();
+        assert_ne!(a.events(), c.events());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::channel::Channel;
+use crate::error::MidiError;
+use crate::header::{Header, MidiFormat};
+use crate::message::MidiMessage;
+use crate::meta::MetaEvent;
+use crate::smf::Smf;
+use crate::track::{EventType, Track, TrackEvent};
+use crate::transform::filter::Pipeline;
+use crate::transform::time::DEFAULT_TEMPO;
+use std::collections::BTreeSet;
+
+// 4/4 with the usual 24 clocks and 8 32nds, what a song without one is in
+const DEFAULT_TIME_SIGNATURE: MetaEvent = MetaEvent::TimeSignature {
+    numerator: 4,
+    denominator: 4,
+    clocks_per_tick: 24,
+    thirty_seconds_per_24_clocks: 8,
+};
+
+fn is_tempo(event: &MetaEvent) -> bool {
+    matches!(event, MetaEvent::SetTempo(_))
+}
+
+fn is_time_signature(event: &MetaEvent) -> bool {
+    matches!(event, MetaEvent::TimeSignature { .. })
+}
+
+// the finest division of them all, so no song loses timing
+fn common_division(songs: &[Smf]) -> Result<u16, MidiError> {
+    let mut division = None;
+    for song in songs {
+        let ticks = song
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)?;
+        division = division.max(Some(ticks as u16));
+    }
+    division.ok_or(MidiError::NothingToMerge)
+}
+
+// meta events of one kind with their tick, over all tracks, in tick order
+fn metas(tracks: &[Track], pick: fn(&MetaEvent) -> bool) -> Vec<(u64, MetaEvent)> {
+    let mut found: Vec<(u64, MetaEvent)> = tracks
+        .iter()
+        .flat_map(|t| t.absolute_events())
+        .filter_map(|(tick, e)| match &e.event {
+            EventType::Meta(meta) if pick(meta) => Some((tick, meta.clone())),
+            _ => None,
+        })
+        .collect();
+    found.sort_by_key(|(tick, _)| *tick);
+    found
+}
+
+// where the next bar starts at or after the end of the song
+fn bar_end(song: &Smf, ticks_per_beat: u64) -> u64 {
+    let end = song
+        .tracks()
+        .iter()
+        .map(|t| t.end_tick())
+        .max()
+        .unwrap_or(0);
+    let (since, bar) = match metas(song.tracks(), is_time_signature).last() {
+        Some((
+            tick,
+            MetaEvent::TimeSignature {
+                numerator,
+                denominator,
+                ..
+            },
+        )) => (
+            *tick,
+            ticks_per_beat * 4 * *numerator as u64 / (*denominator).max(1) as u64,
+        ),
+        _ => (0, ticks_per_beat * 4),
+    };
+    if bar == 0 {
+        return end;
+    }
+    since + (end - since).div_ceil(bar) * bar
+}
+
+fn without_conductor(event: EventType) -> Option<EventType> {
+    match event {
+        EventType::Meta(ref meta)
+            if is_tempo(meta)
+                || is_time_signature(meta)
+                || matches!(meta, MetaEvent::KeySignature { .. }) =>
+        {
+            None
+        }
+        _ => Some(event),
+    }
+}
+
+fn assemble(division: u16, mut tracks: Vec<Track>) -> Result<Smf, MidiError> {
+    for track in tracks.iter_mut() {
+        let mut events: Vec<(u64, TrackEvent)> = track
+            .absolute_events()
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+        events.push((track.end_tick(), TrackEvent::end_track()));
+        track.set_absolute_events(events)?;
+    }
+    let format = if tracks.len() == 1 {
+        MidiFormat::SingleTrack
+    } else {
+        MidiFormat::MultipleTrack
+    };
+    let header = Header::new(format, tracks.len() as u16, division as i16)?;
+    Ok(Smf::new(header, tracks))
+}
+
+impl Smf {
+    // Plays the songs one after the other. Each one starts on the bar after
+    // the previous one ends, all are moved to the finest division, and a
+    // song that doesn't set its own tempo or time signature at its start
+    // gets the defaults back instead of inheriting the previous song's.
+    // Track n of every song goes to track n of the result.
+    pub fn concat(songs: &[Smf]) -> Result<Smf, MidiError> {
+        let division = common_division(songs)?;
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut offset = 0;
+
+        for song in songs {
+            let mut song = song.clone();
+            song.set_division(division)?;
+
+            if offset > 0 {
+                let mut conductor = Vec::new();
+                for (pick, default) in [
+                    (
+                        is_tempo as fn(&MetaEvent) -> bool,
+                        MetaEvent::SetTempo(DEFAULT_TEMPO),
+                    ),
+                    (is_time_signature, DEFAULT_TIME_SIGNATURE),
+                ] {
+                    let starts_with_own = metas(song.tracks(), pick)
+                        .first()
+                        .is_some_and(|(tick, _)| *tick == 0);
+                    let current = metas(&tracks, pick).pop().map(|(_, meta)| meta);
+                    if !starts_with_own && current.is_some_and(|meta| meta != default) {
+                        conductor.push((0, TrackEvent::meta_event(default)));
+                    }
+                }
+                if let Some(first) = song.tracks_mut().first_mut() {
+                    conductor.extend(first.absolute_events().map(|(t, e)| (t, e.clone())));
+                    first.set_absolute_events(conductor)?;
+                }
+            }
+
+            for (index, track) in song.tracks().iter().enumerate() {
+                if index == tracks.len() {
+                    tracks.push(Track::default().with_name(track.name()));
+                }
+                tracks[index].splice(offset, track)?;
+            }
+            offset += bar_end(&song, division as u64);
+        }
+
+        assemble(division, tracks)
+    }
+
+    // Plays the songs at the same time, every track of every song side by
+    // side. A channel already taken by an earlier song moves to a free one,
+    // except drums which all share channel 9. The tempo, time and key
+    // signatures of the first song win, the others' are dropped.
+    pub fn overlay(songs: &[Smf]) -> Result<Smf, MidiError> {
+        let division = common_division(songs)?;
+        let mut taken: BTreeSet<Channel> = BTreeSet::new();
+        let mut tracks: Vec<Track> = Vec::new();
+
+        for (index, song) in songs.iter().enumerate() {
+            let mut song = song.clone();
+            song.set_division(division)?;
+
+            let channels: BTreeSet<Channel> = song
+                .tracks()
+                .iter()
+                .flat_map(|t| t.events())
+                .filter_map(|e| match e.event {
+                    EventType::Midi(MidiMessage::Channel { channel, .. }) => Some(channel),
+                    _ => None,
+                })
+                .collect();
+
+            let mut pipeline = Pipeline::new();
+            if index > 0 {
+                pipeline = pipeline.map(without_conductor);
+            }
+            // a free channel is unused by earlier songs and by this one,
+            // so one move never feeds into the next
+            for &channel in &channels {
+                if channel == Channel::DRUMS || !taken.contains(&channel) {
+                    taken.insert(channel);
+                    continue;
+                }
+                let free = (0..16)
+                    .filter_map(|c| Channel::new(c).ok())
+                    .find(|c| *c != Channel::DRUMS && !taken.contains(c) && !channels.contains(c))
+                    .ok_or(MidiError::NoFreeChannel)?;
+                taken.insert(free);
+                pipeline = pipeline.map_channel(channel, free);
+            }
+
+            song.apply_pipeline(&pipeline)?;
+            tracks.extend(song.tracks().iter().cloned());
+        }
+
+        assemble(division, tracks)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::span::NoteSpan;
+    use crate::test_util::{note, spans, track_of};
+
+    fn song(division: i16, channel: u8, notes: &[(u64, u64)], tempo: Option<u32>) -> Smf {
+        let channel = Channel::new(channel).unwrap();
+        let notes: Vec<NoteSpan> = notes
+            .iter()
+            .map(|&(start, end)| NoteSpan {
+                channel,
+                ..note(start, end, 60)
+            })
+            .collect();
+        let tempo = tempo.map(|tempo| (0, TrackEvent::meta_event(MetaEvent::SetTempo(tempo))));
+        Smf::new(
+            Header::new(MidiFormat::SingleTrack, 1, division).unwrap(),
+            vec![track_of(&notes, tempo.into_iter().collect())],
+        )
+    }
+
+    #[test]
+    fn concat_aligns_bars_and_divisions() {
+        let verse = song(96, 0, &[(0, 96), (96, 200)], Some(400_000));
+        let chorus = song(480, 0, &[(0, 480)], None);
+        let smf = Smf::concat(&[verse, chorus]).unwrap();
+
+        assert_eq!(smf.header().ticks_per_beat(), Some(480));
+        assert_eq!(smf.tracks().len(), 1);
+        // the verse ends at 1000 ticks, inside the first bar of 1920
+        assert_eq!(
+            spans(&smf.note_spans()),
+            vec![(0, 480, 60), (480, 1000, 60), (1920, 2400, 60)]
+        );
+        assert_eq!(
+            metas(smf.tracks(), is_tempo),
+            vec![
+                (0, MetaEvent::SetTempo(400_000)),
+                (1920, MetaEvent::SetTempo(DEFAULT_TEMPO))
+            ]
+        );
+    }
+
+    #[test]
+    fn overlay_moves_colliding_channels() {
+        let bass = song(96, 0, &[(0, 96)], Some(400_000));
+        let keys = song(96, 0, &[(0, 48)], Some(600_000));
+        let drums = song(96, 9, &[(0, 24)], None);
+        let smf = Smf::overlay(&[bass, keys, drums.clone(), drums]).unwrap();
+
+        assert_eq!(smf.tracks().len(), 4);
+        assert_eq!(smf.header().format(), MidiFormat::MultipleTrack);
+        let channels: Vec<u8> = smf
+            .tracks()
+            .iter()
+            .map(|t| t.note_spans()[0].channel.value())
+            .collect();
+        assert_eq!(channels, vec![0, 1, 9, 9]);
+        assert_eq!(
+            metas(smf.tracks(), is_tempo),
+            vec![(0, MetaEvent::SetTempo(400_000))]
+        );
+        assert_eq!(Smf::overlay(&[]).unwrap_err(), MidiError::NothingToMerge);
+    }
+}
@@ -0,0 +1,17 @@
+pub mod filter;
+pub mod groove;
+pub mod humanize;
+pub mod merge;
+pub mod pitch;
+pub mod quantize;
+pub mod retrograde;
+pub mod time;
+pub mod velocity;
+
+pub use filter::*;
+pub use groove::*;
+pub use humanize::*;
+pub use pitch::*;
+pub use quantize::*;
+pub use time::*;
+pub use velocity::*;
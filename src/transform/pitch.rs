@@ -0,0 +1,276 @@
+use crate::channel::Channel;
+use crate::domain::Note;
+use crate::error::MidiError;
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::smf::Smf;
+use crate::theory::Scale;
+use crate::track::{EventType, Track, TrackEvent};
+
+// What to do when a transformed note falls outside 0..=127
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RangePolicy {
+    #[default]
+    Error,
+    Clamp,
+    // the NoteOn, its NoteOff and any key pressure for it disappear
+    Drop,
+    // moved by whole octaves until it fits
+    Fold,
+}
+
+impl RangePolicy {
+    // Ok(None) means drop the event
+    fn resolve(&self, value: i32) -> Result<Option<Note>, MidiError> {
+        let value = match self {
+            _ if (0..=127).contains(&value) => value,
+            RangePolicy::Error => return Err(MidiError::NoteOutOfRange(value)),
+            RangePolicy::Clamp => value.clamp(0, 127),
+            RangePolicy::Drop => return Ok(None),
+            RangePolicy::Fold if value < 0 => value.rem_euclid(12),
+            // highest octave is only C..G (120..=127)
+            RangePolicy::Fold => {
+                let folded = 120 + value.rem_euclid(12);
+                if folded > 127 {
+                    folded - 12
+                } else {
+                    folded
+                }
+            }
+        };
+        Ok(Some(Note::new(value as u8)?))
+    }
+}
+
+// Drums are left alone unless include_drums is set,
+// a kick transposed up a fifth is not a kick anymore
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TransformOptions {
+    pub policy: RangePolicy,
+    pub include_drums: bool,
+}
+
+impl TransformOptions {
+    pub fn new(policy: RangePolicy) -> Self {
+        Self {
+            policy,
+            include_drums: false,
+        }
+    }
+
+    pub fn with_drums(mut self) -> Self {
+        self.include_drums = true;
+        self
+    }
+
+    pub(crate) fn applies_to(&self, channel: Channel) -> bool {
+        self.include_drums || channel != Channel::DRUMS
+    }
+}
+
+impl Track {
+    pub fn transpose(
+        &mut self,
+        semitones: i32,
+        options: TransformOptions,
+    ) -> Result<(), MidiError> {
+        self.map_pitches(options, |note| {
+            (note.value() as i32)
+                .checked_add(semitones)
+                .ok_or(MidiError::NoteOutOfRange(semitones))
+        })
+    }
+
+    // Moves notes by scale steps, notes outside the scale keep their
+    // distance from the nearest scale tone
+    pub fn transpose_diatonic(
+        &mut self,
+        steps: i32,
+        scale: &Scale,
+        options: TransformOptions,
+    ) -> Result<(), MidiError> {
+        self.map_pitches(options, |note| {
+            let anchor = scale.snap(note);
+            let degree = scale.degree_of(anchor).unwrap();
+            let offset = note.value() as i32 - anchor.value() as i32;
+            // so far out no policy can bring it back
+            degree
+                .checked_add(steps)
+                .and_then(|degree| scale.degree_value(degree))
+                .and_then(|value| value.checked_add(offset))
+                .ok_or(MidiError::NoteOutOfRange(steps))
+        })
+    }
+
+    // Melodic inversion, every interval above the axis goes below it
+    pub fn invert(&mut self, axis: Note, options: TransformOptions) -> Result<(), MidiError> {
+        self.map_pitches(options, |note| {
+            Ok(2 * axis.value() as i32 - note.value() as i32)
+        })
+    }
+
+    // Rewrites the note of every NoteOn, NoteOff and PolyphonicKeyPressure.
+    // Nothing is changed if the policy is Error and a note ends up out of range,
+    // or when `f` itself fails.
+    fn map_pitches<F>(&mut self, options: TransformOptions, f: F) -> Result<(), MidiError>
+    where
+        F: Fn(Note) -> Result<i32, MidiError>,
+    {
+        let mut events: Vec<(u64, TrackEvent)> = Vec::with_capacity(self.events().len());
+        for (tick, event) in self.absolute_events() {
+            let mut event = event.clone();
+            if let EventType::Midi(MidiMessage::Channel { channel, message }) = &mut event.event {
+                if options.applies_to(*channel) {
+                    let note = match message {
+                        ChannelMessage::NoteOn { note, .. }
+                        | ChannelMessage::NoteOff { note, .. }
+                        | ChannelMessage::PolyphonicKeyPressure { note, .. } => Some(note),
+                        _ => None,
+                    };
+                    if let Some(note) = note {
+                        match options.policy.resolve(f(*note)?)? {
+                            Some(mapped) => *note = mapped,
+                            None => continue,
+                        }
+                    }
+                }
+            }
+            events.push((tick, event));
+        }
+        self.set_absolute_events(events)
+    }
+}
+
+impl Smf {
+    pub fn transpose(
+        &mut self,
+        semitones: i32,
+        options: TransformOptions,
+    ) -> Result<(), MidiError> {
+        self.try_each_track(|t| t.transpose(semitones, options))
+    }
+
+    pub fn transpose_diatonic(
+        &mut self,
+        steps: i32,
+        scale: &Scale,
+        options: TransformOptions,
+    ) -> Result<(), MidiError> {
+        self.try_each_track(|t| t.transpose_diatonic(steps, scale, options))
+    }
+
+    pub fn invert(&mut self, axis: Note, options: TransformOptions) -> Result<(), MidiError> {
+        self.try_each_track(|t| t.invert(axis, options))
+    }
+
+    // all the tracks are changed or none of them
+    pub(crate) fn try_each_track<F>(&mut self, f: F) -> Result<(), MidiError>
+    where
+        F: Fn(&mut Track) -> Result<(), MidiError>,
+    {
+        let mut tracks = self.tracks().to_vec();
+        for track in tracks.iter_mut() {
+            f(track)?;
+        }
+        self.tracks_mut().clone_from_slice(&tracks);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::theory::ScaleKind;
+    use crate::{Velocity, Vql};
+
+    fn notes_of(track: &Track) -> Vec<u8> {
+        track.note_spans().iter().map(|s| s.note.value()).collect()
+    }
+
+    fn track_with(channel: u8, notes: &[u8]) -> Track {
+        let channel = Channel::new(channel).unwrap();
+        let mut track = Track::default();
+        for &n in notes {
+            let note = Note::new(n).unwrap();
+            track
+                .note_on(Vql::zero(), channel, note, Velocity::new(100).unwrap())
+                .note_off(
+                    Vql::try_from(240).unwrap(),
+                    channel,
+                    note,
+                    Velocity::new(0).unwrap(),
+                );
+        }
+        track.add_event(TrackEvent::end_track());
+        track
+    }
+
+    #[test]
+    fn transpose_policies() {
+        let mut track = track_with(0, &[60, 125]);
+        assert_eq!(
+            track.transpose(5, TransformOptions::default()),
+            Err(MidiError::NoteOutOfRange(130))
+        );
+        assert_eq!(notes_of(&track), vec![60, 125]);
+
+        let mut clamped = track.clone();
+        clamped
+            .transpose(5, TransformOptions::new(RangePolicy::Clamp))
+            .unwrap();
+        assert_eq!(notes_of(&clamped), vec![65, 127]);
+
+        let mut folded = track.clone();
+        folded
+            .transpose(5, TransformOptions::new(RangePolicy::Fold))
+            .unwrap();
+        assert_eq!(notes_of(&folded), vec![65, 118]);
+
+        let mut dropped = track.clone();
+        dropped
+            .transpose(5, TransformOptions::new(RangePolicy::Drop))
+            .unwrap();
+        assert_eq!(notes_of(&dropped), vec![65]);
+        assert_eq!(dropped.end_tick(), track.end_tick());
+    }
+
+    #[test]
+    fn drums_are_skipped_by_default() {
+        let mut track = track_with(9, &[36]);
+        track.transpose(12, TransformOptions::default()).unwrap();
+        assert_eq!(notes_of(&track), vec![36]);
+        track
+            .transpose(12, TransformOptions::default().with_drums())
+            .unwrap();
+        assert_eq!(notes_of(&track), vec![48]);
+    }
+
+    #[test]
+    fn diatonic_and_inversion() {
+        let c_major = Scale::new(Note::new(60).unwrap(), ScaleKind::Major);
+        // C E G B C#
+        let mut track = track_with(0, &[60, 64, 67, 71, 61]);
+        track
+            .transpose_diatonic(1, &c_major, TransformOptions::default())
+            .unwrap();
+        // D F A C D#
+        assert_eq!(notes_of(&track), vec![62, 65, 69, 72, 63]);
+        // past any octave, even clamping is an error
+        for steps in [i32::MAX, i32::MIN] {
+            assert_eq!(
+                track.transpose_diatonic(
+                    steps,
+                    &c_major,
+                    TransformOptions::new(RangePolicy::Clamp)
+                ),
+                Err(MidiError::NoteOutOfRange(steps))
+            );
+        }
+        assert_eq!(notes_of(&track), vec![62, 65, 69, 72, 63]);
+
+        let mut track = track_with(0, &[60, 64, 67]);
+        track
+            .invert(Note::new(60).unwrap(), TransformOptions::default())
+            .unwrap();
+        assert_eq!(notes_of(&track), vec![60, 56, 53]);
+    }
+}
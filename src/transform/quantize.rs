@@ -0,0 +1,181 @@
+use crate::error::MidiError;
+use crate::smf::Smf;
+use crate::span::{pair_notes, sort_releases_first};
+use crate::theory::Duration;
+use crate::track::{Track, TrackEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QuantizeOptions {
+    pub grid: Duration,
+    // how far towards the grid a note moves, 100 snaps it, 50 goes half way
+    pub strength: u8,
+    // where the off-beat falls inside a pair of grid steps, in percent:
+    // 50 is straight, 66 a triplet feel (MPC style)
+    pub swing: u8,
+    // only notes closer than this percentage of half a grid step move,
+    // 100 moves everything
+    pub window: u8,
+    // also snap the NoteOffs, otherwise notes keep their length
+    pub quantize_ends: bool,
+}
+
+impl QuantizeOptions {
+    pub fn new(grid: Duration) -> Self {
+        Self {
+            grid,
+            strength: 100,
+            swing: 50,
+            window: 100,
+            quantize_ends: false,
+        }
+    }
+
+    pub fn with_strength(mut self, strength: u8) -> Self {
+        self.strength = strength.min(100);
+        self
+    }
+
+    pub fn with_swing(mut self, swing: u8) -> Self {
+        self.swing = swing.min(100);
+        self
+    }
+
+    pub fn with_window(mut self, window: u8) -> Self {
+        self.window = window.min(100);
+        self
+    }
+
+    pub fn with_ends(mut self) -> Self {
+        self.quantize_ends = true;
+        self
+    }
+
+    // where tick should go, or the same tick when it's outside the window
+    fn target(&self, tick: u64, step: u64) -> u64 {
+        let pair = 2 * step;
+        let offbeat = pair * self.swing as u64 / 100;
+
+        let base = tick / pair * pair;
+        let lines = [
+            base.checked_sub(pair - offbeat),
+            Some(base),
+            Some(base + offbeat),
+            Some(base + pair),
+        ];
+        let nearest = lines
+            .into_iter()
+            .flatten()
+            .min_by_key(|&line| line.abs_diff(tick))
+            .unwrap();
+
+        let distance = nearest as i64 - tick as i64;
+        let window = (step as i64 * self.window as i64) / 200;
+        if distance.abs() > window {
+            return tick;
+        }
+        let moved = distance * self.strength as i64;
+        // rounded to the nearest tick
+        let moved = (moved + moved.signum() * 50) / 100;
+        (tick as i64 + moved) as u64
+    }
+}
+
+impl Track {
+    // Moves NoteOns (and NoteOffs with quantize_ends) towards the grid,
+    // ticks_per_beat is the Header division. Other events stay where they are.
+    pub fn quantize(
+        &mut self,
+        ticks_per_beat: u32,
+        options: &QuantizeOptions,
+    ) -> Result<(), MidiError> {
+        let step = options.grid.ticks(ticks_per_beat)? as u64;
+        if step == 0 {
+            return Err(MidiError::InvalidDuration);
+        }
+
+        let mut events: Vec<(u64, TrackEvent)> = self
+            .absolute_events()
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+
+        for (on, off) in pair_notes(events.iter().map(|(_, e)| e)) {
+            let start = events[on].0;
+            let new_start = options.target(start, step);
+            events[on].0 = new_start;
+
+            if let Some(off) = off {
+                let end = events[off].0;
+                let new_end = if options.quantize_ends {
+                    options.target(end, step)
+                } else {
+                    (end + new_start).saturating_sub(start)
+                };
+                // a note never collapses to nothing
+                events[off].0 = new_end.max(new_start + 1);
+            }
+        }
+
+        sort_releases_first(&mut events);
+        self.set_absolute_events(events)
+    }
+}
+
+impl Smf {
+    pub fn quantize(&mut self, options: &QuantizeOptions) -> Result<(), MidiError> {
+        let ticks_per_beat = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)?;
+        self.try_each_track(|t| t.quantize(ticks_per_beat, options))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{note, spans, track_of};
+
+    #[test]
+    fn snaps_starts_and_keeps_lengths() {
+        let mut track = track_of(
+            &[note(10, 110, 60), note(230, 430, 60), note(500, 600, 60)],
+            vec![],
+        );
+        track
+            .quantize(480, &QuantizeOptions::new(Duration::EIGHTH))
+            .unwrap();
+        assert_eq!(
+            spans(&track.note_spans()),
+            vec![(0, 100, 60), (240, 440, 60), (480, 580, 60)]
+        );
+    }
+
+    #[test]
+    fn strength_window_and_ends() {
+        let mut track = track_of(&[note(20, 120, 60), note(100, 200, 60)], vec![]);
+        let options = QuantizeOptions::new(Duration::EIGHTH)
+            .with_strength(50)
+            .with_window(50)
+            .with_ends();
+        track.quantize(480, &options).unwrap();
+        // the window is 60 ticks: 20 and 200 move half way, 100 and 120 are too far
+        assert_eq!(
+            spans(&track.note_spans()),
+            vec![(10, 120, 60), (100, 220, 60)]
+        );
+    }
+
+    #[test]
+    fn swing_moves_offbeats() {
+        let mut track = track_of(
+            &[note(0, 60, 60), note(240, 300, 60), note(480, 540, 60)],
+            vec![],
+        );
+        let options = QuantizeOptions::new(Duration::EIGHTH).with_swing(66);
+        track.quantize(480, &options).unwrap();
+        assert_eq!(
+            spans(&track.note_spans()),
+            vec![(0, 60, 60), (316, 376, 60), (480, 540, 60)]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::domain::Velocity;
+use crate::error::MidiError;
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::smf::Smf;
+use crate::span::{note_edge, pair_notes, sort_releases_first};
+use crate::track::{EventType, Track, TrackEvent, Vql};
+use crate::transform::TransformOptions;
+
+impl Track {
+    // Plays the track backwards: a note sounding over [start, end) ends up
+    // over [length - end, length - start), so every NoteOn still comes
+    // before its own NoteOff. Key pressure is mirrored too, controllers,
+    // program changes and meta events stay where they are.
+    pub fn retrograde(&mut self, options: TransformOptions) -> Result<(), MidiError> {
+        let length = self.end_tick();
+        let timed: Vec<(u64, TrackEvent)> = self
+            .absolute_events()
+            .map(|(tick, e)| (tick, e.clone()))
+            .collect();
+        let mut ticks: Vec<u64> = timed.iter().map(|(tick, _)| *tick).collect();
+        let mut extra: Vec<(u64, TrackEvent)> = Vec::new();
+
+        let in_scope = |event: &TrackEvent| match event.event {
+            EventType::Midi(MidiMessage::Channel { channel, .. }) => options.applies_to(channel),
+            _ => false,
+        };
+
+        for (on, off) in pair_notes(timed.iter().map(|(_, e)| e)) {
+            let event = &timed[on].1;
+            if !in_scope(event) {
+                continue;
+            }
+            match off {
+                Some(off) => {
+                    ticks[on] = length - timed[off].0;
+                    ticks[off] = length - timed[on].0;
+                }
+                // a note hanging until the end now starts the track and
+                // gets a NoteOff where it used to start
+                None => {
+                    let (channel, note, _) = note_edge(event).unwrap();
+                    ticks[on] = 0;
+                    extra.push((
+                        length - timed[on].0,
+                        TrackEvent::note_off(Vql::zero(), channel, note, Velocity::new(0)?),
+                    ));
+                }
+            }
+        }
+
+        for (i, (tick, event)) in timed.iter().enumerate() {
+            if let EventType::Midi(MidiMessage::Channel {
+                message: ChannelMessage::PolyphonicKeyPressure { .. },
+                ..
+            }) = event.event
+            {
+                if in_scope(event) {
+                    ticks[i] = length - tick;
+                }
+            }
+        }
+
+        let mut events: Vec<(u64, TrackEvent)> = ticks
+            .into_iter()
+            .zip(timed.into_iter().map(|(_, e)| e))
+            .chain(extra)
+            .collect();
+        sort_releases_first(&mut events);
+        self.set_absolute_events(events)
+    }
+}
+
+impl Smf {
+    // every track is mirrored on its own length
+    pub fn retrograde(&mut self, options: TransformOptions) -> Result<(), MidiError> {
+        self.try_each_track(|t| t.retrograde(options))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Channel, Note};
+
+    #[test]
+    fn reverses_note_spans() {
+        let channel = Channel::new(0).unwrap();
+        let vel = Velocity::new(100).unwrap();
+        let off = Velocity::new(0).unwrap();
+        let c4 = Note::new(60).unwrap();
+        let e4 = Note::new(64).unwrap();
+
+        let mut track = Track::default();
+        track
+            .note_on(Vql::zero(), channel, c4, vel)
+            .note_off(Vql::try_from(100).unwrap(), channel, c4, off)
+            .note_on(Vql::zero(), channel, e4, vel)
+            .note_off(Vql::try_from(300).unwrap(), channel, e4, off);
+        track.add_event(TrackEvent::end_track());
+
+        track.retrograde(TransformOptions::default()).unwrap();
+
+        let spans: Vec<_> = track
+            .note_spans()
+            .iter()
+            .map(|s| (s.note, s.start, s.end))
+            .collect();
+        assert_eq!(spans, vec![(e4, 0, 300), (c4, 300, 400)]);
+        assert_eq!(track.end_tick(), 400);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::error::MidiError;
+use crate::meta::MetaEvent;
+use crate::smf::Smf;
+use crate::span::{pair_notes, sort_releases_first};
+use crate::track::{EventType, TrackEvent};
+
+// 120 BPM, what a player assumes when a song has no SetTempo
+pub const DEFAULT_TEMPO: u32 = 500_000;
+// SetTempo is stored on three bytes
+const MAX_TEMPO: u32 = 0xFF_FFFF;
+
+impl Smf {
+    // Plays the song `factor` times faster by rewriting every SetTempo
+    // (2.0 doubles the BPM), ticks are untouched. A song without tempo
+    // events gets one at the start of the first track.
+    pub fn scale_tempo(&mut self, factor: f64) -> Result<(), MidiError> {
+        if !factor.is_finite() || factor <= 0.0 {
+            return Err(MidiError::InvalidTempo);
+        }
+        let scale = |tempo: u32| -> Result<u32, MidiError> {
+            let scaled = (tempo as f64 / factor).round();
+            if (1.0..=MAX_TEMPO as f64).contains(&scaled) {
+                Ok(scaled as u32)
+            } else {
+                Err(MidiError::InvalidTempo)
+            }
+        };
+
+        let has_tempo = self.tracks().iter().any(|t| {
+            t.events()
+                .iter()
+                .any(|e| matches!(e.event, EventType::Meta(MetaEvent::SetTempo(_))))
+        });
+        if !has_tempo {
+            let tempo = scale(DEFAULT_TEMPO)?;
+            if let Some(first) = self.tracks_mut().first_mut() {
+                let mut events: Vec<(u64, TrackEvent)> = first
+                    .absolute_events()
+                    .map(|(tick, e)| (tick, e.clone()))
+                    .collect();
+                events.insert(0, (0, TrackEvent::meta_event(MetaEvent::SetTempo(tempo))));
+                first.set_absolute_events(events)?;
+            }
+            return Ok(());
+        }
+
+        self.try_each_track(|track| {
+            let mut events: Vec<(u64, TrackEvent)> = Vec::new();
+            for (tick, event) in track.absolute_events() {
+                let mut event = event.clone();
+                if let EventType::Meta(MetaEvent::SetTempo(tempo)) = &mut event.event {
+                    *tempo = scale(*tempo)?;
+                }
+                events.push((tick, event));
+            }
+            track.set_absolute_events(events)
+        })
+    }
+
+    // Makes the song `factor` times longer by moving every event,
+    // the tempo stays the same. See remap_ticks for the rounding.
+    pub fn stretch(&mut self, factor: f64) -> Result<(), MidiError> {
+        if !factor.is_finite() || factor <= 0.0 {
+            return Err(MidiError::InvalidTempo);
+        }
+        self.remap_ticks(|tick| (tick as f64 * factor).round() as u64)
+    }
+
+    // Re-targets the song to another ticks per beat resolution (96 -> 480 PPQ),
+    // the music itself sounds the same
+    pub fn set_division(&mut self, ticks_per_beat: u16) -> Result<(), MidiError> {
+        let old = self
+            .header()
+            .ticks_per_beat()
+            .ok_or(MidiError::UnsupportedDivision)? as u64;
+        if ticks_per_beat == 0 || ticks_per_beat > i16::MAX as u16 {
+            return Err(MidiError::UnsupportedDivision);
+        }
+        let new = ticks_per_beat as u64;
+        self.remap_ticks(|tick| (tick * new + old / 2) / old)?;
+        self.header_mut().set_division(ticks_per_beat as i16);
+        Ok(())
+    }
+
+    // Moves every event through `map`, events landing on the same tick
+    // keep their order. Only a NoteOff that would end up on or before its
+    // NoteOn is nudged to the tick after it, so notes never shrink to zero
+    // length and nothing else drifts.
+    pub(crate) fn remap_ticks<F>(&mut self, map: F) -> Result<(), MidiError>
+    where
+        F: Fn(u64) -> u64,
+    {
+        self.try_each_track(|track| {
+            let mut events: Vec<(u64, TrackEvent)> = track
+                .absolute_events()
+                .map(|(tick, e)| (map(tick), e.clone()))
+                .collect();
+            for (on, off) in pair_notes(events.iter().map(|(_, e)| e)) {
+                let start = events[on].0;
+                if let Some(off) = off.filter(|&off| events[off].0 <= start) {
+                    events[off].0 = start + 1;
+                }
+            }
+            sort_releases_first(&mut events);
+            track.set_absolute_events(events)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::{ChannelMessage, MidiMessage};
+    use crate::test_util::{note, spans, track_of};
+    use crate::track::Vql;
+    use crate::{Channel, Header, MidiFormat};
+
+    fn song(division: i16, notes: &[(u64, u64)]) -> Smf {
+        let notes: Vec<_> = notes
+            .iter()
+            .map(|&(start, end)| note(start, end, 60))
+            .collect();
+        Smf::new(
+            Header::new(MidiFormat::SingleTrack, 1, division).unwrap(),
+            vec![track_of(&notes, vec![])],
+        )
+    }
+
+    #[test]
+    fn tempo_scaling() {
+        let mut smf = song(96, &[(0, 96)]);
+        smf.scale_tempo(2.0).unwrap();
+        assert_eq!(
+            smf.tracks()[0].events()[0].event,
+            EventType::Meta(MetaEvent::SetTempo(250_000))
+        );
+        smf.scale_tempo(0.5).unwrap();
+        assert_eq!(
+            smf.tracks()[0].events()[0].event,
+            EventType::Meta(MetaEvent::SetTempo(500_000))
+        );
+        assert_eq!(spans(&smf.note_spans()), vec![(0, 96, 60)]);
+        assert_eq!(smf.scale_tempo(0.0), Err(MidiError::InvalidTempo));
+    }
+
+    #[test]
+    fn division_change() {
+        let mut smf = song(96, &[(0, 24), (48, 96)]);
+        smf.set_division(480).unwrap();
+        assert_eq!(smf.header().ticks_per_beat(), Some(480));
+        assert_eq!(spans(&smf.note_spans()), vec![(0, 120, 60), (240, 480, 60)]);
+    }
+
+    #[test]
+    fn coarser_division_keeps_timing() {
+        // 1 and 2 ticks at 480 both round to 0 at 24 PPQ, the NoteOffs go
+        // one tick after
+        let mut smf = song(480, &[(0, 1), (2, 3), (480, 960)]);
+        smf.set_division(24).unwrap();
+        assert_eq!(
+            spans(&smf.note_spans()),
+            vec![(0, 1, 60), (0, 1, 60), (24, 48, 60)]
+        );
+
+        let mut smf = song(480, &[(0, 10), (10, 20)]);
+        smf.stretch(0.01).unwrap();
+        assert_eq!(spans(&smf.note_spans()), vec![(0, 1, 60), (0, 1, 60)]);
+
+        // a bar of pitch bends on every tick doesn't push the next bar late
+        let channel = Channel::new(0).unwrap();
+        let bends = (0..1920)
+            .map(|tick| {
+                let message = ChannelMessage::PitchBend { value: 8192 };
+                let event = EventType::Midi(MidiMessage::Channel { channel, message });
+                (tick, TrackEvent::new(Vql::zero(), event))
+            })
+            .collect();
+        let track = track_of(&[note(1920, 2400, 60)], bends);
+        let mut smf = Smf::new(
+            Header::new(MidiFormat::SingleTrack, 1, 480).unwrap(),
+            vec![track],
+        );
+        smf.set_division(24).unwrap();
+        assert_eq!(spans(&smf.note_spans()), vec![(96, 120, 60)]);
+        assert_eq!(smf.tracks()[0].end_tick(), 120);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::channel::Channel;
+use crate::domain::{Note, Velocity};
+use crate::error::MidiError;
+use crate::message::{ChannelMessage, MidiMessage};
+use crate::smf::Smf;
+use crate::track::{EventType, Track, TrackEvent};
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VelocityCurve {
+    // every note at the same velocity
+    Fixed(u8),
+    // velocity * scale + offset
+    Linear { scale: f32, offset: i16 },
+    // 127 * (velocity / 127) ^ gamma, above 1 pushes soft notes softer,
+    // below 1 brings them up
+    Exponential(f32),
+    // straight lines between (input, output) points, sorted by input,
+    // flat before the first point and after the last one
+    Custom(Vec<(u8, u8)>),
+}
+
+impl VelocityCurve {
+    fn apply(&self, velocity: u8) -> f32 {
+        let v = velocity as f32;
+        match self {
+            VelocityCurve::Fixed(value) => *value as f32,
+            VelocityCurve::Linear { scale, offset } => v * scale + *offset as f32,
+            VelocityCurve::Exponential(gamma) => 127.0 * (v / 127.0).powf(*gamma),
+            VelocityCurve::Custom(points) => {
+                let Some(&(first_in, first_out)) = points.first() else {
+                    return v;
+                };
+                if velocity <= first_in {
+                    return first_out as f32;
+                }
+                for pair in points.windows(2) {
+                    let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+                    if velocity <= x1 {
+                        if x1 == x0 {
+                            return y1 as f32;
+                        }
+                        let t = (v - x0 as f32) / (x1 as f32 - x0 as f32);
+                        return y0 as f32 + t * (y1 as f32 - y0 as f32);
+                    }
+                }
+                points.last().unwrap().1 as f32
+            }
+        }
+    }
+}
+
+// Above the threshold every `ratio` steps of velocity become one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compressor {
+    pub threshold: u8,
+    pub ratio: f32,
+}
+
+impl Compressor {
+    pub fn new(threshold: u8, ratio: f32) -> Self {
+        Self { threshold, ratio }
+    }
+
+    fn apply(&self, velocity: u8) -> f32 {
+        let (v, t) = (velocity as f32, self.threshold as f32);
+        if v <= t || self.ratio <= 0.0 {
+            v
+        } else {
+            t + (v - t) / self.ratio
+        }
+    }
+}
+
+// Which NoteOns a velocity transform touches, everything by default
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VelocityScope {
+    pub channel: Option<Channel>,
+    pub notes: Option<RangeInclusive<Note>>,
+}
+
+impl VelocityScope {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn channel(mut self, channel: Channel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn notes(mut self, notes: RangeInclusive<Note>) -> Self {
+        self.notes = Some(notes);
+        self
+    }
+
+    fn contains(&self, channel: Channel, note: Note) -> bool {
+        self.channel.is_none_or(|c| c == channel)
+            && self
+                .notes
+                .as_ref()
+                .is_none_or(|range| range.contains(&note))
+    }
+}
+
+impl Track {
+    pub fn apply_velocity_curve(
+        &mut self,
+        curve: &VelocityCurve,
+        scope: &VelocityScope,
+    ) -> Result<(), MidiError> {
+        self.map_velocities(scope, |_, v| curve.apply(v))
+    }
+
+    pub fn compress_velocity(
+        &mut self,
+        compressor: Compressor,
+        scope: &VelocityScope,
+    ) -> Result<(), MidiError> {
+        self.map_velocities(scope, |_, v| compressor.apply(v))
+    }
+
+    // scales every velocity in scope so the loudest one becomes peak
+    pub fn normalize_velocity(&mut self, peak: u8, scope: &VelocityScope) -> Result<(), MidiError> {
+        match self.peak_velocity(scope) {
+            Some(loudest) => {
+                let gain = peak as f32 / loudest as f32;
+                self.map_velocities(scope, |_, v| v as f32 * gain)
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Crescendo (from < to) or decrescendo (from > to) over [start, end):
+    // velocities are multiplied by a factor going linearly from `from` to `to`
+    pub fn velocity_ramp(
+        &mut self,
+        start: u64,
+        end: u64,
+        from: f32,
+        to: f32,
+        scope: &VelocityScope,
+    ) -> Result<(), MidiError> {
+        self.map_velocities(scope, |tick, v| {
+            if tick < start || tick >= end {
+                return v as f32;
+            }
+            let t = (tick - start) as f32 / (end - start) as f32;
+            v as f32 * (from + (to - from) * t)
+        })
+    }
+
+    fn peak_velocity(&self, scope: &VelocityScope) -> Option<u8> {
+        self.events()
+            .iter()
+            .filter_map(|e| note_on(e, scope))
+            .map(|v| v.value())
+            .max()
+    }
+
+    // Only real NoteOns are touched and they never go below 1,
+    // a velocity 0 NoteOn would turn into a NoteOff. A curve giving NaN or
+    // an infinity lands on 1 as well
+    fn map_velocities<F>(&mut self, scope: &VelocityScope, f: F) -> Result<(), MidiError>
+    where
+        F: Fn(u64, u8) -> f32,
+    {
+        let mut events: Vec<(u64, TrackEvent)> = Vec::with_capacity(self.events().len());
+        for (tick, event) in self.absolute_events() {
+            let mut event = event.clone();
+            if let Some(velocity) = note_on_mut(&mut event, scope) {
+                let value = f(tick, velocity.value()).round();
+                let value = if value.is_finite() {
+                    value.clamp(1.0, 127.0)
+                } else {
+                    1.0
+                };
+                *velocity = Velocity::new(value as u8)?;
+            }
+            events.push((tick, event));
+        }
+        self.set_absolute_events(events)
+    }
+}
+
+fn note_on<'a>(event: &'a TrackEvent, scope: &VelocityScope) -> Option<&'a Velocity> {
+    match &event.event {
+        EventType::Midi(MidiMessage::Channel {
+            channel,
+            message: ChannelMessage::NoteOn { note, velocity },
+        }) if velocity.value() > 0 && scope.contains(*channel, *note) => Some(velocity),
+        _ => None,
+    }
+}
+
+fn note_on_mut<'a>(event: &'a mut TrackEvent, scope: &VelocityScope) -> Option<&'a mut Velocity> {
+    match &mut event.event {
+        EventType::Midi(MidiMessage::Channel {
+            channel,
+            message: ChannelMessage::NoteOn { note, velocity },
+        }) if velocity.value() > 0 && scope.contains(*channel, *note) => Some(velocity),
+        _ => None,
+    }
+}
+
+impl Smf {
+    pub fn apply_velocity_curve(
+        &mut self,
+        curve: &VelocityCurve,
+        scope: &VelocityScope,
+    ) -> Result<(), MidiError> {
+        self.try_each_track(|t| t.apply_velocity_curve(curve, scope))
+    }
+
+    pub fn compress_velocity(
+        &mut self,
+        compressor: Compressor,
+        scope: &VelocityScope,
+    ) -> Result<(), MidiError> {
+        self.try_each_track(|t| t.compress_velocity(compressor, scope))
+    }
+
+    // the peak is taken over the whole song, so tracks keep their balance
+    pub fn normalize_velocity(&mut self, peak: u8, scope: &VelocityScope) -> Result<(), MidiError> {
+        let Some(loudest) = self
+            .tracks()
+            .iter()
+            .filter_map(|t| t.peak_velocity(scope))
+            .max()
+        else {
+            return Ok(());
+        };
+        let gain = peak as f32 / loudest as f32;
+        self.try_each_track(|t| t.map_velocities(scope, |_, v| v as f32 * gain))
+    }
+
+    pub fn velocity_ramp(
+        &mut self,
+        start: u64,
+        end: u64,
+        from: f32,
+        to: f32,
+        scope: &VelocityScope,
+    ) -> Result<(), MidiError> {
+        self.try_each_track(|t| t.velocity_ramp(start, end, from, to, scope))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::span::NoteSpan;
+    use crate::test_util::{note, track_of};
+
+    // (channel, note, velocity), one after the other 100 ticks long
+    fn in_turn(notes: &[(u8, u8, u8)]) -> Track {
+        let notes: Vec<NoteSpan> = (0..)
+            .zip(notes)
+            .map(|(i, &(channel, key, velocity))| NoteSpan {
+                channel: Channel::new(channel).unwrap(),
+                velocity: Velocity::new(velocity).unwrap(),
+                ..note(i * 100, i * 100 + 100, key)
+            })
+            .collect();
+        track_of(&notes, vec![])
+    }
+
+    fn velocities(track: &Track) -> Vec<u8> {
+        track
+            .note_spans()
+            .iter()
+            .map(|s| s.velocity.value())
+            .collect()
+    }
+
+    #[test]
+    fn curves() {
+        let track = in_turn(&[(0, 60, 32), (0, 62, 64), (0, 64, 127)]);
+        let all = VelocityScope::all();
+
+        let mut fixed = track.clone();
+        fixed
+            .apply_velocity_curve(&VelocityCurve::Fixed(90), &all)
+            .unwrap();
+        assert_eq!(velocities(&fixed), vec![90, 90, 90]);
+
+        let mut linear = track.clone();
+        let curve = VelocityCurve::Linear {
+            scale: 0.5,
+            offset: 10,
+        };
+        linear.apply_velocity_curve(&curve, &all).unwrap();
+        assert_eq!(velocities(&linear), vec![26, 42, 74]);
+
+        let mut exponential = track.clone();
+        exponential
+            .apply_velocity_curve(&VelocityCurve::Exponential(2.0), &all)
+            .unwrap();
+        assert_eq!(velocities(&exponential), vec![8, 32, 127]);
+
+        let mut custom = track.clone();
+        let curve = VelocityCurve::Custom(vec![(0, 0), (64, 100), (127, 110)]);
+        custom.apply_velocity_curve(&curve, &all).unwrap();
+        assert_eq!(velocities(&custom), vec![50, 100, 110]);
+
+        // NaN is no velocity, but 1 to the power of NaN is still 1
+        let nan = VelocityCurve::Linear {
+            scale: f32::NAN,
+            offset: 0,
+        };
+        for (curve, expected) in [
+            (nan, vec![1, 1, 1]),
+            (VelocityCurve::Exponential(f32::NAN), vec![1, 1, 127]),
+        ] {
+            let mut broken = track.clone();
+            broken.apply_velocity_curve(&curve, &all).unwrap();
+            assert_eq!(velocities(&broken), expected);
+        }
+    }
+
+    #[test]
+    fn compressor_and_normalize_with_scope() {
+        let mut track = in_turn(&[(9, 36, 127), (9, 42, 127), (0, 60, 127)]);
+        let kick_only = VelocityScope::all()
+            .channel(Channel::DRUMS)
+            .notes(Note::new(35).unwrap()..=Note::new(36).unwrap());
+        track
+            .compress_velocity(Compressor::new(80, 2.0), &kick_only)
+            .unwrap();
+        assert_eq!(velocities(&track), vec![104, 127, 127]);
+
+        let mut track = in_turn(&[(0, 60, 50), (0, 62, 100)]);
+        track
+            .normalize_velocity(120, &VelocityScope::all())
+            .unwrap();
+        assert_eq!(velocities(&track), vec![60, 120]);
+    }
+
+    #[test]
+    fn crescendo() {
+        let mut track = in_turn(&[(0, 60, 100), (0, 62, 100), (0, 64, 100), (0, 65, 100)]);
+        track
+            .velocity_ramp(0, 300, 0.5, 1.0, &VelocityScope::all())
+            .unwrap();
+        // notes start every 100 ticks, the last one is past the ramp
+        assert_eq!(velocities(&track), vec![50, 67, 83, 100]);
+    }
+}